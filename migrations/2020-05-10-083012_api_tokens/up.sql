@@ -0,0 +1,15 @@
+CREATE TYPE token_scope AS ENUM ('full', 'read_only', 'tasks', 'repos');
+
+CREATE TABLE api_tokens (
+  id UUID PRIMARY KEY,
+  uid UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+  name VARCHAR(50) NOT NULL,
+  token_hash TEXT NOT NULL, -- argon hash
+  scope token_scope NOT NULL DEFAULT 'full',
+  expires_at TIMESTAMP,
+  last_used_at TIMESTAMP,
+  is_valid BOOLEAN NOT NULL DEFAULT 't',
+  created_at TIMESTAMP NOT NULL DEFAULT current_timestamp
+);
+
+CREATE INDEX idx_token_uid ON api_tokens (uid);
@@ -0,0 +1,9 @@
+CREATE TABLE password_resets (
+  id UUID NOT NULL PRIMARY KEY,
+  email VARCHAR(100) NOT NULL,
+  used BOOLEAN NOT NULL DEFAULT 'f',
+  expires_at TIMESTAMP NOT NULL,
+  created_at TIMESTAMP NOT NULL
+);
+
+CREATE INDEX idx_reset_email ON password_resets (email);
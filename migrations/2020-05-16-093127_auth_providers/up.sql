@@ -0,0 +1,3 @@
+CREATE TYPE auth_provider AS ENUM ('local', 'ldap', 'oidc');
+
+ALTER TABLE users ADD COLUMN provider auth_provider NOT NULL DEFAULT 'local';
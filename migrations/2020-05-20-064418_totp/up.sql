@@ -0,0 +1,16 @@
+CREATE TABLE totp_credentials (
+  uid UUID PRIMARY KEY REFERENCES users(id) ON DELETE CASCADE,
+  secret VARCHAR(64) NOT NULL, -- base32
+  enabled BOOLEAN NOT NULL DEFAULT 'f',
+  last_step BIGINT NOT NULL DEFAULT 0,
+  created_at TIMESTAMP NOT NULL DEFAULT current_timestamp
+);
+
+CREATE TABLE recovery_codes (
+  id SERIAL PRIMARY KEY,
+  uid UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+  code_hash TEXT NOT NULL, -- argon hash
+  used BOOLEAN NOT NULL DEFAULT 'f'
+);
+
+CREATE INDEX idx_recovery_uid ON recovery_codes (uid);
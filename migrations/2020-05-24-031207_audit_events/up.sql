@@ -0,0 +1,18 @@
+-- No foreign keys, events outlive the users and resources they refer to
+CREATE TABLE audit_events (
+  id BIGSERIAL PRIMARY KEY,
+  actor UUID,
+  impersonator UUID,
+  role cluster_role,
+  method VARCHAR(8) NOT NULL,
+  path TEXT NOT NULL,
+  target TEXT,
+  payload TEXT,
+  status INTEGER NOT NULL,
+  success BOOLEAN NOT NULL,
+  ip VARCHAR(64) NOT NULL,
+  created_at TIMESTAMP NOT NULL DEFAULT current_timestamp
+);
+
+CREATE INDEX idx_audit_created ON audit_events (created_at);
+CREATE INDEX idx_audit_actor ON audit_events (actor);
@@ -0,0 +1,6 @@
+DROP INDEX idx_invitations_department;
+
+ALTER TABLE invitations
+  DROP COLUMN invited_by,
+  DROP COLUMN accepted_at,
+  DROP COLUMN revoked_at;
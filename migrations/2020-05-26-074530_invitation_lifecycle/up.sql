@@ -0,0 +1,6 @@
+ALTER TABLE invitations
+  ADD COLUMN invited_by UUID REFERENCES users(id) ON DELETE SET NULL,
+  ADD COLUMN accepted_at TIMESTAMP,
+  ADD COLUMN revoked_at TIMESTAMP;
+
+CREATE INDEX idx_invitations_department ON invitations (department);
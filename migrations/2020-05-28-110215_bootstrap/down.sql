@@ -0,0 +1,2 @@
+INSERT INTO departments(name)
+SELECT 'test' WHERE NOT EXISTS (SELECT 1 FROM departments WHERE name = 'test');
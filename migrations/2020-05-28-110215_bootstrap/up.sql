@@ -0,0 +1,9 @@
+-- The seeded departments were inserted with explicit ids
+SELECT setval('departments_id_seq', (SELECT MAX(id) FROM departments));
+
+-- Organisation departments are created by `pegasus admin`,
+-- drop the sample one unless it is in use
+DELETE FROM departments
+WHERE name = 'test'
+  AND id NOT IN (SELECT belong_to FROM users WHERE belong_to IS NOT NULL)
+  AND id NOT IN (SELECT department FROM invitations WHERE department IS NOT NULL);
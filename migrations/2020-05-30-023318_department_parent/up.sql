@@ -0,0 +1,4 @@
+ALTER TABLE departments
+  ADD COLUMN parent INTEGER REFERENCES departments(id) ON DELETE SET NULL;
+
+CREATE INDEX idx_departments_parent ON departments (parent);
@@ -0,0 +1,2 @@
+DROP TABLE namespace_quotas;
+DROP TABLE quota_policies;
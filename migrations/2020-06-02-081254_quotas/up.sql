@@ -0,0 +1,26 @@
+-- Quota granted to new namespaces, a policy targets either a role
+-- or a department, department policies are inherited by sub-teams
+CREATE TABLE quota_policies (
+  id SERIAL PRIMARY KEY,
+  role cluster_role UNIQUE,
+  department INTEGER UNIQUE REFERENCES departments(id) ON DELETE CASCADE,
+  cpu VARCHAR(32) NOT NULL,
+  memory VARCHAR(32) NOT NULL,
+  pods INTEGER NOT NULL,
+  services INTEGER NOT NULL,
+  default_cpu VARCHAR(32) NOT NULL,
+  default_memory VARCHAR(32) NOT NULL,
+  CHECK ((role IS NULL) <> (department IS NULL))
+);
+
+-- Amounts applied to the ResourceQuota and LimitRange of a namespace
+CREATE TABLE namespace_quotas (
+  namespace_id INTEGER PRIMARY KEY REFERENCES namespaces(id) ON DELETE CASCADE,
+  cpu VARCHAR(32) NOT NULL,
+  memory VARCHAR(32) NOT NULL,
+  pods INTEGER NOT NULL,
+  services INTEGER NOT NULL,
+  default_cpu VARCHAR(32) NOT NULL,
+  default_memory VARCHAR(32) NOT NULL,
+  updated_at TIMESTAMP NOT NULL DEFAULT current_timestamp
+);
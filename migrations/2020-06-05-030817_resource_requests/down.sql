@@ -0,0 +1,6 @@
+DROP TABLE resource_requests;
+DROP TYPE request_status;
+DROP TYPE request_kind;
+
+ALTER TABLE namespace_quotas DROP COLUMN storage;
+ALTER TABLE quota_policies DROP COLUMN namespaces, DROP COLUMN storage;
@@ -0,0 +1,28 @@
+ALTER TABLE quota_policies
+  ADD COLUMN storage VARCHAR(32) NOT NULL DEFAULT '20Gi',
+  ADD COLUMN namespaces INTEGER NOT NULL DEFAULT 3;
+ALTER TABLE namespace_quotas ADD COLUMN storage VARCHAR(32) NOT NULL DEFAULT '20Gi';
+
+CREATE TYPE request_kind AS ENUM ('cpu', 'memory', 'storage', 'namespaces');
+CREATE TYPE request_status AS ENUM ('pending', 'department_approved', 'approved', 'rejected');
+
+-- `amount` is the new hard limit of `namespace` as a quantity, or the
+-- count of extra namespaces for `namespaces` requests
+CREATE TABLE resource_requests (
+  id SERIAL PRIMARY KEY,
+  uid UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+  namespace VARCHAR,
+  kind request_kind NOT NULL,
+  amount VARCHAR(32) NOT NULL,
+  justification TEXT NOT NULL,
+  status request_status NOT NULL DEFAULT 'pending',
+  depart_reviewer UUID REFERENCES users(id) ON DELETE SET NULL,
+  cluster_reviewer UUID REFERENCES users(id) ON DELETE SET NULL,
+  comment TEXT,
+  created_at TIMESTAMP NOT NULL DEFAULT current_timestamp,
+  updated_at TIMESTAMP NOT NULL DEFAULT current_timestamp,
+  CHECK ((kind = 'namespaces') = (namespace IS NULL))
+);
+
+CREATE INDEX idx_resource_requests_uid ON resource_requests (uid);
+CREATE INDEX idx_resource_requests_status ON resource_requests (status);
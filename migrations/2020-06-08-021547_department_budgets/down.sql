@@ -0,0 +1 @@
+DROP TABLE department_budgets;
@@ -0,0 +1,9 @@
+-- Total quota the namespaces of a department and its sub-teams may be
+-- granted, NULL amounts are unlimited
+CREATE TABLE department_budgets (
+  department INTEGER PRIMARY KEY REFERENCES departments(id) ON DELETE CASCADE,
+  cpu VARCHAR(32),
+  memory VARCHAR(32),
+  storage VARCHAR(32),
+  updated_at TIMESTAMP NOT NULL DEFAULT current_timestamp
+);
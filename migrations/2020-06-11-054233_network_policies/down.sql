@@ -0,0 +1,3 @@
+DROP TABLE network_rules;
+DROP TABLE namespace_networks;
+DROP TYPE network_profile;
@@ -0,0 +1,18 @@
+CREATE TYPE network_profile AS ENUM ('isolated', 'department', 'ingress_controller');
+
+CREATE TABLE namespace_networks (
+  namespace_id INTEGER PRIMARY KEY REFERENCES namespaces(id) ON DELETE CASCADE,
+  profile network_profile NOT NULL,
+  updated_at TIMESTAMP NOT NULL DEFAULT current_timestamp
+);
+
+-- Pods of `from_ns` may connect to the pods of `to_ns`
+CREATE TABLE network_rules (
+  id SERIAL PRIMARY KEY,
+  from_ns INTEGER NOT NULL REFERENCES namespaces(id) ON DELETE CASCADE,
+  to_ns INTEGER NOT NULL REFERENCES namespaces(id) ON DELETE CASCADE,
+  created_by UUID REFERENCES users(id) ON DELETE SET NULL,
+  created_at TIMESTAMP NOT NULL DEFAULT current_timestamp,
+  UNIQUE (from_ns, to_ns),
+  CHECK (from_ns <> to_ns)
+);
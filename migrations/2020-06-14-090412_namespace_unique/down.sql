@@ -0,0 +1,2 @@
+ALTER TABLE namespaces DROP CONSTRAINT namespaces_namespace_key;
+ALTER TABLE namespaces ALTER COLUMN namespace TYPE VARCHAR(30);
@@ -0,0 +1,7 @@
+-- Keep one row per name, the valid or else the latest one
+DELETE FROM namespaces a USING namespaces b
+  WHERE a.namespace = b.namespace AND (a.valid, a.id) < (b.valid, b.id);
+
+-- Kubernetes namespace names are DNS-1123 labels of up to 63 characters
+ALTER TABLE namespaces ALTER COLUMN namespace TYPE VARCHAR(63);
+ALTER TABLE namespaces ADD CONSTRAINT namespaces_namespace_key UNIQUE (namespace);
@@ -0,0 +1,2 @@
+DROP TABLE namespace_members;
+DROP TYPE member_role;
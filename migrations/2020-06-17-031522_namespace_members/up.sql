@@ -0,0 +1,16 @@
+CREATE TYPE member_role AS ENUM ('owner', 'maintainer', 'viewer');
+
+-- Users working in a namespace, the owner is the `uid` of the namespace
+CREATE TABLE namespace_members (
+  namespace_id INTEGER NOT NULL REFERENCES namespaces(id) ON DELETE CASCADE,
+  uid UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+  role member_role NOT NULL,
+  added_by UUID REFERENCES users(id) ON DELETE SET NULL,
+  created_at TIMESTAMP NOT NULL DEFAULT current_timestamp,
+  PRIMARY KEY (namespace_id, uid)
+);
+
+CREATE INDEX idx_namespace_members_uid ON namespace_members (uid);
+
+INSERT INTO namespace_members (namespace_id, uid, role)
+  SELECT id, uid, 'owner' FROM namespaces;
@@ -0,0 +1,49 @@
+//! First-run setup with `pegasus admin <email> <name> [department...]`.
+//!
+//! Creates the initial `ClusterAdmin` and the organisation departments,
+//! the password is read from `PEGASUS_ADMIN_PASSWD` or the first line of
+//! stdin without echo. Refused once a `ClusterAdmin` exists, further users
+//! are invited.
+
+use crate::errors::ApiError;
+use crate::models::user::User;
+
+const USAGE: &str = "Usage: pegasus admin <email> <name> [department...]";
+
+pub fn run(args: &[String]) -> Result<(), ApiError> {
+    let (email, name, departments) = match args {
+        [email, name, departments @ ..] if email.contains('@') => (email, name, departments),
+        _ => return Err(ApiError::new(400, USAGE.to_owned())),
+    };
+
+    let password = match std::env::var("PEGASUS_ADMIN_PASSWD") {
+        Ok(password) => password,
+        Err(_) => rpassword::prompt_password_stdout(&format!("Password of {}: ", email))
+            .map_err(|e| ApiError::new(400, format!("Read password: {}", e)))?,
+    };
+    if password.len() < 8 {
+        return Err(ApiError::new(
+            400,
+            "Password must have at least 8 characters".to_owned(),
+        ));
+    }
+
+    let user = User::bootstrap(email, name, &password, departments)?;
+    println!(
+        "Cluster administrator {} created with departments {:?}",
+        user.email, departments
+    );
+    Ok(())
+}
+
+/// Remind the operator on startup while no `ClusterAdmin` exists
+pub fn check() {
+    match User::exist_admin() {
+        Ok(false) => warn!(
+            "No cluster administrator exists, create one with `{}`",
+            USAGE
+        ),
+        Ok(true) => (),
+        Err(e) => error!("Failed to check cluster administrators: {}", e),
+    }
+}
@@ -1,7 +1,10 @@
+use actix::MailboxError;
 use actix_http::http::StatusCode;
+use actix_redis::Error as RedisError;
 use actix_web::error::Error as ActixError;
 use actix_web::{HttpResponse, ResponseError};
 use diesel::result::Error as DBError;
+use ldap3::LdapError;
 use lettre::smtp::error::Error as SmtpError;
 use lettre_email::error::Error as ClientError;
 use reqwest::Error as ReqError;
@@ -11,6 +14,7 @@ use std::fmt;
 
 use kube::Error as KubeError;
 
+use super::ServiceError;
 use std::convert::From;
 
 #[derive(Debug, Deserialize)]
@@ -94,8 +98,34 @@ impl From<SerdeError> for ApiError {
     }
 }
 
+impl From<LdapError> for ApiError {
+    fn from(error: LdapError) -> Self {
+        ApiError::new(500, format!("LDAP error: {}", error))
+    }
+}
+
+impl From<RedisError> for ApiError {
+    fn from(error: RedisError) -> Self {
+        ApiError::new(500, format!("Redis error: {}", error))
+    }
+}
+
+impl From<MailboxError> for ApiError {
+    fn from(error: MailboxError) -> Self {
+        ApiError::new(500, format!("Redis actor mailbox: {}", error))
+    }
+}
+
 impl From<ReqError> for ApiError {
     fn from(error: ReqError) -> Self {
         ApiError::new(500, format!("Reqwest call error: {}", error))
     }
 }
+
+/// Keep the status code of `ServiceError`, handlers return `ApiError`
+/// while the identity extractors report `ServiceError`
+impl From<ServiceError> for ApiError {
+    fn from(error: ServiceError) -> Self {
+        ApiError::new(error.status_code().as_u16(), error.to_string())
+    }
+}
@@ -26,9 +26,11 @@ pub enum ServiceError {
     #[display(fmt = "Pegasus BadRequest: {}", _0)]
     BadRequest(String),
 
-    #[allow(dead_code)]
     #[display(fmt = "Pegasus Unauthorized")]
     Unauthorized,
+
+    #[display(fmt = "Pegasus Forbidden: {}", _0)]
+    Forbidden(String),
 }
 
 // impl `ResponseError` trait allows `ServiceError` convert to
@@ -45,6 +47,7 @@ impl ResponseError for ServiceError {
             ServiceError::InternalServerError => StatusCode::INTERNAL_SERVER_ERROR,
             ServiceError::BadRequest(_) => StatusCode::BAD_REQUEST,
             ServiceError::Unauthorized => StatusCode::UNAUTHORIZED,
+            ServiceError::Forbidden(_) => StatusCode::FORBIDDEN,
         }
     }
 }
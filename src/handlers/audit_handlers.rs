@@ -0,0 +1,88 @@
+use actix_web::http::header;
+use actix_web::{get, web, HttpResponse, Scope};
+use serde_json::json;
+
+use crate::errors::ApiError;
+use crate::models::audit::{AuditEvent, AuditQuery};
+use crate::mw::AdminIdentity;
+
+#[get("")]
+async fn list_events(
+    info: web::Query<AuditQuery>,
+    admin: AdminIdentity,
+) -> Result<HttpResponse, ApiError> {
+    // Department admins read the events of their departments
+    let departs = admin.0.managed_departments()?;
+    let (total, results) = AuditEvent::search(&info, departs.as_deref())?;
+
+    Ok(HttpResponse::Ok().json(json!({
+        "status": true,
+        "total": total,
+        "page": info.page.unwrap_or(1),
+        "data": results,
+    })))
+}
+
+#[get("/export")]
+async fn export_events(
+    info: web::Query<AuditQuery>,
+    admin: AdminIdentity,
+) -> Result<HttpResponse, ApiError> {
+    let departs = admin.0.managed_departments()?;
+    let results = AuditEvent::export(&info, departs.as_deref())?;
+
+    let mut csv = String::from(
+        "id,created_at,actor,impersonator,role,method,path,target,payload,status,success,ip\n",
+    );
+    for event in results.iter() {
+        let row = [
+            event.id.to_string(),
+            event.created_at.to_string(),
+            event.actor.map(|x| x.to_string()).unwrap_or_default(),
+            event
+                .impersonator
+                .map(|x| x.to_string())
+                .unwrap_or_default(),
+            event.role.map(|x| format!("{:?}", x)).unwrap_or_default(),
+            event.method.clone(),
+            event.path.clone(),
+            event.target.clone().unwrap_or_default(),
+            event.payload.clone().unwrap_or_default(),
+            event.status.to_string(),
+            event.success.to_string(),
+            event.ip.clone(),
+        ];
+        let row: Vec<String> = row.iter().map(|x| csv_field(x)).collect();
+        csv.push_str(&row.join(","));
+        csv.push('\n');
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/csv; charset=utf-8")
+        .header(
+            header::CONTENT_DISPOSITION,
+            "attachment; filename=\"audit.csv\"",
+        )
+        .body(csv))
+}
+
+/// Quote the field if needed, leading formula characters are
+/// escaped for spreadsheet applications
+fn csv_field(value: &str) -> String {
+    let value = if value.starts_with(&['=', '+', '-', '@'][..]) {
+        format!("'{}", value)
+    } else {
+        value.to_owned()
+    };
+    if value.contains(&[',', '"', '\n', '\r'][..]) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value
+    }
+}
+
+pub fn audit_scope() -> Scope {
+    web::scope("/audit")
+        .service(list_events)
+        .service(export_events)
+}
@@ -1,23 +1,52 @@
-use actix_web::{get, post, web, HttpResponse, Scope};
+use actix::Addr;
+use actix_redis::RedisActor;
+use actix_web::{delete, get, post, web, HttpResponse, Scope};
+use futures::stream::{self, StreamExt, TryStreamExt};
+use serde_json::json;
+
+use std::collections::{BTreeMap, BTreeSet};
 
 use crate::errors::ApiError;
-use crate::models::department::Department;
+use crate::models::budget::{BudgetInfo, DepartBudget};
+use crate::models::department::{Department, MemberDashboard};
+use crate::models::member::NamespaceMember;
+use crate::models::namespace::Namespace;
+use crate::models::repository::Repository;
+use crate::models::user::User;
+use crate::mw::{AdminIdentity, ClusterAdminIdentity, Identity};
+use crate::services::{kube_service, reconcile_service, session_service};
+
+/// Namespaces queried against the cluster at the same time
+const DASHBOARD_CONCURRENCY: usize = 16;
 
 #[derive(Deserialize)]
 struct Info {
     pub name: String,
     pub email: Option<String>,
+    pub parent: Option<i32>,
 }
 
 #[post("/create")]
-async fn create_depart(info: web::Json<Info>) -> Result<HttpResponse, ApiError> {
+async fn create_depart(
+    info: web::Json<Info>,
+    _: ClusterAdminIdentity,
+) -> Result<HttpResponse, ApiError> {
     let info = info.into_inner();
-    let res = Department::create(info.name, info.email)?;
+    let mut res = Department::create(info.name, info.email)?;
+    if info.parent.is_some() {
+        res = Department::set_parent(res.id, info.parent)?;
+    }
     Ok(HttpResponse::Ok().json(res))
 }
 
+// The new admin must be in the department, the admins it replaces
+// become lessees like with `/users/role`
 #[post("/admin")]
-async fn update_admin(info: web::Json<Department>) -> Result<HttpResponse, ApiError> {
+async fn update_admin(
+    info: web::Json<Department>,
+    redis: web::Data<Addr<RedisActor>>,
+    admin: ClusterAdminIdentity,
+) -> Result<HttpResponse, ApiError> {
     let info = info.into_inner();
 
     if let None = info.admin {
@@ -26,28 +55,216 @@ async fn update_admin(info: web::Json<Department>) -> Result<HttpResponse, ApiEr
             "Admin field must be speficed".to_string(),
         ));
     }
-    let res = Department::set_admin(info.id, &info.admin.unwrap())?;
+    let uid = info.admin.unwrap();
+    let (res, replaced) = Department::appoint_admin(info.id, &uid)?;
+    for old in replaced.iter().chain(std::iter::once(&uid)) {
+        session_service::revoke_all(&redis, old, None).await?;
+    }
+    info!(
+        target: "audit",
+        "{} appointed {} as admin of department {} replacing {:?}",
+        admin.0.id, uid, info.id, replaced
+    );
+    regrant(Namespace::get_of_department(info.id)?).await?;
     Ok(HttpResponse::Ok().json(res))
 }
 
+/// Grant the access of `namespaces` and of the namespaces whose members
+/// left the department of the owner
+async fn regrant(namespaces: Vec<String>) -> Result<(), ApiError> {
+    let mut affected: BTreeSet<String> = namespaces.into_iter().collect();
+    affected.extend(NamespaceMember::prune()?);
+    reconcile_service::grant_access_all(&affected).await;
+    Ok(())
+}
+
 #[get("/get")]
-async fn get_all() -> Result<HttpResponse, ApiError> {
+async fn get_all(_: Identity) -> Result<HttpResponse, ApiError> {
     let results = Department::list_all()?;
 
     Ok(HttpResponse::Ok().json(results))
 }
 
 #[get("/list")]
-async fn list_info() -> Result<HttpResponse, ApiError> {
+async fn list_info(_: Identity) -> Result<HttpResponse, ApiError> {
     let results = Department::list_infos()?;
 
     Ok(HttpResponse::Ok().json(results))
 }
 
+#[derive(Deserialize)]
+struct RenameInfo {
+    pub id: i32,
+    pub name: String,
+}
+
+#[post("/rename")]
+async fn rename_depart(
+    info: web::Json<RenameInfo>,
+    _: ClusterAdminIdentity,
+) -> Result<HttpResponse, ApiError> {
+    let info = info.into_inner();
+    let res = Department::rename(info.id, &info.name)?;
+    Ok(HttpResponse::Ok().json(res))
+}
+
+#[derive(Deserialize)]
+struct ParentInfo {
+    pub id: i32,
+    pub parent: Option<i32>,
+}
+
+// `parent: null` moves the department to the top level
+#[post("/parent")]
+async fn set_parent(
+    info: web::Json<ParentInfo>,
+    _: ClusterAdminIdentity,
+) -> Result<HttpResponse, ApiError> {
+    let info = info.into_inner();
+    let res = Department::set_parent(info.id, info.parent)?;
+    Ok(HttpResponse::Ok().json(res))
+}
+
+#[get("/tree")]
+async fn get_tree(_: Identity) -> Result<HttpResponse, ApiError> {
+    let results = Department::tree(None)?;
+
+    Ok(HttpResponse::Ok().json(results))
+}
+
+#[get("/tree/{id}")]
+async fn get_subtree(info: web::Path<i32>, _: Identity) -> Result<HttpResponse, ApiError> {
+    let results = Department::tree(Some(info.into_inner()))?;
+
+    Ok(HttpResponse::Ok().json(results))
+}
+
+#[get("/usage/{id}")]
+async fn get_usage(info: web::Path<i32>, admin: AdminIdentity) -> Result<HttpResponse, ApiError> {
+    let id = info.into_inner();
+    admin.0.check_department(id)?;
+    let res = Department::usage(id)?;
+    Ok(HttpResponse::Ok().json(res))
+}
+
+// Members of the department with their namespaces and repositories,
+// the namespaces of all members are fetched concurrently
+#[get("/dashboard/{id}")]
+async fn get_dashboard(
+    info: web::Path<i32>,
+    admin: AdminIdentity,
+) -> Result<HttpResponse, ApiError> {
+    let id = info.into_inner();
+    admin.0.check_department(id)?;
+
+    let mut members = Vec::new();
+    let mut owned = Vec::new();
+    for user in User::find_users_in(id)? {
+        let uid = match user.id {
+            Some(uid) => uid,
+            None => continue,
+        };
+        for ns in Namespace::get_owned_by(&uid)? {
+            owned.push((members.len(), ns));
+        }
+        members.push(MemberDashboard {
+            id: uid,
+            name: user.name,
+            email: user.email,
+            role: user.role,
+            namespaces: BTreeMap::new(),
+            repositories: Repository::get_private_by_uid(&uid)?,
+        });
+    }
+
+    let resources: Vec<_> = stream::iter(&owned)
+        .map(|(_, ns)| kube_service::get_resources_within(ns))
+        .buffered(DASHBOARD_CONCURRENCY)
+        .try_collect()
+        .await?;
+    for ((idx, ns), res) in owned.into_iter().zip(resources) {
+        members[idx].namespaces.insert(ns, res);
+    }
+
+    Ok(HttpResponse::Ok().json(json!({
+        "status": true,
+        "msg": "",
+        "data": members,
+    })))
+}
+
+// Applies to later grants, namespaces already granted are kept
+#[post("/budget")]
+async fn set_budget(
+    info: web::Json<BudgetInfo>,
+    _: ClusterAdminIdentity,
+) -> Result<HttpResponse, ApiError> {
+    let res = DepartBudget::set(&info.into_inner())?;
+    Ok(HttpResponse::Ok().json(res))
+}
+
+#[get("/budget/{id}")]
+async fn get_budget(info: web::Path<i32>, admin: AdminIdentity) -> Result<HttpResponse, ApiError> {
+    let id = info.into_inner();
+    admin.0.check_department(id)?;
+    let res = DepartBudget::report(id)?;
+    Ok(HttpResponse::Ok().json(res))
+}
+
+#[derive(Deserialize)]
+struct DeleteQuery {
+    pub cascade: Option<bool>,
+}
+
+// Referenced departments are only deleted with `?cascade=true`
+#[delete("/{id}")]
+async fn delete_depart(
+    info: web::Path<i32>,
+    query: web::Query<DeleteQuery>,
+    _: ClusterAdminIdentity,
+) -> Result<HttpResponse, ApiError> {
+    let id = info.into_inner();
+    let namespaces = Namespace::get_of_department(id)?;
+    let usage = Department::delete(id, query.cascade.unwrap_or(false))?;
+    regrant(namespaces).await?;
+    Ok(HttpResponse::Ok().json(json!({
+        "status": true,
+        "msg": format!("Department {} deleted, {} users detached", id, usage.users),
+        "data": usage,
+    })))
+}
+
+#[derive(Deserialize)]
+struct MergeInfo {
+    pub from: i32,
+    pub into: i32,
+}
+
+#[post("/merge")]
+async fn merge_depart(
+    info: web::Json<MergeInfo>,
+    _: ClusterAdminIdentity,
+) -> Result<HttpResponse, ApiError> {
+    let info = info.into_inner();
+    let res = Department::merge(info.from, info.into)?;
+    regrant(Namespace::get_of_department(res.id)?).await?;
+    Ok(HttpResponse::Ok().json(res))
+}
+
 pub fn department_scope() -> Scope {
     web::scope("/departs")
         .service(create_depart)
         .service(update_admin)
         .service(get_all)
         .service(list_info)
+        .service(rename_depart)
+        .service(set_parent)
+        .service(get_tree)
+        .service(get_subtree)
+        .service(get_usage)
+        .service(get_dashboard)
+        .service(set_budget)
+        .service(get_budget)
+        .service(delete_depart)
+        .service(merge_depart)
 }
@@ -6,6 +6,7 @@ use crate::errors::ApiError;
 use crate::services::kube_service;
 use crate::models::ingress::IngressInfo;
 use crate::models::kube::DeleteInfo;
+use crate::mw::Identity;
 
 #[derive(Deserialize)]
 struct GetInfo {
@@ -18,14 +19,23 @@ struct NameInfo {
 }
 
 #[get("/all")]
-async fn get_ings_belong_to(info: web::Query<GetInfo>) -> Result<HttpResponse, ApiError> {
+async fn get_ings_belong_to(
+    info: web::Query<GetInfo>,
+    ident: Identity,
+) -> Result<HttpResponse, ApiError> {
+    ident.check_user(&info.uid)?;
     let results = kube_service::get_ing_belong(&info.uid).await?;
     Ok(HttpResponse::Ok().json(results))
 }
 
 #[post("/create")]
-async fn create_ing(info: web::Json<IngressInfo>) -> Result<HttpResponse, ApiError> {
-    let result = kube_service::create_ing(&info.into_inner()).await?;
+async fn create_ing(
+    info: web::Json<IngressInfo>,
+    ident: Identity,
+) -> Result<HttpResponse, ApiError> {
+    let info = info.into_inner();
+    ident.check_namespace(&info.ns)?;
+    let result = kube_service::create_ing(&info).await?;
 
     Ok(HttpResponse::Ok().json(json!({
         "status": true,
@@ -35,16 +45,21 @@ async fn create_ing(info: web::Json<IngressInfo>) -> Result<HttpResponse, ApiErr
 }
 
 #[get("/svcmap")]
-async fn get_svc_map(info: web::Query<GetInfo>) -> Result<HttpResponse, ApiError> {
+async fn get_svc_map(info: web::Query<GetInfo>, ident: Identity) -> Result<HttpResponse, ApiError> {
     let info = info.into_inner();
+    ident.check_user(&info.uid)?;
     let result = kube_service::get_svc_map(&info.uid).await?;
 
     Ok(HttpResponse::Ok().json(result))
 }
 
 #[delete("/item")]
-async fn delete_ing(info: web::Json<DeleteInfo>) -> Result<HttpResponse, ApiError> {
+async fn delete_ing(
+    info: web::Json<DeleteInfo>,
+    ident: Identity,
+) -> Result<HttpResponse, ApiError> {
     let info = info.into_inner();
+    ident.check_namespace(&info.namespace)?;
 
     let msg = kube_service::delete_ing(&info.namespace, &info.name).await?;
     Ok(HttpResponse::Ok().json(json!({
@@ -1,54 +1,254 @@
-//use actix_session::Session;
-use actix_web::{post, web, HttpResponse, Scope};
+use actix_web::{get, post, web, HttpResponse, Scope};
 use serde_json::json;
 use std::str::FromStr;
 use uuid::Uuid;
 
-use crate::errors::ApiError;
-use crate::models::invitation::{Invitation, InvitationData};
+use crate::errors::{ApiError, ServiceError};
+use crate::models::department::Department;
+use crate::models::invitation::{Invitation, InvitationData, InvitationStatus};
 use crate::models::user::User;
+use crate::mw::{AdminIdentity, Identity};
 use crate::services::email_service;
 
+/// Check the limits and send one invitation, the inner error is a
+/// rejection reported with `"status": false`
+fn invite(admin: &Identity, data: &InvitationData) -> Result<Result<Invitation, String>, ApiError> {
+    // Department admins only invite members into their own department
+    if !admin.is_cluster_admin() {
+        match data.department {
+            Some(depart) => admin.check_department(depart)?,
+            None => {
+                return Err(ApiError::new(
+                    403,
+                    "Department must be specified".to_owned(),
+                ))
+            }
+        }
+    }
+    // Check user exist
+    if User::exist(&data.email)? {
+        return Ok(Err("The user with this email exist".to_owned()));
+    }
+
+    // Send limits
+    let cnt = Invitation::count_one_day(&data.email)?;
+    if cnt >= 3 {
+        return Ok(Err(
+            "Most 3 invitations are allow for one email within 24 hours".to_owned(),
+        ));
+    }
+
+    let info = Invitation::create(data, &admin.id)?;
+    email_service::send_email(&info)?;
+    Ok(Ok(info))
+}
+
+/// Check the admin can manage an existing invitation
+fn check_invitation(admin: &Identity, invit: &Invitation) -> Result<(), ApiError> {
+    match invit.department {
+        _ if admin.is_cluster_admin() => Ok(()),
+        Some(depart) => admin.check_department(depart),
+        None => {
+            Err(ServiceError::Forbidden("Not allowed to manage this invitation".to_owned()).into())
+        }
+    }
+}
+
 #[post("/post")]
 async fn post_invitation(
     invit_data: web::Json<InvitationData>,
-    //sess: Session,
+    admin: AdminIdentity,
 ) -> Result<HttpResponse, ApiError> {
-    /*if let None = sess.get::<Option<ClusterRole>>("cluster_role")? {
-            return Err(ApiError::new(401, "Unauthorized".to_string()))
-        } else if let Some(ClusterRole::Lessee) = sess.get("cluster_role")? {
-            return Ok(HttpResponse::Ok().json(json!({
-                "status": false,
-                "msg": "You're not allowed to invitate membors",
-            })))
-    }*/
-
     let data = invit_data.into_inner();
-    // Check user exist
-    if User::exist(&data.email)? {
-        return Ok(HttpResponse::Ok().json(json!({
+
+    match invite(&admin.0, &data)? {
+        Ok(_) => Ok(HttpResponse::Ok().json(json!({
+               "status": true,
+               "msg": format!(
+                "Invitation for {} send successfully",
+                &data.email
+        )}))),
+        Err(msg) => Ok(HttpResponse::Ok().json(json!({
             "status": false,
-            "msg": "The user with this email exist",
-        })));
+            "msg": msg,
+        }))),
     }
+}
 
-    // Send limits
-    let cnt = Invitation::count_one_day(&data.email)?;
-    if cnt >= 3 {
-        return Ok(HttpResponse::Ok().json(json!({
+#[derive(Deserialize)]
+struct ListQuery {
+    pub department: Option<i32>,
+    pub status: Option<InvitationStatus>,
+}
+
+// Department admins list their departments unless one is given
+#[get("/list")]
+async fn list_invitations(
+    info: web::Query<ListQuery>,
+    admin: AdminIdentity,
+) -> Result<HttpResponse, ApiError> {
+    let departments = match info.department {
+        Some(depart) => {
+            admin.0.check_department(depart)?;
+            Some(vec![depart])
+        }
+        None => admin.0.managed_departments()?,
+    };
+
+    let results = Invitation::list(departments.as_deref(), info.status)?;
+    Ok(HttpResponse::Ok().json(results))
+}
+
+#[derive(Deserialize)]
+struct IdInfo {
+    pub id: Uuid,
+}
+
+#[post("/revoke")]
+async fn revoke_invitation(
+    info: web::Json<IdInfo>,
+    admin: AdminIdentity,
+) -> Result<HttpResponse, ApiError> {
+    let invit = Invitation::get_info(&info.id)?;
+    check_invitation(&admin.0, &invit)?;
+    if let InvitationStatus::Accepted | InvitationStatus::Revoked = invit.status() {
+        return Err(ApiError::new(
+            400,
+            "The invitation is accepted or revoked already".to_owned(),
+        ));
+    }
+
+    let invit = Invitation::revoke(&invit.id)?;
+    Ok(HttpResponse::Ok().json(json!({
+        "status": true,
+        "msg": format!("Invitation for {} revoked", invit.email),
+    })))
+}
+
+// Send a fresh invitation with the same data, the old one is revoked
+#[post("/resend")]
+async fn resend_invitation(
+    info: web::Json<IdInfo>,
+    admin: AdminIdentity,
+) -> Result<HttpResponse, ApiError> {
+    let invit = Invitation::get_info(&info.id)?;
+    check_invitation(&admin.0, &invit)?;
+    let status = invit.status();
+    if let InvitationStatus::Accepted | InvitationStatus::Revoked = status {
+        return Err(ApiError::new(
+            400,
+            "The invitation is accepted or revoked already".to_owned(),
+        ));
+    }
+
+    match invite(&admin.0, &InvitationData::from(&invit))? {
+        Ok(resent) => {
+            if status == InvitationStatus::Pending {
+                Invitation::revoke(&invit.id)?;
+            }
+            Ok(HttpResponse::Ok().json(json!({
+                "status": true,
+                "msg": format!("Invitation for {} resent", invit.email),
+                "data": resent,
+            })))
+        }
+        Err(msg) => Ok(HttpResponse::Ok().json(json!({
             "status": false,
-            "msg": "Most 3 invitations are allow for one email within 24 hours"
-        })));
+            "msg": msg,
+        }))),
     }
+}
 
-    let info = Invitation::create(&data)?;
-    email_service::send_email(&info)?;
+/// Most rows of one bulk upload
+const BULK_LIMIT: usize = 500;
+
+/// One csv row `email,department,is_admin`, the department is
+/// an id or a name and may be empty
+fn parse_row(line: &str) -> Result<InvitationData, String> {
+    let fields: Vec<&str> = line
+        .split(',')
+        .map(|x| x.trim().trim_matches('"'))
+        .collect();
+    let email = match fields.first() {
+        Some(email) if email.contains('@') => email.to_string(),
+        _ => return Err("Invalid email".to_owned()),
+    };
+    let department = match fields.get(1) {
+        None | Some(&"") => None,
+        Some(depart) => match depart.parse::<i32>() {
+            Ok(id) => Some(id),
+            Err(_) => match Department::find_by_names(&[depart.to_string()]) {
+                Ok(Some(id)) => Some(id),
+                Ok(None) => return Err(format!("Unknown department {}", depart)),
+                Err(e) => return Err(e.msg),
+            },
+        },
+    };
+    let is_admin = match fields.get(2).map(|x| x.to_lowercase()) {
+        None => false,
+        Some(flag) => match flag.as_str() {
+            "" | "false" | "0" | "no" => false,
+            "true" | "1" | "yes" => true,
+            _ => return Err(format!("Invalid admin flag {}", flag)),
+        },
+    };
+
+    Ok(InvitationData {
+        email,
+        department,
+        is_admin,
+    })
+}
+
+// Invite the rows of a csv body, a header line starting with `email`
+// is skipped. Every row is answered with its own result.
+#[post("/bulk")]
+async fn bulk_invite(body: String, admin: AdminIdentity) -> Result<HttpResponse, ApiError> {
+    let rows: Vec<(usize, &str)> = body
+        .lines()
+        .enumerate()
+        .map(|(i, line)| (i + 1, line.trim()))
+        .filter(|(i, line)| !(line.is_empty() || *i == 1 && line.starts_with("email")))
+        .collect();
+    if rows.len() > BULK_LIMIT {
+        return Err(ApiError::new(
+            400,
+            format!("Most {} rows are allowed in one upload", BULK_LIMIT),
+        ));
+    }
+
+    let mut results = Vec::new();
+    for (row, line) in rows {
+        let result = match parse_row(line) {
+            Ok(data) => match invite(&admin.0, &data) {
+                Ok(Ok(_)) => Ok(data.email),
+                Ok(Err(msg)) => Err((data.email, msg)),
+                Err(e) => Err((data.email, e.msg)),
+            },
+            Err(msg) => Err((line.to_owned(), msg)),
+        };
+        results.push(match result {
+            Ok(email) => json!({
+                "row": row,
+                "email": email,
+                "status": true,
+                "msg": "Invitation send successfully",
+            }),
+            Err((email, msg)) => json!({
+                "row": row,
+                "email": email,
+                "status": false,
+                "msg": msg,
+            }),
+        });
+    }
+
+    let sent = results.iter().filter(|x| x["status"] == true).count();
     Ok(HttpResponse::Ok().json(json!({
-           "status": true,
-           "msg": format!(
-            "Invitation for {} send successfully",
-            &data.email
-    )})))
+        "status": true,
+        "msg": format!("{} of {} invitations send", sent, results.len()),
+        "data": results,
+    })))
 }
 
 #[derive(Deserialize)]
@@ -85,6 +285,10 @@ async fn get_email(info: web::Json<EmailInfo>) -> Result<HttpResponse, ApiError>
 pub fn invitation_scope() -> Scope {
     web::scope("/invitations")
         .service(post_invitation)
+        .service(list_invitations)
+        .service(revoke_invitation)
+        .service(resend_invitation)
+        .service(bulk_invite)
         .service(is_expired)
         .service(get_email)
 }
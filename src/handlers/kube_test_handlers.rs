@@ -2,10 +2,11 @@ use actix_web::{get, post, web, HttpResponse, Scope};
 use serde_json::json;
 
 use crate::errors::ApiError;
+use crate::mw::ClusterAdminIdentity;
 use crate::services::kube_service;
 
 #[get("/nodes")]
-async fn get_nodes_info() -> Result<HttpResponse, ApiError> {
+async fn get_nodes_info(_: ClusterAdminIdentity) -> Result<HttpResponse, ApiError> {
     let res = kube_service::get_nodes().await?;
     Ok(HttpResponse::Ok().json(json!({ "data": res })))
 }
@@ -16,7 +17,10 @@ struct NamespaceInfo {
 }
 
 #[post("/createns")]
-async fn create_ns(info: web::Json<NamespaceInfo>) -> Result<HttpResponse, ApiError> {
+async fn create_ns(
+    info: web::Json<NamespaceInfo>,
+    _: ClusterAdminIdentity,
+) -> Result<HttpResponse, ApiError> {
     let info = &info.into_inner().name;
     kube_service::create_ns(info).await?;
 
@@ -24,7 +28,10 @@ async fn create_ns(info: web::Json<NamespaceInfo>) -> Result<HttpResponse, ApiEr
 }
 
 #[post("/deletens")]
-async fn delete_ns(info: web::Json<NamespaceInfo>) -> Result<HttpResponse, ApiError> {
+async fn delete_ns(
+    info: web::Json<NamespaceInfo>,
+    _: ClusterAdminIdentity,
+) -> Result<HttpResponse, ApiError> {
     let info = &info.into_inner().name;
     let res = kube_service::delete_ns(info).await?;
 
@@ -34,21 +41,30 @@ async fn delete_ns(info: web::Json<NamespaceInfo>) -> Result<HttpResponse, ApiEr
 }
 
 #[get("/deploy")]
-async fn get_deploy(info: web::Query<NamespaceInfo>) -> Result<HttpResponse, ApiError> {
+async fn get_deploy(
+    info: web::Query<NamespaceInfo>,
+    _: ClusterAdminIdentity,
+) -> Result<HttpResponse, ApiError> {
     let info = info.into_inner().name;
     let results = kube_service::get_deploy_within(&info).await?;
     Ok(HttpResponse::Ok().json(results))
 }
 
 #[get("/svc")]
-async fn get_svc(info: web::Query<NamespaceInfo>) -> Result<HttpResponse, ApiError> {
+async fn get_svc(
+    info: web::Query<NamespaceInfo>,
+    _: ClusterAdminIdentity,
+) -> Result<HttpResponse, ApiError> {
     let ns = info.into_inner().name;
     let results = kube_service::get_svc_within(&ns).await?;
     Ok(HttpResponse::Ok().json(results))
 }
 
 #[get("/pod")]
-async fn get_pod(info: web::Query<NamespaceInfo>) -> Result<HttpResponse, ApiError> {
+async fn get_pod(
+    info: web::Query<NamespaceInfo>,
+    _: ClusterAdminIdentity,
+) -> Result<HttpResponse, ApiError> {
     let ns = info.into_inner().name;
     let results = kube_service::get_pod_within(&ns).await?;
     Ok(HttpResponse::Ok().json(results))
@@ -56,8 +72,6 @@ async fn get_pod(info: web::Query<NamespaceInfo>) -> Result<HttpResponse, ApiErr
 
 pub fn kube_test_scope() -> Scope {
     web::scope("/kubetest")
-        // TODO: guard identity
-        // .guard()
         .service(get_nodes_info)
         .service(create_ns)
         .service(delete_ns)
@@ -1,8 +1,11 @@
+pub mod audit_handlers;
 pub mod depart_handlers;
 pub mod invitation_handlers;
 pub mod kube_test_handlers;
+pub mod network_handlers;
 pub mod ns_handlers;
 pub mod repos_handlers;
+pub mod request_handlers;
 pub mod tasks_handlers;
 pub mod user_handlers;
 pub mod ing_handlers;
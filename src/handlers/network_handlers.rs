@@ -0,0 +1,108 @@
+use actix_web::{delete, get, post, web, HttpResponse, Scope};
+use serde_json::json;
+
+use crate::errors::ApiError;
+use crate::models::namespace::Namespace;
+use crate::models::network::{NamespaceNetwork, NetworkProfile, NetworkRule};
+use crate::models::user::User;
+use crate::mw::Identity;
+use crate::services::kube_service;
+
+#[derive(Deserialize)]
+struct NetworkQuery {
+    pub namespace: String,
+}
+
+// Profile of a namespace with the rules from and to it
+#[get("")]
+async fn get_network(
+    info: web::Query<NetworkQuery>,
+    ident: Identity,
+) -> Result<HttpResponse, ApiError> {
+    ident.check_namespace_view(&info.namespace)?;
+    let ns = Namespace::find_valid(&info.namespace)?;
+
+    Ok(HttpResponse::Ok().json(json!({
+        "namespace": ns.namespace,
+        "profile": NamespaceNetwork::profile_of(ns.id)?,
+        "rules": NetworkRule::list_of(ns.id)?,
+    })))
+}
+
+#[derive(Deserialize)]
+struct ProfileInfo {
+    pub namespace: String,
+    pub profile: NetworkProfile,
+}
+
+#[post("/profile")]
+async fn set_profile(
+    info: web::Json<ProfileInfo>,
+    ident: Identity,
+) -> Result<HttpResponse, ApiError> {
+    let info = info.into_inner();
+    ident.check_namespace(&info.namespace)?;
+    let ns = Namespace::find_valid(&info.namespace)?;
+    let owner = User::find(ns.uid)?;
+
+    kube_service::apply_network(&ns.namespace, info.profile, &owner.id, owner.belong_to).await?;
+    let res = NamespaceNetwork::set(ns.id, info.profile)?;
+    Ok(HttpResponse::Ok().json(json!({
+        "status": true,
+        "msg": format!("Namespace {} switched to {:?}", ns.namespace, info.profile),
+        "data": res,
+    })))
+}
+
+#[derive(Deserialize)]
+struct RuleInfo {
+    pub from: String,
+    pub to: String,
+}
+
+// Let the pods of `from` connect to the pods of `to`
+#[post("/rules")]
+async fn create_rule(info: web::Json<RuleInfo>, ident: Identity) -> Result<HttpResponse, ApiError> {
+    let info = info.into_inner();
+    ident.check_namespace(&info.from)?;
+    ident.check_namespace(&info.to)?;
+    let from = Namespace::find_valid(&info.from)?;
+    let to = Namespace::find_valid(&info.to)?;
+
+    let rule = NetworkRule::create(&from, &to, &ident.id)?;
+    if let Err(e) = kube_service::allow_from(&to.namespace, &from.namespace).await {
+        NetworkRule::delete(rule.id)?;
+        return Err(e);
+    }
+    Ok(HttpResponse::Ok().json(json!({
+        "status": true,
+        "msg": format!("{} may connect to {}", from.namespace, to.namespace),
+        "data": rule,
+    })))
+}
+
+// Either side of a rule may remove it
+#[delete("/rules/{id}")]
+async fn delete_rule(info: web::Path<i32>, ident: Identity) -> Result<HttpResponse, ApiError> {
+    let rule = NetworkRule::find(info.into_inner())?;
+    let from = Namespace::find(rule.from_ns)?;
+    let to = Namespace::find(rule.to_ns)?;
+    if !ident.allows_namespace(&to.namespace)? {
+        ident.check_namespace(&from.namespace)?;
+    }
+
+    kube_service::revoke_from(&to.namespace, &from.namespace).await?;
+    NetworkRule::delete(rule.id)?;
+    Ok(HttpResponse::Ok().json(json!({
+        "status": true,
+        "msg": format!("{} may no longer connect to {}", from.namespace, to.namespace),
+    })))
+}
+
+pub fn network_scope() -> Scope {
+    web::scope("/network")
+        .service(get_network)
+        .service(set_profile)
+        .service(create_rule)
+        .service(delete_rule)
+}
@@ -1,3 +1,4 @@
+use actix_web::http::header;
 use actix_web::{delete, get, post, web, HttpResponse, Scope};
 use serde_json::json;
 use uuid::Uuid;
@@ -5,14 +6,68 @@ use uuid::Uuid;
 use std::collections::BTreeMap;
 
 use crate::errors::ApiError;
+use crate::models::budget::DepartBudget;
+use crate::models::member::{MemberRole, NamespaceMember};
 use crate::models::namespace::{Namespace, NamespaceInfo};
+use crate::models::network::{NamespaceNetwork, NetworkProfile};
+use crate::models::quota::{NamespaceQuota, PolicyInfo, QuotaPolicy, QuotaSpec};
+use crate::models::request::ResourceRequest;
+use crate::models::user::User;
+use crate::mw::{ClusterAdminIdentity, Identity};
 use crate::services::kube_service;
+use crate::services::reconcile_service::{self, grant_access, RepairPolicy};
+
+/// Quota, access control and network isolation of a new namespace
+async fn provision(ns: &str, spec: &QuotaSpec, owner: &User) -> Result<(), ApiError> {
+    kube_service::apply_quota(ns, spec).await?;
+    grant_access(ns, owner).await?;
+    kube_service::apply_network(ns, NetworkProfile::configured(), &owner.id, owner.belong_to).await
+}
 
 #[post("/create")]
-async fn create_ns(info: web::Json<NamespaceInfo>) -> Result<HttpResponse, ApiError> {
-    let info = info.into_inner();
+async fn create_ns(
+    info: web::Json<NamespaceInfo>,
+    ident: Identity,
+) -> Result<HttpResponse, ApiError> {
+    let mut info = info.into_inner();
+    ident.check_user(&info.uid)?;
+    let owner = User::find(info.uid)?;
+    info.ns = Namespace::name_for(&owner, &info.ns)?;
+    Namespace::check_available(&info.ns, &owner.id)?;
+    let limit = ResourceRequest::namespace_limit(&owner)?;
+    if Namespace::get_owned_by(&owner.id)?.len() >= limit.max(0) as usize {
+        return Err(ApiError::new(
+            403,
+            format!(
+                "Namespace limit {} reached, request more namespaces first",
+                limit
+            ),
+        ));
+    }
+    let spec = QuotaPolicy::resolve(&owner)?;
+    DepartBudget::check(&owner, None, &spec)?;
+
     kube_service::create_ns(&info.ns).await?;
-    let ns = Namespace::create(info)?;
+    // No namespace is left without a quota or access control
+    if let Err(e) = provision(&info.ns, &spec, &owner).await {
+        kube_service::delete_ns(&info.ns).await?;
+        return Err(e);
+    }
+    // Lost a race for the name or the limit, the cluster namespace is ours to remove
+    let ns = match Namespace::create(&info, Some(limit)) {
+        Ok(ns) => ns,
+        Err(e) => {
+            kube_service::delete_ns(&info.ns).await?;
+            return Err(e);
+        }
+    };
+    // Checked again with the budgets locked against concurrent grants
+    if let Err(e) = DepartBudget::grant(&owner, ns.id, &spec) {
+        kube_service::delete_ns(&ns.namespace).await?;
+        Namespace::delete(&ns.uid, &ns.namespace)?;
+        return Err(e);
+    }
+    NamespaceNetwork::set(ns.id, NetworkProfile::configured())?;
 
     Ok(HttpResponse::Ok().json(ns))
 }
@@ -24,11 +79,25 @@ struct DeleteInfo {
 }
 
 #[delete("/delete")]
-async fn delete_ns(info: web::Json<DeleteInfo>) -> Result<HttpResponse, ApiError> {
+async fn delete_ns(info: web::Json<DeleteInfo>, ident: Identity) -> Result<HttpResponse, ApiError> {
     let info = info.into_inner();
+    ident.check_namespace(&info.namespace)?;
+    if Namespace::owner_of(&info.namespace)? != Some(info.uid) {
+        return Err(ApiError::new(
+            400,
+            format!("Namespace {} does not belong to the user", &info.namespace),
+        ));
+    }
+    // Maintainers operate a shared namespace but never delete it
+    ident.check_user(&info.uid)?;
 
+    kube_service::remove_network_rules(&Namespace::find_valid(&info.namespace)?).await?;
     let res = kube_service::delete_ns(&info.namespace).await?;
     Namespace::delete(&info.uid, &info.namespace)?;
+    // Kubeconfigs handed out before still name the namespace
+    if let Err(e) = kube_service::rotate_token(&info.uid).await {
+        error!("Failed to rotate the token of {}: {}", info.uid, e);
+    }
 
     Ok(HttpResponse::Ok().json(json!({
         "msg": res,
@@ -41,16 +110,24 @@ struct GetInfo {
 }
 
 #[get("belong")]
-async fn get_ns_belong(info: web::Query<GetInfo>) -> Result<HttpResponse, ApiError> {
+async fn get_ns_belong(
+    info: web::Query<GetInfo>,
+    ident: Identity,
+) -> Result<HttpResponse, ApiError> {
     let info = info.into_inner();
+    ident.check_user(&info.id)?;
 
     let res = Namespace::get_ns_of(&info.id)?;
     Ok(HttpResponse::Ok().json(res))
 }
 
 #[get("/labels")]
-async fn get_app_labels(info: web::Query<GetInfo>) -> Result<HttpResponse, ApiError> {
+async fn get_app_labels(
+    info: web::Query<GetInfo>,
+    ident: Identity,
+) -> Result<HttpResponse, ApiError> {
     let info = info.into_inner();
+    ident.check_user(&info.id)?;
     let nss = Namespace::get_ns_of(&info.id)?;
     let mut results: BTreeMap<String, Vec<Option<String>>> = BTreeMap::new();
     for ns in nss.iter() {
@@ -60,10 +137,230 @@ async fn get_app_labels(info: web::Query<GetInfo>) -> Result<HttpResponse, ApiEr
     Ok(HttpResponse::Ok().json(results))
 }
 
+#[derive(Deserialize)]
+struct QuotaQuery {
+    pub namespace: String,
+}
+
+// Granted amounts with the hard limits and usage in the cluster
+#[get("/quota")]
+async fn get_quota(
+    info: web::Query<QuotaQuery>,
+    ident: Identity,
+) -> Result<HttpResponse, ApiError> {
+    ident.check_namespace_view(&info.namespace)?;
+
+    let granted = NamespaceQuota::find_by_ns(&info.namespace)?;
+    let status = kube_service::get_quota_status(&info.namespace).await?;
+    Ok(HttpResponse::Ok().json(json!({
+        "status": true,
+        "msg": "",
+        "data": {
+            "namespace": &info.namespace,
+            "granted": granted,
+            "hard": status.hard,
+            "used": status.used,
+        },
+    })))
+}
+
+#[derive(Deserialize)]
+struct QuotaInfo {
+    pub namespace: String,
+    #[serde(flatten)]
+    pub spec: QuotaSpec,
+}
+
+// Override the quota of one namespace
+#[post("/quota")]
+async fn set_quota(
+    info: web::Json<QuotaInfo>,
+    _: ClusterAdminIdentity,
+) -> Result<HttpResponse, ApiError> {
+    let info = info.into_inner();
+    info.spec.validate()?;
+    let ns = Namespace::find_valid(&info.namespace)?;
+    let (res, previous) = DepartBudget::grant(&User::find(ns.uid)?, ns.id, &info.spec)?;
+
+    if let Err(e) = kube_service::apply_quota(&ns.namespace, &info.spec).await {
+        NamespaceQuota::restore(ns.id, previous.as_ref())?;
+        return Err(e);
+    }
+    Ok(HttpResponse::Ok().json(res))
+}
+
+#[get("/quota/policies")]
+async fn list_policies(_: ClusterAdminIdentity) -> Result<HttpResponse, ApiError> {
+    let results = QuotaPolicy::list()?;
+    Ok(HttpResponse::Ok().json(results))
+}
+
+// Applies to namespaces created afterwards
+#[post("/quota/policies")]
+async fn set_policy(
+    info: web::Json<PolicyInfo>,
+    _: ClusterAdminIdentity,
+) -> Result<HttpResponse, ApiError> {
+    let res = QuotaPolicy::set(&info.into_inner())?;
+    Ok(HttpResponse::Ok().json(res))
+}
+
+#[delete("/quota/policies/{id}")]
+async fn delete_policy(
+    info: web::Path<i32>,
+    _: ClusterAdminIdentity,
+) -> Result<HttpResponse, ApiError> {
+    let res = QuotaPolicy::delete(info.into_inner())?;
+    Ok(HttpResponse::Ok().json(res))
+}
+
+// Kubeconfig of the identity with a context per owned or shared namespace,
+// the account and its bindings come with the namespaces
+#[get("/kubeconfig")]
+async fn get_kubeconfig(ident: Identity) -> Result<HttpResponse, ApiError> {
+    ident.check_not_impersonated()?;
+    let user = ident.user()?;
+    let namespaces = Namespace::get_ns_of(&user.id)?;
+    if namespaces.is_empty() {
+        return Err(ApiError::new(
+            404,
+            "No namespace to access yet, create one first".to_owned(),
+        ));
+    }
+
+    let config = kube_service::render_kubeconfig(&user.id, &namespaces).await?;
+    Ok(HttpResponse::Ok()
+        .content_type("application/yaml; charset=utf-8")
+        .header(
+            header::CONTENT_DISPOSITION,
+            "attachment; filename=\"kubeconfig\"",
+        )
+        .body(config))
+}
+
+// Revoke the kubeconfigs downloaded before
+#[post("/kubeconfig/rotate")]
+async fn rotate_kubeconfig(ident: Identity) -> Result<HttpResponse, ApiError> {
+    ident.check_not_impersonated()?;
+    kube_service::rotate_token(&ident.id).await?;
+    Ok(HttpResponse::Ok().json(json!({
+        "status": true,
+        "msg": "Token rotated, download the kubeconfig again",
+    })))
+}
+
+#[derive(Deserialize)]
+struct MemberQuery {
+    pub namespace: String,
+}
+
+// Members of a namespace, visible to all of them
+#[get("/members")]
+async fn list_members(
+    info: web::Query<MemberQuery>,
+    ident: Identity,
+) -> Result<HttpResponse, ApiError> {
+    ident.check_namespace_view(&info.namespace)?;
+    let ns = Namespace::find_valid(&info.namespace)?;
+    let results = NamespaceMember::list_of(ns.id)?;
+    Ok(HttpResponse::Ok().json(results))
+}
+
+#[derive(Deserialize)]
+struct AddMemberInfo {
+    pub namespace: String,
+    pub email: String,
+    pub role: MemberRole,
+}
+
+// Share a namespace with a user of the same department, done by the
+// owner or the admins above the owner
+#[post("/members")]
+async fn add_member(
+    info: web::Json<AddMemberInfo>,
+    ident: Identity,
+) -> Result<HttpResponse, ApiError> {
+    let info = info.into_inner();
+    let ns = Namespace::find_valid(&info.namespace)?;
+    ident.check_user(&ns.uid)?;
+    let user = User::find_by_email(&info.email)?;
+
+    let res = NamespaceMember::add(&ns, &user, info.role, &ident.id)?;
+    if let Err(e) = grant_access(&ns.namespace, &User::find(ns.uid)?).await {
+        NamespaceMember::remove(ns.id, &user.id)?;
+        return Err(e);
+    }
+    Ok(HttpResponse::Ok().json(json!({
+        "status": true,
+        "msg": format!("{} joined {} as {:?}", user.email, ns.namespace, res.role),
+        "data": res,
+    })))
+}
+
+#[derive(Deserialize)]
+struct RemoveMemberInfo {
+    pub namespace: String,
+    pub uid: Uuid,
+}
+
+// Members may leave by themselves
+#[delete("/members")]
+async fn remove_member(
+    info: web::Json<RemoveMemberInfo>,
+    ident: Identity,
+) -> Result<HttpResponse, ApiError> {
+    let info = info.into_inner();
+    let ns = Namespace::find_valid(&info.namespace)?;
+    if info.uid != ident.id {
+        ident.check_user(&ns.uid)?;
+    }
+
+    NamespaceMember::remove(ns.id, &info.uid)?;
+    grant_access(&ns.namespace, &User::find(ns.uid)?).await?;
+    Ok(HttpResponse::Ok().json(json!({
+        "status": true,
+        "msg": format!("User {} left {}", info.uid, ns.namespace),
+    })))
+}
+
+// Namespaces only in the cluster or only in the database
+#[get("/drift")]
+async fn get_drift(_: ClusterAdminIdentity) -> Result<HttpResponse, ApiError> {
+    let report = reconcile_service::scan().await?;
+    Ok(HttpResponse::Ok().json(report))
+}
+
+// Repair the drift with the given policy, the configured one by default
+#[post("/drift/repair")]
+async fn repair_drift(
+    info: Option<web::Json<RepairPolicy>>,
+    _: ClusterAdminIdentity,
+) -> Result<HttpResponse, ApiError> {
+    let policy = info.map_or_else(RepairPolicy::configured, |x| x.into_inner());
+    let report = reconcile_service::reconcile(policy).await?;
+    Ok(HttpResponse::Ok().json(json!({
+        "status": true,
+        "msg": format!("{} namespaces repaired", report.repairs.len()),
+        "data": report,
+    })))
+}
+
 pub fn ns_scope() -> Scope {
     web::scope("/ns")
         .service(create_ns)
         .service(delete_ns)
         .service(get_ns_belong)
         .service(get_app_labels)
+        .service(get_kubeconfig)
+        .service(rotate_kubeconfig)
+        .service(list_members)
+        .service(add_member)
+        .service(remove_member)
+        .service(get_quota)
+        .service(set_quota)
+        .service(list_policies)
+        .service(set_policy)
+        .service(delete_policy)
+        .service(get_drift)
+        .service(repair_drift)
 }
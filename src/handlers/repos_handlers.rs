@@ -2,15 +2,29 @@ use actix_web::{delete, get, post, web, HttpResponse, Scope};
 use serde_json::json;
 use uuid::Uuid;
 
-use crate::errors::ApiError;
+use crate::errors::{ApiError, ServiceError};
 use crate::models::registry::{RepoBuildRule, RepoCreateInfo, RuleDeleteInfo, RuleStartInfo};
 use crate::models::repository::{DeleteInfo, ImageInfo, PageInfo, RepoRecordState, Repository};
 use crate::models::tag::{Tag, TagRecordState};
+use crate::mw::Identity;
 use crate::services::registry_service;
 
 #[post("/create")]
-async fn create_repo(info: web::Json<RepoCreateInfo>) -> Result<HttpResponse, ApiError> {
+async fn create_repo(
+    info: web::Json<RepoCreateInfo>,
+    ident: Identity,
+) -> Result<HttpResponse, ApiError> {
     let info = info.into_inner();
+    match info.belong_to.as_ref() {
+        Some(uid) => ident.check_user(uid)?,
+        None if ident.is_cluster_admin() => (),
+        None => {
+            return Err(ServiceError::Forbidden(
+                "Only cluster administrators create shared repositories".to_owned(),
+            )
+            .into())
+        }
+    }
     let state = Repository::record_state(&info.name)?;
     if state == RepoRecordState::Active {
         return Ok(HttpResponse::Ok().json(json!({
@@ -38,7 +52,8 @@ async fn create_repo(info: web::Json<RepoCreateInfo>) -> Result<HttpResponse, Ap
 type GetInfo = DeleteInfo;
 
 #[get("/repo")]
-async fn get_repo(info: web::Query<GetInfo>) -> Result<HttpResponse, ApiError> {
+async fn get_repo(info: web::Query<GetInfo>, ident: Identity) -> Result<HttpResponse, ApiError> {
+    ident.check_repository(&info.repo_name, false)?;
     let res = registry_service::get_repo(&info.repo_name).await?;
     Ok(HttpResponse::Ok().json(res))
 }
@@ -50,7 +65,11 @@ struct UserInfo {
 
 // Get one user's private repos
 #[get("/private")]
-async fn get_private_repos(info: web::Query<UserInfo>) -> Result<HttpResponse, ApiError> {
+async fn get_private_repos(
+    info: web::Query<UserInfo>,
+    ident: Identity,
+) -> Result<HttpResponse, ApiError> {
+    ident.check_user(&info.uid)?;
     let names = Repository::get_repos_by_uid(&info.uid)?;
     let mut result = Vec::new();
     for name in names.iter() {
@@ -62,7 +81,7 @@ async fn get_private_repos(info: web::Query<UserInfo>) -> Result<HttpResponse, A
 }
 
 #[get("/public")]
-async fn get_public_repos() -> Result<HttpResponse, ApiError> {
+async fn get_public_repos(_: Identity) -> Result<HttpResponse, ApiError> {
     let res = Repository::get_public()?;
     Ok(HttpResponse::Ok().json(json!({
         "data": res,
@@ -70,7 +89,11 @@ async fn get_public_repos() -> Result<HttpResponse, ApiError> {
 }
 
 #[delete("/repo")]
-async fn delete_repo(info: web::Json<DeleteInfo>) -> Result<HttpResponse, ApiError> {
+async fn delete_repo(
+    info: web::Json<DeleteInfo>,
+    ident: Identity,
+) -> Result<HttpResponse, ApiError> {
+    ident.check_repository(&info.repo_name, true)?;
     registry_service::delete_repo(&info.repo_name).await?;
     //git_service::delete_repo(&info.repo_name).await?;
     Repository::delete(&info.repo_name)?;
@@ -81,7 +104,11 @@ async fn delete_repo(info: web::Json<DeleteInfo>) -> Result<HttpResponse, ApiErr
 }
 
 #[delete("/image")]
-async fn delete_image(info: web::Json<ImageInfo>) -> Result<HttpResponse, ApiError> {
+async fn delete_image(
+    info: web::Json<ImageInfo>,
+    ident: Identity,
+) -> Result<HttpResponse, ApiError> {
+    ident.check_repository(&info.repo_name, true)?;
     registry_service::delete_image(&info.repo_name, &info.tag).await?;
     Ok(HttpResponse::Ok().json(json!({
         "status": true,
@@ -91,7 +118,11 @@ async fn delete_image(info: web::Json<ImageInfo>) -> Result<HttpResponse, ApiErr
 
 // build rules handlers
 #[post("/rule")]
-async fn create_build_rule(info: web::Json<RepoBuildRule>) -> Result<HttpResponse, ApiError> {
+async fn create_build_rule(
+    info: web::Json<RepoBuildRule>,
+    ident: Identity,
+) -> Result<HttpResponse, ApiError> {
+    ident.check_repository(&info.repo_name, true)?;
     let tag_state = Tag::record_state(&info.repo_name, &info.tag)?;
     if tag_state == TagRecordState::Active {
         return Ok(HttpResponse::Ok().json(json!({
@@ -110,14 +141,22 @@ async fn create_build_rule(info: web::Json<RepoBuildRule>) -> Result<HttpRespons
 }
 
 #[get("/rules")]
-async fn get_build_rules(info: web::Query<GetInfo>) -> Result<HttpResponse, ApiError> {
+async fn get_build_rules(
+    info: web::Query<GetInfo>,
+    ident: Identity,
+) -> Result<HttpResponse, ApiError> {
     let info = info.into_inner();
+    ident.check_repository(&info.repo_name, false)?;
     let res = registry_service::get_build_rules(&info.repo_name).await?;
     Ok(HttpResponse::Ok().json(res))
 }
 
 #[post("/startbuild")]
-async fn start_build_rule(info: web::Json<RuleStartInfo>) -> Result<HttpResponse, ApiError> {
+async fn start_build_rule(
+    info: web::Json<RuleStartInfo>,
+    ident: Identity,
+) -> Result<HttpResponse, ApiError> {
+    ident.check_repository(&info.repo_name, true)?;
     registry_service::start_build_rule(&info.repo_name, &info.build_rule_id).await?;
     Ok(HttpResponse::Ok().json(json!({
         "status": true,
@@ -126,7 +165,8 @@ async fn start_build_rule(info: web::Json<RuleStartInfo>) -> Result<HttpResponse
 }
 
 #[get("/tags")]
-async fn get_tags(info: web::Query<PageInfo>) -> Result<HttpResponse, ApiError> {
+async fn get_tags(info: web::Query<PageInfo>, ident: Identity) -> Result<HttpResponse, ApiError> {
+    ident.check_repository(&info.name, false)?;
     let response =
         registry_service::get_repo_tags(&info.name, format!("{}", info.page).as_str()).await?;
     Ok(HttpResponse::Ok().json(response))
@@ -134,8 +174,12 @@ async fn get_tags(info: web::Query<PageInfo>) -> Result<HttpResponse, ApiError>
 
 // Delete build rule and set tags file invalid
 #[delete("/buildrule")]
-async fn delete_build_rule(info: web::Json<RuleDeleteInfo>) -> Result<HttpResponse, ApiError> {
+async fn delete_build_rule(
+    info: web::Json<RuleDeleteInfo>,
+    ident: Identity,
+) -> Result<HttpResponse, ApiError> {
     let info = info.into_inner();
+    ident.check_repository(&info.repo_name, true)?;
     registry_service::delete_build_rule(&info.repo_name, &info.tag, &info.build_rule_id).await?;
     Ok(HttpResponse::Ok().json(json!({
         "status": true,
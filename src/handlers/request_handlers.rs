@@ -0,0 +1,204 @@
+use actix_web::{get, post, web, HttpResponse, Scope};
+use serde_json::json;
+
+use crate::errors::{ApiError, ServiceError};
+use crate::models::budget::DepartBudget;
+use crate::models::namespace::Namespace;
+use crate::models::quota::NamespaceQuota;
+use crate::models::request::{RequestInfo, RequestQuery, RequestStatus, ResourceRequest};
+use crate::models::user::{ClusterRole, User};
+use crate::mw::{AdminIdentity, Identity};
+use crate::services::{email_service, kube_service};
+
+/// Mail the notice to every address, failures are only logged
+/// so a broken smtp server never blocks a review
+fn notify(emails: &[String], req: &ResourceRequest, event: &str) {
+    for email in emails {
+        if let Err(e) = email_service::send_request_notice(email, req, event) {
+            error!("Failed to notify {} of request {}: {}", email, req.id, e);
+        }
+    }
+}
+
+#[post("")]
+async fn create_request(
+    info: web::Json<RequestInfo>,
+    ident: Identity,
+) -> Result<HttpResponse, ApiError> {
+    let info = info.into_inner();
+    if let Some(ns) = info.namespace.as_ref() {
+        ident.check_namespace(ns)?;
+    }
+
+    let user = ident.user()?;
+    let res = ResourceRequest::create(&user.id, &info)?;
+    notify(
+        &User::reviewer_emails(user.belong_to)?,
+        &res,
+        &format!("was submitted by {} and waits for your review", user.email),
+    );
+    Ok(HttpResponse::Ok().json(json!({
+        "status": true,
+        "msg": "Request submitted",
+        "data": res,
+    })))
+}
+
+// Lessees list their own requests, department admins the requests
+// of their departments
+#[get("")]
+async fn list_requests(
+    info: web::Query<RequestQuery>,
+    ident: Identity,
+) -> Result<HttpResponse, ApiError> {
+    let mut query = info.into_inner();
+    let results = match ident.role {
+        ClusterRole::ClusterAdmin => ResourceRequest::list(&query, None)?,
+        ClusterRole::DepartmentAdmin => {
+            let departs = ident.managed_departments()?;
+            ResourceRequest::list(&query, departs.as_deref())?
+        }
+        ClusterRole::Lessee => {
+            query.uid = Some(ident.id);
+            ResourceRequest::list(&query, None)?
+        }
+    };
+    Ok(HttpResponse::Ok().json(results))
+}
+
+#[get("/{id}")]
+async fn get_request(info: web::Path<i32>, ident: Identity) -> Result<HttpResponse, ApiError> {
+    let res = ResourceRequest::find(info.into_inner())?;
+    ident.check_user(&res.uid)?;
+    Ok(HttpResponse::Ok().json(res))
+}
+
+#[derive(Deserialize)]
+struct ReviewInfo {
+    pub comment: Option<String>,
+}
+
+/// Raise the quota of the namespace of an approved request
+async fn apply_request(req: &ResourceRequest) -> Result<(), ApiError> {
+    if let Some((ns, spec)) = req.granted_spec()? {
+        let (_, previous) = DepartBudget::grant(&User::find(req.uid)?, ns.id, &spec)?;
+        if let Err(e) = kube_service::apply_quota(&ns.namespace, &spec).await {
+            NamespaceQuota::restore(ns.id, previous.as_ref())?;
+            return Err(e);
+        }
+    }
+    Ok(())
+}
+
+/// Move the request one step on and apply the quota once approved
+async fn review(
+    id: i32,
+    approve: bool,
+    comment: Option<String>,
+    admin: &Identity,
+) -> Result<ResourceRequest, ApiError> {
+    let req = ResourceRequest::find(id)?;
+    let by_cluster_admin = admin.is_cluster_admin();
+    if req.uid == admin.id && !by_cluster_admin {
+        return Err(
+            ServiceError::Forbidden("Not allowed to review own requests".to_owned()).into(),
+        );
+    }
+    admin.check_user(&req.uid)?;
+
+    let status = req.next_status(approve, by_cluster_admin)?;
+    // Claimed first so concurrent reviews never both apply the quota
+    let res = req.transition(status, &admin.id, by_cluster_admin, comment)?;
+    if res.status == RequestStatus::Approved {
+        if let Err(e) = apply_request(&res).await {
+            req.restore(&res)?;
+            return Err(e);
+        }
+    }
+
+    let requester = User::find(res.uid)?;
+    match res.status {
+        RequestStatus::DepartmentApproved => {
+            notify(
+                &[requester.email],
+                &res,
+                "was approved by the department and waits for a cluster administrator",
+            );
+            notify(
+                &User::reviewer_emails(None)?,
+                &res,
+                "was approved by the department and waits for your review",
+            );
+        }
+        RequestStatus::Approved => notify(&[requester.email], &res, "was approved"),
+        RequestStatus::Rejected => notify(&[requester.email], &res, "was rejected"),
+        RequestStatus::Pending => (),
+    }
+    Ok(res)
+}
+
+#[post("/{id}/approve")]
+async fn approve_request(
+    info: web::Path<i32>,
+    body: Option<web::Json<ReviewInfo>>,
+    admin: AdminIdentity,
+) -> Result<HttpResponse, ApiError> {
+    let comment = body.and_then(|x| x.into_inner().comment);
+    let res = review(info.into_inner(), true, comment, &admin.0).await?;
+    Ok(HttpResponse::Ok().json(json!({
+        "status": true,
+        "msg": format!("Request {:?}", res.status),
+        "data": res,
+    })))
+}
+
+#[post("/{id}/reject")]
+async fn reject_request(
+    info: web::Path<i32>,
+    body: Option<web::Json<ReviewInfo>>,
+    admin: AdminIdentity,
+) -> Result<HttpResponse, ApiError> {
+    let comment = body.and_then(|x| x.into_inner().comment);
+    let res = review(info.into_inner(), false, comment, &admin.0).await?;
+    Ok(HttpResponse::Ok().json(json!({
+        "status": true,
+        "msg": "Request rejected",
+        "data": res,
+    })))
+}
+
+#[derive(Deserialize)]
+struct LimitQuery {
+    pub namespace: Option<String>,
+}
+
+// Namespace count allowance, with the granted quota of one namespace
+#[get("/limits")]
+async fn get_limits(
+    info: web::Query<LimitQuery>,
+    ident: Identity,
+) -> Result<HttpResponse, ApiError> {
+    let user = ident.user()?;
+    let granted = match info.namespace.as_ref() {
+        Some(ns) => {
+            ident.check_namespace_view(ns)?;
+            NamespaceQuota::find_by_ns(ns)?
+        }
+        None => None,
+    };
+    Ok(HttpResponse::Ok().json(json!({
+        "namespaces": Namespace::get_owned_by(&user.id)?.len(),
+        "namespace_limit": ResourceRequest::namespace_limit(&user)?,
+        "granted": granted,
+    })))
+}
+
+pub fn request_scope() -> Scope {
+    web::scope("/requests")
+        .service(create_request)
+        .service(list_requests)
+        .service(get_limits)
+        .service(get_request)
+        .service(approve_request)
+        .service(reject_request)
+}
@@ -11,6 +11,7 @@ use crate::models::kube::{DeleteInfo, DeployInfo, GetInfo,
                           ContainerInfo, ServiceInfo};
 use crate::models::namespace::Namespace;
 use crate::models::user::User;
+use crate::mw::Identity;
 use crate::services::kube_service;
 
 use std::collections::BTreeMap;
@@ -21,8 +22,9 @@ struct UserInfo {
 }
 
 #[get("/infos")]
-async fn get_info(info: web::Query<UserInfo>) -> Result<HttpResponse, ApiError> {
+async fn get_info(info: web::Query<UserInfo>, ident: Identity) -> Result<HttpResponse, ApiError> {
     let uid = info.into_inner().id;
+    ident.check_user(&uid)?;
 
     if !User::exist_id(&uid)? {
         return Ok(HttpResponse::Ok().json(json!({
@@ -58,14 +60,22 @@ async fn get_info(info: web::Query<UserInfo>) -> Result<HttpResponse, ApiError>
 }
 
 #[get("/deploy")]
-async fn get_deploy(info: web::Query<GetInfo>) -> Result<HttpResponse, ApiError> {
+async fn get_deploy(
+    info: web::Query<GetInfo>,
+    ident: Identity,
+) -> Result<HttpResponse, ApiError> {
+    ident.check_namespace_view(&info.namespace)?;
     let res = kube_service::get_deploy_state(&info.namespace, &info.name).await?;
     Ok(HttpResponse::Ok().json(res))
 }
 
 #[post("/deploy")]
-async fn create_deploy(info: web::Json<DeployInfo>) -> Result<HttpResponse, ApiError> {
+async fn create_deploy(
+    info: web::Json<DeployInfo>,
+    ident: Identity,
+) -> Result<HttpResponse, ApiError> {
     let info = info.into_inner();
+    ident.check_namespace(&info.namespace)?;
     let res = kube_service::create_deploy(info).await?;
     Ok(HttpResponse::Ok().json(json!({
         "status": true,
@@ -75,8 +85,12 @@ async fn create_deploy(info: web::Json<DeployInfo>) -> Result<HttpResponse, ApiE
 }
 
 #[delete("/deploy")]
-async fn delete_deploy(info: web::Json<DeleteInfo>) -> Result<HttpResponse, ApiError> {
+async fn delete_deploy(
+    info: web::Json<DeleteInfo>,
+    ident: Identity,
+) -> Result<HttpResponse, ApiError> {
     let info = info.into_inner();
+    ident.check_namespace(&info.namespace)?;
 
     let res = kube_service::delete_deploy(&info.namespace, &info.name).await?;
     Ok(HttpResponse::Ok().json(json!({
@@ -85,10 +99,14 @@ async fn delete_deploy(info: web::Json<DeleteInfo>) -> Result<HttpResponse, ApiE
 }
 
 #[post("/replacedeploy")]
-async fn replace_deploy(info: web::Json<Deployment>) -> Result<HttpResponse, ApiError> {
+async fn replace_deploy(
+    info: web::Json<Deployment>,
+    ident: Identity,
+) -> Result<HttpResponse, ApiError> {
     let info = info.into_inner();
     let name = &info.name();
     if let Some(ns) = info.meta().namespace.as_ref() {
+        ident.check_namespace(ns)?;
         let o_patched = kube_service::replace_deploy(ns, name, &info).await?;
 
         Ok(HttpResponse::Ok().json(json!({
@@ -105,8 +123,12 @@ async fn replace_deploy(info: web::Json<Deployment>) -> Result<HttpResponse, Api
 }
 
 #[post("/svc")]
-async fn create_svc(info: web::Json<ServiceInfo>) -> Result<HttpResponse, ApiError> {
+async fn create_svc(
+    info: web::Json<ServiceInfo>,
+    ident: Identity,
+) -> Result<HttpResponse, ApiError> {
     let info = info.into_inner();
+    ident.check_namespace(&info.namespace)?;
 
     let res = kube_service::create_svc(info).await?;
     Ok(HttpResponse::Ok().json(json!({
@@ -117,14 +139,22 @@ async fn create_svc(info: web::Json<ServiceInfo>) -> Result<HttpResponse, ApiErr
 }
 
 #[get("/svc")]
-async fn get_svc(info: web::Query<GetInfo>) -> Result<HttpResponse, ApiError> {
+async fn get_svc(
+    info: web::Query<GetInfo>,
+    ident: Identity,
+) -> Result<HttpResponse, ApiError> {
+    ident.check_namespace_view(&info.namespace)?;
     let res = kube_service::get_svc_state(&info.namespace, &info.name).await?;
     Ok(HttpResponse::Ok().json(res))
 }
 
 #[delete("/svc")]
-async fn delete_svc(info: web::Json<DeleteInfo>) -> Result<HttpResponse, ApiError> {
+async fn delete_svc(
+    info: web::Json<DeleteInfo>,
+    ident: Identity,
+) -> Result<HttpResponse, ApiError> {
     let info = info.into_inner();
+    ident.check_namespace(&info.namespace)?;
 
     let msg = kube_service::delete_svc(&info.namespace, &info.name).await?;
     Ok(HttpResponse::Ok().json(json!({
@@ -133,10 +163,14 @@ async fn delete_svc(info: web::Json<DeleteInfo>) -> Result<HttpResponse, ApiErro
 }
 
 #[post("/replacesvc")]
-async fn replace_svc(info: web::Json<Service>) -> Result<HttpResponse, ApiError> {
+async fn replace_svc(
+    info: web::Json<Service>,
+    ident: Identity,
+) -> Result<HttpResponse, ApiError> {
     let info = info.into_inner();
     let name = &info.name();
     if let Some(ns) = info.meta().namespace.as_ref() {
+        ident.check_namespace(ns)?;
         let o_patched = kube_service::replace_svc(ns, name, &info).await?;
 
         Ok(HttpResponse::Ok().json(json!({
@@ -153,8 +187,12 @@ async fn replace_svc(info: web::Json<Service>) -> Result<HttpResponse, ApiError>
 }
 
 #[delete("/pod")]
-async fn delete_pod(info: web::Json<DeleteInfo>) -> Result<HttpResponse, ApiError> {
+async fn delete_pod(
+    info: web::Json<DeleteInfo>,
+    ident: Identity,
+) -> Result<HttpResponse, ApiError> {
     let info = info.into_inner();
+    ident.check_namespace(&info.namespace)?;
 
     let msg = kube_service::delete_pod(&info.namespace, &info.name).await?;
     Ok(HttpResponse::Ok().json(json!({
@@ -163,16 +201,24 @@ async fn delete_pod(info: web::Json<DeleteInfo>) -> Result<HttpResponse, ApiErro
 }
 
 #[get("/containers")]
-async fn get_containers(info: web::Query<GetInfo>) -> Result<HttpResponse, ApiError> {
+async fn get_containers(
+    info: web::Query<GetInfo>,
+    ident: Identity,
+) -> Result<HttpResponse, ApiError> {
     let info = info.into_inner();
+    ident.check_namespace_view(&info.namespace)?;
 
     let data = kube_service::get_containers_within(&info.namespace, &info.name).await?;
     Ok(HttpResponse::Ok().json(data))
 }
 
 #[get("/podlog")]
-async fn get_pod_log(info: web::Query<ContainerInfo>) -> Result<HttpResponse, ApiError> {
+async fn get_pod_log(
+    info: web::Query<ContainerInfo>,
+    ident: Identity,
+) -> Result<HttpResponse, ApiError> {
     let info = info.into_inner();
+    ident.check_namespace_view(&info.namespace)?;
 
     let data = kube_service::get_pod_log(&info.namespace, &info.name, info.container).await?;
     Ok(HttpResponse::Ok().json(data))
@@ -1,60 +1,370 @@
+use actix::Addr;
+use actix_redis::RedisActor;
 use actix_session::Session;
-use actix_web::{get, post, web, HttpResponse, Scope};
+use actix_web::http::header;
+use actix_web::{delete, get, post, web, HttpRequest, HttpResponse, Scope};
+use chrono::Utc;
+use rand::Rng;
 use serde_json::json;
 use uuid::Uuid;
 
+use std::collections::BTreeSet;
+
 use crate::errors::ApiError;
 use crate::models::department::Department;
-use crate::models::invitation::Invitation;
-use crate::models::user::{ClusterRole, LoginInfo, User, UserInfo};
+use crate::models::lockout::{LockKind, LockoutInfo};
+use crate::models::namespace::Namespace;
+use crate::models::repository::Repository;
+use crate::models::reset::{PasswordReset, ResetInfo, ResetRequest};
+use crate::models::token::{ApiToken, TokenData};
+use crate::models::totp::{TotpCode, TotpCredential};
+use crate::models::user::{
+    AuthProvider, ClusterRole, LoginInfo, PasswordInfo, RegisterInfo, User, UserUpdate,
+};
+use crate::mw::{AdminIdentity, ClusterAdminIdentity, Identity, IMPERSONATE_MINUTES};
+use crate::services::{
+    auth_service, email_service, kube_service, lockout_service, reconcile_service, session_service,
+};
+use crate::utils::{client_ip, totp, EMAIL_DOMAIN};
 
 #[post("/register")]
-async fn register(info: web::Json<UserInfo>) -> Result<HttpResponse, ApiError> {
-    if User::exist(&info.email)? {
-        Ok(HttpResponse::Ok().json(json!({
-            "status": false,
-            "msg":format!("User with {} exists already!", info.email)}
-        )))
-    } else {
-        let user = User::create(info.into_inner())?;
-        if let ClusterRole::DepartmentAdmin = user.role {
-            Department::set_admin(user.belong_to.unwrap_or(2), &user.id)?;
-        }
-        Invitation::set_expire(&user.email)?;
-        Ok(HttpResponse::Ok().json(json!({
+async fn register(info: web::Json<RegisterInfo>) -> Result<HttpResponse, ApiError> {
+    match User::register(&info) {
+        Ok(_) => Ok(HttpResponse::Ok().json(json!({
             "status": true,
             "msg": "Sign up successfully!",
-        })))
+        }))),
+        Err(e) if e.status_code == 409 => Ok(HttpResponse::Ok().json(json!({
+            "status": false,
+            "msg": e.msg,
+        }))),
+        Err(e) => Err(e),
     }
 }
 
 #[post("/login")]
-async fn sign_in(info: web::Json<LoginInfo>, sess: Session) -> Result<HttpResponse, ApiError> {
+async fn sign_in(
+    info: web::Json<LoginInfo>,
+    sess: Session,
+    req: HttpRequest,
+    redis: web::Data<Addr<RedisActor>>,
+) -> Result<HttpResponse, ApiError> {
     let credentials = info.into_inner();
+    let ip = client_ip(&req);
 
-    let user = User::find_by_email(&credentials.email).map_err(|err| match err.status_code {
-        404 => ApiError::new(401, "User doesn't exists".to_owned()),
-        _ => err,
-    })?;
+    lockout_service::check(&redis, &credentials.email, &ip).await?;
+    match auth_service::sign_in(&credentials).await {
+        // The failures are only cleared once the second factor passed too
+        Ok(user) => match second_factor(&user, true)? {
+            Some(challenge) => {
+                set_pending(&sess, &user)?;
+                Ok(HttpResponse::Ok().json(challenge))
+            }
+            None => {
+                lockout_service::clear(&redis, LockKind::Email, &credentials.email).await?;
+                start_session(&sess, &req, &redis, &user).await?;
+                Ok(HttpResponse::Ok().json(user))
+            }
+        },
+        Err(e) if e.status_code == 401 => {
+            lockout_service::record_failure(&redis, &credentials.email, &ip).await?;
+            Err(e)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// Maximum wrong codes of one pending sign in
+const TOTP_ATTEMPTS: i32 = 5;
+
+/// The second step challenge of users with TOTP enabled or
+/// required by their role, `None` if the password is enough.
+/// A required enrollment is only started by `enroll` when the user has
+/// no credential at all, a pending secret is never shown nor replaced
+/// to someone who only knows the password.
+fn second_factor(user: &User, enroll: bool) -> Result<Option<serde_json::Value>, ApiError> {
+    let cred = TotpCredential::find(&user.id)?;
+    match cred {
+        Some(cred) if cred.enabled => Ok(Some(json!({
+            "status": true,
+            "msg": "Verification code required",
+            "challenge": "totp",
+        }))),
+        None if enroll && TotpCredential::is_required(user.role) => {
+            let cred = TotpCredential::enroll(&user.id)?;
+            Ok(Some(json!({
+                "status": true,
+                "msg": "Two-factor authentication is required for your role",
+                "challenge": "enroll",
+                "secret": cred.secret,
+                "uri": totp::uri("Pegasus", &user.email, &cred.secret),
+            })))
+        }
+        _ if TotpCredential::is_required(user.role) => Ok(Some(json!({
+            "status": true,
+            "msg": "Confirm the enrollment started before with a code, \
+                    or ask an administrator to reset it",
+            "challenge": "enroll",
+        }))),
+        _ => Ok(None),
+    }
+}
 
-    let is_valid = user.verify_password(&credentials.password)?;
+/// Remember the user who passed the first step, `user_id` is only
+/// set after the second one
+fn set_pending(sess: &Session, user: &User) -> Result<(), ApiError> {
+    sess.remove("user_id");
+    sess.remove("pending_challenge");
+    sess.set("pending_user_id", user.id)?;
+    sess.set("pending_attempts", 0)?;
+    Ok(())
+}
 
-    if is_valid {
-        sess.set("user_id", user.id)?;
-        sess.set("cluster_role", &user.role)?;
-        sess.renew();
+/// Sign the user in and register the session for listing and revocation
+async fn start_session(
+    sess: &Session,
+    req: &HttpRequest,
+    redis: &Addr<RedisActor>,
+    user: &User,
+) -> Result<(), ApiError> {
+    let user_agent = req
+        .headers()
+        .get(header::USER_AGENT)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("unknown");
+    let session_id = session_service::start(redis, &user.id, &client_ip(req), user_agent).await?;
 
-        Ok(HttpResponse::Ok().json(user))
+    sess.remove("pending_user_id");
+    sess.remove("pending_attempts");
+    sess.remove("pending_challenge");
+    sess.set("user_id", user.id)?;
+    sess.set("session_id", session_id)?;
+    sess.renew();
+    Ok(())
+}
+
+// Redirect the browser to the OIDC provider
+#[get("/oidc/login")]
+async fn oidc_login(sess: Session) -> Result<HttpResponse, ApiError> {
+    let state: [u8; 16] = rand::thread_rng().gen();
+    let state = hex::encode(state);
+
+    let url = auth_service::oidc_authorize_url(&state)?;
+    sess.set("oidc_state", &state)?;
+    Ok(HttpResponse::Found().header(header::LOCATION, url).finish())
+}
+
+#[derive(Deserialize)]
+struct OidcCallback {
+    pub code: String,
+    pub state: String,
+}
+
+#[get("/oidc/callback")]
+async fn oidc_callback(
+    info: web::Query<OidcCallback>,
+    sess: Session,
+    req: HttpRequest,
+    redis: web::Data<Addr<RedisActor>>,
+) -> Result<HttpResponse, ApiError> {
+    let info = info.into_inner();
+    let state: Option<String> = sess.get("oidc_state")?;
+    sess.remove("oidc_state");
+    if state.as_ref() != Some(&info.state) {
+        return Err(ApiError::new(400, "Invalid OIDC state".to_owned()));
+    }
+
+    let user = auth_service::oidc_sign_in(&info.code).await?;
+    // The front end fetches the challenge from `/totp/challenge`
+    let location = if let Some(challenge) = second_factor(&user, true)? {
+        set_pending(&sess, &user)?;
+        sess.set("pending_challenge", challenge)?;
+        format!("http://{}/totp", EMAIL_DOMAIN.as_str())
     } else {
-        Err(ApiError::new(401, "Password not invalid".to_owned()))
+        start_session(&sess, &req, &redis, &user).await?;
+        format!("http://{}/", EMAIL_DOMAIN.as_str())
+    };
+    Ok(HttpResponse::Found()
+        .header(header::LOCATION, location)
+        .finish())
+}
+
+fn pending_user(sess: &Session) -> Result<User, ApiError> {
+    let id: Option<Uuid> = sess.get("pending_user_id")?;
+
+    match id {
+        Some(id) => User::find(id),
+        None => Err(ApiError::new(401, "No pending sign in".to_owned())),
+    }
+}
+
+// The challenge of the pending sign in, a secret enrolled by an OIDC
+// sign in is handed out once
+#[post("/totp/challenge")]
+async fn totp_challenge(sess: Session) -> Result<HttpResponse, ApiError> {
+    let user = pending_user(&sess)?;
+    let started: Option<serde_json::Value> = sess.get("pending_challenge")?;
+    if let Some(challenge) = started {
+        sess.remove("pending_challenge");
+        return Ok(HttpResponse::Ok().json(challenge));
     }
+
+    match second_factor(&user, false)? {
+        Some(challenge) => Ok(HttpResponse::Ok().json(challenge)),
+        None => Err(ApiError::new(400, "No challenge required".to_owned())),
+    }
+}
+
+#[derive(Deserialize)]
+struct VerifyInfo {
+    pub code: Option<String>,
+    pub recovery_code: Option<String>,
+}
+
+// Second sign in step with an authenticator or recovery code, the first
+// valid code of a required enrollment enables the credential
+#[post("/totp/verify")]
+async fn totp_verify(
+    info: web::Json<VerifyInfo>,
+    sess: Session,
+    req: HttpRequest,
+    redis: web::Data<Addr<RedisActor>>,
+) -> Result<HttpResponse, ApiError> {
+    let info = info.into_inner();
+    let user = pending_user(&sess)?;
+    let ip = client_ip(&req);
+    lockout_service::check(&redis, &user.email, &ip).await?;
+    let attempts: i32 = sess.get("pending_attempts")?.unwrap_or(0);
+    if attempts >= TOTP_ATTEMPTS {
+        sess.purge();
+        return Err(ApiError::new(
+            401,
+            "Too many invalid codes, sign in again".to_owned(),
+        ));
+    }
+    let cred = TotpCredential::find(&user.id)?.ok_or_else(|| {
+        ApiError::new(400, "Two-factor authentication is not enrolled".to_owned())
+    })?;
+
+    let mut recovery_codes = None;
+    let passed = match (info.code, info.recovery_code) {
+        (Some(code), _) if !cred.enabled => {
+            recovery_codes = cred.enable(&code)?;
+            recovery_codes.is_some()
+        }
+        (Some(code), _) => cred.verify(&code)?,
+        (None, Some(code)) if cred.enabled => cred.use_recovery(&code)?,
+        _ => false,
+    };
+    if !passed {
+        // Counted like wrong passwords, signing in again gives no fresh guesses
+        lockout_service::record_failure(&redis, &user.email, &ip).await?;
+        sess.set("pending_attempts", attempts + 1)?;
+        return Err(ApiError::new(401, "Invalid verification code".to_owned()));
+    }
+
+    lockout_service::clear(&redis, LockKind::Email, &user.email).await?;
+    start_session(&sess, &req, &redis, &user).await?;
+    Ok(HttpResponse::Ok().json(json!({
+        "status": true,
+        "msg": "Signed in successfully",
+        "data": user,
+        "recovery_codes": recovery_codes,
+    })))
+}
+
+#[post("/totp/enroll")]
+async fn totp_enroll(ident: Identity) -> Result<HttpResponse, ApiError> {
+    ident.check_not_impersonated()?;
+    let user = ident.user()?;
+    let cred = TotpCredential::enroll(&user.id)?;
+
+    Ok(HttpResponse::Ok().json(json!({
+        "status": true,
+        "msg": "Scan the secret and confirm with a code",
+        "secret": cred.secret,
+        "uri": totp::uri("Pegasus", &user.email, &cred.secret),
+    })))
+}
+
+#[post("/totp/confirm")]
+async fn totp_confirm(
+    info: web::Json<TotpCode>,
+    ident: Identity,
+) -> Result<HttpResponse, ApiError> {
+    ident.check_not_impersonated()?;
+    let cred = TotpCredential::find(&ident.id)?
+        .filter(|x| !x.enabled)
+        .ok_or_else(|| ApiError::new(400, "No pending enrollment".to_owned()))?;
+
+    match cred.enable(&info.code)? {
+        Some(codes) => Ok(HttpResponse::Ok().json(json!({
+            "status": true,
+            "msg": "Two-factor authentication enabled, keep the recovery codes safe",
+            "recovery_codes": codes,
+        }))),
+        None => Err(ApiError::new(401, "Invalid verification code".to_owned())),
+    }
+}
+
+#[derive(Deserialize)]
+struct TotpResetInfo {
+    pub id: Uuid,
+}
+
+// Drop the credential of a user who lost the authenticator or the
+// secret of a pending enrollment, the next sign in enrolls again
+#[post("/totp/reset")]
+async fn totp_reset(
+    info: web::Json<TotpResetInfo>,
+    admin: ClusterAdminIdentity,
+) -> Result<HttpResponse, ApiError> {
+    admin.0.check_not_impersonated()?;
+    TotpCredential::disable(&info.id)?;
+    info!(target: "audit", "{} reset the two-factor authentication of {}", admin.0.id, info.id);
+    Ok(HttpResponse::Ok().json(json!({
+        "status": true,
+        "msg": "Two-factor authentication reset",
+    })))
+}
+
+#[post("/totp/disable")]
+async fn totp_disable(
+    info: web::Json<TotpCode>,
+    ident: Identity,
+) -> Result<HttpResponse, ApiError> {
+    ident.check_not_impersonated()?;
+    if TotpCredential::is_required(ident.role) {
+        return Err(ApiError::new(
+            400,
+            "Two-factor authentication is required for your role".to_owned(),
+        ));
+    }
+    let cred = TotpCredential::find(&ident.id)?
+        .filter(|x| x.enabled)
+        .ok_or_else(|| ApiError::new(400, "Two-factor authentication is not enabled".to_owned()))?;
+    if !cred.verify(&info.code)? {
+        return Err(ApiError::new(401, "Invalid verification code".to_owned()));
+    }
+
+    TotpCredential::disable(&ident.id)?;
+    Ok(HttpResponse::Ok().json(json!({
+        "status": true,
+        "msg": "Two-factor authentication disabled",
+    })))
 }
 
 #[post("/logout")]
-async fn sign_out(sess: Session) -> Result<HttpResponse, ApiError> {
+async fn sign_out(
+    sess: Session,
+    redis: web::Data<Addr<RedisActor>>,
+) -> Result<HttpResponse, ApiError> {
     let id: Option<Uuid> = sess.get("user_id")?;
 
-    if let Some(_) = id {
+    if let Some(id) = id {
+        if let Some(session_id) = session_service::current(&sess) {
+            session_service::revoke(&redis, &id, &session_id).await?;
+        }
         sess.purge();
         Ok(HttpResponse::Ok().json(json!({
             "msg": "Signed out successfully"
@@ -64,38 +374,473 @@ async fn sign_out(sess: Session) -> Result<HttpResponse, ApiError> {
     }
 }
 
+// `impersonated_by` is set while an admin acts as the user
 #[post("/whoami")]
-async fn who_am_i(sess: Session) -> Result<HttpResponse, ApiError> {
-    let id: Option<Uuid> = sess.get("user_id")?;
+async fn who_am_i(ident: Identity) -> Result<HttpResponse, ApiError> {
+    let user = ident.user()?;
 
-    if let Some(id) = id {
-        let user = User::find(id)?;
-        Ok(HttpResponse::Ok().json(user))
-    } else {
-        Err(ApiError::new(401, "Unauthorized".to_owned()))
+    let mut data = serde_json::to_value(&user)?;
+    data["impersonated_by"] = json!(ident.impersonator);
+    Ok(HttpResponse::Ok().json(data))
+}
+
+#[derive(Deserialize)]
+struct ImpersonateInfo {
+    pub id: Uuid,
+}
+
+// Act as another user within the current session for `IMPERSONATE_MINUTES`
+#[post("/impersonate")]
+async fn impersonate(
+    info: web::Json<ImpersonateInfo>,
+    sess: Session,
+    admin: ClusterAdminIdentity,
+) -> Result<HttpResponse, ApiError> {
+    admin.0.check_not_impersonated()?;
+    let user = User::find(info.id)?;
+    if user.role == ClusterRole::ClusterAdmin {
+        return Err(ApiError::new(
+            400,
+            "Can not impersonate a cluster administrator".to_owned(),
+        ));
     }
+
+    let until = Utc::now() + chrono::Duration::minutes(IMPERSONATE_MINUTES);
+    sess.set("impersonate_id", user.id)?;
+    sess.set("impersonate_until", until.timestamp())?;
+    info!(
+        target: "audit",
+        "{} started impersonating {} ({})",
+        admin.0.id,
+        user.id,
+        user.email
+    );
+    Ok(HttpResponse::Ok().json(json!({
+        "status": true,
+        "msg": format!("Acting as {} until {}", user.email, until.naive_utc()),
+        "data": user,
+    })))
+}
+
+#[post("/impersonate/stop")]
+async fn stop_impersonate(sess: Session, ident: Identity) -> Result<HttpResponse, ApiError> {
+    let admin = ident
+        .impersonator
+        .ok_or_else(|| ApiError::new(400, "Not impersonating any user".to_owned()))?;
+
+    sess.remove("impersonate_id");
+    sess.remove("impersonate_until");
+    info!(
+        target: "audit",
+        "{} stopped impersonating {}",
+        admin,
+        ident.id
+    );
+    Ok(HttpResponse::Ok().json(json!({
+        "status": true,
+        "msg": "Impersonation stopped",
+    })))
+}
+
+#[derive(Deserialize)]
+struct SessionQuery {
+    pub uid: Option<Uuid>,
+}
+
+// Sessions of the signed in user, or of `uid` for administrators
+#[get("/sessions")]
+async fn list_sessions(
+    info: web::Query<SessionQuery>,
+    sess: Session,
+    redis: web::Data<Addr<RedisActor>>,
+    ident: Identity,
+) -> Result<HttpResponse, ApiError> {
+    let uid = info.uid.unwrap_or(ident.id);
+    ident.check_user(&uid)?;
+
+    let current = session_service::current(&sess);
+    let mut results = session_service::list(&redis, &uid).await?;
+    for info in results.iter_mut() {
+        info.current = Some(&info.id) == current.as_ref();
+    }
+    Ok(HttpResponse::Ok().json(results))
+}
+
+#[delete("/sessions/{id}")]
+async fn revoke_session(
+    info: web::Path<String>,
+    redis: web::Data<Addr<RedisActor>>,
+    ident: Identity,
+) -> Result<HttpResponse, ApiError> {
+    let id = info.into_inner();
+    let session = session_service::find(&redis, &id)
+        .await?
+        .ok_or_else(|| ApiError::new(404, "Session not found".to_owned()))?;
+    ident.check_user(&session.uid)?;
+
+    session_service::revoke(&redis, &session.uid, &id).await?;
+    warn!(
+        target: "audit",
+        "{} revoked session {} of {}",
+        ident.id,
+        id,
+        session.uid
+    );
+    Ok(HttpResponse::Ok().json(json!({
+        "status": true,
+        "msg": "Session revoked",
+    })))
+}
+
+#[post("/password")]
+async fn change_password(
+    info: web::Json<PasswordInfo>,
+    sess: Session,
+    redis: web::Data<Addr<RedisActor>>,
+    ident: Identity,
+) -> Result<HttpResponse, ApiError> {
+    ident.check_not_impersonated()?;
+    let info = info.into_inner();
+    let user = ident.user()?;
+
+    if user.provider != AuthProvider::Local {
+        return Err(ApiError::new(
+            400,
+            "Password is managed by your directory provider".to_owned(),
+        ));
+    }
+    if !user.verify_password(&info.old_password)? {
+        return Err(ApiError::new(401, "Password not invalid".to_owned()));
+    }
+    User::set_password(&user.id, &info.new_password)?;
+    let current = session_service::current(&sess);
+    session_service::revoke_all(&redis, &user.id, current.as_deref()).await?;
+    Ok(HttpResponse::Ok().json(json!({
+        "status": true,
+        "msg": "Password changed successfully",
+    })))
+}
+
+// Always answer the same message to avoid leaking registered emails
+#[post("/reset/request")]
+async fn request_reset(info: web::Json<ResetRequest>) -> Result<HttpResponse, ApiError> {
+    let email = info.into_inner().email;
+
+    let is_local = match User::find_by_email(&email) {
+        Ok(user) => user.provider == AuthProvider::Local,
+        Err(e) if e.status_code == 404 => false,
+        Err(e) => return Err(e),
+    };
+    if is_local && PasswordReset::count_one_day(&email)? < 3 {
+        let reset = PasswordReset::create(&email)?;
+        email_service::send_reset_email(&reset)?;
+    }
+    Ok(HttpResponse::Ok().json(json!({
+        "status": true,
+        "msg": format!("A reset link will be sent to {} if the account exists", email),
+    })))
+}
+
+#[post("/reset")]
+async fn reset_password(
+    info: web::Json<ResetInfo>,
+    redis: web::Data<Addr<RedisActor>>,
+) -> Result<HttpResponse, ApiError> {
+    let info = info.into_inner();
+
+    let email = PasswordReset::consume(&info.id, &info.password)?;
+    let user = User::find_by_email(&email)?;
+    session_service::revoke_all(&redis, &user.id, None).await?;
+    Ok(HttpResponse::Ok().json(json!({
+        "status": true,
+        "msg": "Password reset successfully",
+    })))
 }
 
 #[get("/list/{id}")]
-async fn list_depart_users(info: web::Path<i32>) -> Result<HttpResponse, ApiError> {
-    let infos = User::find_users_in(info.into_inner())?;
+async fn list_depart_users(
+    info: web::Path<i32>,
+    admin: AdminIdentity,
+) -> Result<HttpResponse, ApiError> {
+    let depart_id = info.into_inner();
+    admin.0.check_department(depart_id)?;
+    let infos = User::find_users_in(depart_id)?;
 
     Ok(HttpResponse::Ok().json(infos))
 }
 
 #[get("/all")]
-async fn list_users_all() -> Result<HttpResponse, ApiError> {
+async fn list_users_all(_: ClusterAdminIdentity) -> Result<HttpResponse, ApiError> {
     let infos = User::find_users_all()?;
 
     Ok(HttpResponse::Ok().json(infos))
 }
 
+#[post("/tokens")]
+async fn create_token(
+    info: web::Json<TokenData>,
+    ident: Identity,
+) -> Result<HttpResponse, ApiError> {
+    ident.check_not_impersonated()?;
+    let (token, plain) = ApiToken::create(&ident.id, &info.into_inner())?;
+
+    Ok(HttpResponse::Ok().json(json!({
+        "status": true,
+        "msg": "Token created, it will only be shown once",
+        "token": plain,
+        "data": token,
+    })))
+}
+
+#[get("/tokens")]
+async fn list_tokens(ident: Identity) -> Result<HttpResponse, ApiError> {
+    let tokens = ApiToken::list_of(&ident.id)?;
+
+    Ok(HttpResponse::Ok().json(tokens))
+}
+
+#[delete("/tokens/{id}")]
+async fn revoke_token(info: web::Path<Uuid>, ident: Identity) -> Result<HttpResponse, ApiError> {
+    ident.check_not_impersonated()?;
+    let token = ApiToken::revoke(&ident.id, &info.into_inner())?;
+
+    Ok(HttpResponse::Ok().json(json!({
+        "status": true,
+        "msg": format!("Token {} revoked", token.name),
+    })))
+}
+
+#[derive(Deserialize)]
+struct EditInfo {
+    pub id: Uuid,
+    pub name: String,
+}
+
+#[post("/edit")]
+async fn edit_user(info: web::Json<EditInfo>, ident: Identity) -> Result<HttpResponse, ApiError> {
+    let info = info.into_inner();
+    ident.check_user(&info.id)?;
+
+    let changes = UserUpdate {
+        name: Some(info.name),
+        ..Default::default()
+    };
+    let user = User::update(&info.id, changes)?;
+    Ok(HttpResponse::Ok().json(user))
+}
+
+#[derive(Deserialize)]
+struct MoveInfo {
+    pub id: Uuid,
+    pub belong_to: i32,
+}
+
+// Memberships only hold within a department, a moved user leaves the
+// namespaces shared with them and the members of their own namespaces go
+#[post("/move")]
+async fn move_user(
+    info: web::Json<MoveInfo>,
+    redis: web::Data<Addr<RedisActor>>,
+    _: ClusterAdminIdentity,
+) -> Result<HttpResponse, ApiError> {
+    let info = info.into_inner();
+    let before = User::find(info.id)?;
+
+    let changes = UserUpdate {
+        belong_to: Some(info.belong_to),
+        ..Default::default()
+    };
+    let user = User::update(&info.id, changes)?;
+    let mut affected = BTreeSet::new();
+    if before.belong_to != user.belong_to {
+        affected.extend(Namespace::leave_all_of(&user.id)?);
+        affected.extend(admin_view_of(&before, &user)?);
+    }
+    sync_depart_admin(&redis, &user).await?;
+    session_service::revoke_all(&redis, &user.id, None).await?;
+    reconcile_service::grant_access_all(&affected).await;
+    Ok(HttpResponse::Ok().json(user))
+}
+
+#[derive(Deserialize)]
+struct RoleInfo {
+    pub id: Uuid,
+    pub role: ClusterRole,
+}
+
+#[post("/role")]
+async fn change_role(
+    info: web::Json<RoleInfo>,
+    redis: web::Data<Addr<RedisActor>>,
+    admin: ClusterAdminIdentity,
+) -> Result<HttpResponse, ApiError> {
+    let info = info.into_inner();
+    if admin.0.id == info.id {
+        return Err(ApiError::new(
+            400,
+            "Can not change your own role".to_owned(),
+        ));
+    }
+
+    let before = User::find(info.id)?;
+
+    let changes = UserUpdate {
+        role: Some(info.role),
+        ..Default::default()
+    };
+    let user = User::update(&info.id, changes)?;
+    let affected = admin_view_of(&before, &user)?;
+    sync_depart_admin(&redis, &user).await?;
+    session_service::revoke_all(&redis, &user.id, None).await?;
+    reconcile_service::grant_access_all(&affected).await;
+    Ok(HttpResponse::Ok().json(user))
+}
+
+/// Namespaces whose department admins change when `before` becomes
+/// `after`, department admins view the namespaces of their department
+fn admin_view_of(before: &User, after: &User) -> Result<BTreeSet<String>, ApiError> {
+    let mut results = BTreeSet::new();
+    for user in [before, after].iter() {
+        if let (ClusterRole::DepartmentAdmin, Some(depart)) = (user.role, user.belong_to) {
+            results.extend(Namespace::get_of_department(depart)?);
+        }
+    }
+    Ok(results)
+}
+
+/// Keep `departments.admin` consistent with the user role and department,
+/// the admins replaced by `user` become lessees and are signed out
+async fn sync_depart_admin(redis: &Addr<RedisActor>, user: &User) -> Result<(), ApiError> {
+    Department::clear_admin(&user.id)?;
+    if let (ClusterRole::DepartmentAdmin, Some(depart)) = (user.role, user.belong_to) {
+        for old in User::admins_of(depart)?.iter().filter(|x| x.id != user.id) {
+            let changes = UserUpdate {
+                role: Some(ClusterRole::Lessee),
+                ..Default::default()
+            };
+            User::update(&old.id, changes)?;
+            session_service::revoke_all(redis, &old.id, None).await?;
+            info!(target: "audit", "{} replaced {} as admin of department {}", user.id, old.id, depart);
+        }
+        Department::set_admin(depart, &user.id)?;
+    }
+    Ok(())
+}
+
+// Delete user with all the namespaces and repositories
+#[delete("/{id}")]
+async fn delete_user(
+    info: web::Path<Uuid>,
+    redis: web::Data<Addr<RedisActor>>,
+    admin: ClusterAdminIdentity,
+) -> Result<HttpResponse, ApiError> {
+    let id = info.into_inner();
+    if admin.0.id == id {
+        return Err(ApiError::new(400, "Can not delete yourself".to_owned()));
+    }
+    let user = User::find(id)?;
+
+    for ns in Namespace::get_owned_by(&id)?.iter() {
+        kube_service::remove_network_rules(&Namespace::find_valid(ns)?).await?;
+        match kube_service::delete_ns(ns).await {
+            Err(e) if e.status_code != 404 => return Err(e),
+            _ => (),
+        }
+    }
+    kube_service::delete_account(&id).await?;
+    Namespace::delete_all_of(&id)?;
+    Repository::delete_all_of(&id)?;
+    Department::clear_admin(&id)?;
+    User::delete(id)?;
+    session_service::revoke_all(&redis, &id, None).await?;
+
+    Ok(HttpResponse::Ok().json(json!({
+        "status": true,
+        "msg": format!("User {} deleted", user.email),
+    })))
+}
+
+#[get("/lockouts")]
+async fn list_lockouts(
+    redis: web::Data<Addr<RedisActor>>,
+    _: ClusterAdminIdentity,
+) -> Result<HttpResponse, ApiError> {
+    let results = lockout_service::list(&redis).await?;
+
+    Ok(HttpResponse::Ok().json(results))
+}
+
+#[delete("/lockouts")]
+async fn clear_lockout(
+    info: web::Json<LockoutInfo>,
+    redis: web::Data<Addr<RedisActor>>,
+    _: ClusterAdminIdentity,
+) -> Result<HttpResponse, ApiError> {
+    let info = info.into_inner();
+
+    lockout_service::clear(&redis, info.kind, &info.value).await?;
+    Ok(HttpResponse::Ok().json(json!({
+        "status": true,
+        "msg": format!("Lockout of {} {} cleared", info.kind.as_str(), info.value),
+    })))
+}
+
 pub fn user_scope() -> Scope {
     web::scope("/users")
         .service(register)
         .service(sign_in)
+        .service(oidc_login)
+        .service(oidc_callback)
+        .service(totp_challenge)
+        .service(totp_verify)
+        .service(totp_enroll)
+        .service(totp_confirm)
+        .service(totp_disable)
+        .service(totp_reset)
         .service(sign_out)
         .service(who_am_i)
+        .service(impersonate)
+        .service(stop_impersonate)
+        .service(list_sessions)
+        .service(revoke_session)
+        .service(change_password)
+        .service(request_reset)
+        .service(reset_password)
         .service(list_depart_users)
         .service(list_users_all)
+        .service(create_token)
+        .service(list_tokens)
+        .service(revoke_token)
+        .service(edit_user)
+        .service(move_user)
+        .service(change_role)
+        // Before `delete_user`, whose `/{id}` would catch `/lockouts`
+        .service(list_lockouts)
+        .service(clear_lockout)
+        .service(delete_user)
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_redis::RedisActor;
+    use actix_web::http::StatusCode;
+    use actix_web::{test, App};
+    use serde_json::json;
+
+    use crate::router::api_scope;
+
+    #[actix_rt::test]
+    async fn lockouts_are_not_taken_for_a_user_id() {
+        let redis = RedisActor::start("127.0.0.1:6379");
+        let mut app = test::init_service(App::new().data(redis).service(api_scope())).await;
+
+        // `delete_user` would answer 404 as `lockouts` is no uuid, the
+        // anonymous request reaching `clear_lockout` is unauthorized
+        let req = test::TestRequest::delete()
+            .uri("/api/users/lockouts")
+            .set_json(&json!({"kind": "email", "value": "a@example.com"}))
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    }
 }
@@ -16,6 +16,7 @@ extern crate log;
 extern crate argon2;
 extern crate derive_more;
 
+use actix_redis::RedisActor;
 use actix_web::middleware::Logger;
 use actix_web::{App, HttpServer};
 
@@ -23,6 +24,7 @@ use dotenv;
 use env_logger;
 use listenfd::ListenFd;
 
+mod bootstrap;
 mod errors;
 mod handlers;
 mod models;
@@ -39,18 +41,28 @@ async fn main() -> std::io::Result<()> {
 
     std::env::set_var(
         "RUST_LOG",
-        "actix_web=info,actix_server=info,service_error=debug,kube=trace",
+        "actix_web=info,actix_server=info,service_error=debug,kube=trace,audit=info",
     );
     std::env::set_var("RUST_BACKTRACE", "1");
     env_logger::init();
     models::db::init();
 
+    let args: Vec<String> = std::env::args().collect();
+    if args.get(1).map(String::as_str) == Some("admin") {
+        return bootstrap::run(&args[2..]).map_err(|e| std::io::Error::other(e.msg));
+    }
+    bootstrap::check();
+    services::reconcile_service::spawn();
+
     let mut listenfd = ListenFd::from_env();
 
     let mut server = HttpServer::new(move || {
         App::new()
+            .wrap(mw::Audit)
+            .wrap(mw::Impersonation)
             .wrap(Logger::new("Status:%s  Req:\"%r\" %a Time:%Dms"))
             .wrap(mw::redis_session(1, DOMAIN.as_str()))
+            .data(RedisActor::start(mw::redis_addr()))
             .service(router::healthy)
             .service(router::api_scope())
     });
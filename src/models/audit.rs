@@ -0,0 +1,144 @@
+use chrono::NaiveDateTime;
+use diesel::pg::Pg;
+use diesel::prelude::*;
+use uuid::Uuid;
+
+use super::db;
+use super::user::ClusterRole;
+use crate::errors::ApiError;
+use crate::utils::schema::{audit_events, users};
+
+/// Maximum rows of one page and of one csv export
+const MAX_PER_PAGE: i64 = 200;
+const EXPORT_LIMIT: i64 = 10000;
+
+/// A mutating api request recorded by the audit middleware,
+/// `actor` is `None` for anonymous requests such as sign in
+#[derive(Debug, Serialize, Queryable)]
+pub struct AuditEvent {
+    pub id: i64,
+    pub actor: Option<Uuid>,
+    pub impersonator: Option<Uuid>,
+    pub role: Option<ClusterRole>,
+    pub method: String,
+    pub path: String,
+    pub target: Option<String>,
+    pub payload: Option<String>,
+    pub status: i32,
+    pub success: bool,
+    pub ip: String,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Debug, Insertable)]
+#[table_name = "audit_events"]
+pub struct NewAuditEvent {
+    pub actor: Option<Uuid>,
+    pub impersonator: Option<Uuid>,
+    pub role: Option<ClusterRole>,
+    pub method: String,
+    pub path: String,
+    pub target: Option<String>,
+    pub payload: Option<String>,
+    pub status: i32,
+    pub success: bool,
+    pub ip: String,
+}
+
+/// Query string filters of the audit log, `path` matches as prefix
+/// and `target` as substring
+#[derive(Default, Deserialize)]
+pub struct AuditQuery {
+    pub actor: Option<Uuid>,
+    pub method: Option<String>,
+    pub path: Option<String>,
+    pub target: Option<String>,
+    pub success: Option<bool>,
+    pub from: Option<NaiveDateTime>,
+    pub to: Option<NaiveDateTime>,
+    pub page: Option<i64>,
+    pub per_page: Option<i64>,
+}
+
+impl AuditEvent {
+    pub fn record(event: &NewAuditEvent) -> Result<(), ApiError> {
+        let conn = db::connection()?;
+
+        diesel::insert_into(audit_events::table)
+            .values(event)
+            .execute(&conn)?;
+        Ok(())
+    }
+
+    /// One page of the matched events with the total count, newest first.
+    /// `departs` restricts the actors to the users of the departments.
+    pub fn search(
+        query: &AuditQuery,
+        departs: Option<&[i32]>,
+    ) -> Result<(i64, Vec<AuditEvent>), ApiError> {
+        let conn = db::connection()?;
+
+        let per_page = query.per_page.unwrap_or(50).clamp(1, MAX_PER_PAGE);
+        let page = query.page.unwrap_or(1).max(1);
+        let total = filtered(query, departs).count().get_result(&conn)?;
+        let results = filtered(query, departs)
+            .order(audit_events::id.desc())
+            .limit(per_page)
+            .offset((page - 1) * per_page)
+            .get_results(&conn)?;
+        Ok((total, results))
+    }
+
+    /// All the matched events up to `EXPORT_LIMIT`, newest first
+    pub fn export(
+        query: &AuditQuery,
+        departs: Option<&[i32]>,
+    ) -> Result<Vec<AuditEvent>, ApiError> {
+        let conn = db::connection()?;
+
+        let results = filtered(query, departs)
+            .order(audit_events::id.desc())
+            .limit(EXPORT_LIMIT)
+            .get_results(&conn)?;
+        Ok(results)
+    }
+}
+
+fn filtered(
+    query: &AuditQuery,
+    departs: Option<&[i32]>,
+) -> audit_events::BoxedQuery<'static, Pg> {
+    let mut q = audit_events::table.into_boxed();
+
+    if let Some(actor) = query.actor {
+        q = q.filter(audit_events::actor.eq(actor));
+    }
+    if let Some(method) = query.method.as_ref() {
+        q = q.filter(audit_events::method.eq(method.to_uppercase()));
+    }
+    if let Some(path) = query.path.as_ref() {
+        q = q.filter(audit_events::path.like(format!("{}%", path)));
+    }
+    if let Some(target) = query.target.as_ref() {
+        q = q.filter(audit_events::target.like(format!("%{}%", target)));
+    }
+    if let Some(success) = query.success {
+        q = q.filter(audit_events::success.eq(success));
+    }
+    if let Some(from) = query.from {
+        q = q.filter(audit_events::created_at.ge(from));
+    }
+    if let Some(to) = query.to {
+        q = q.filter(audit_events::created_at.le(to));
+    }
+    if let Some(departs) = departs {
+        q = q.filter(
+            audit_events::actor.eq_any(
+                users::table
+                    .filter(users::belong_to.eq_any(departs.to_vec()))
+                    .select(users::id.nullable()),
+            ),
+        );
+    }
+    q
+}
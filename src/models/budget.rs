@@ -0,0 +1,248 @@
+use chrono::{NaiveDateTime, Utc};
+use diesel::prelude::*;
+
+use std::collections::BTreeMap;
+
+use super::db;
+use super::department::Department;
+use super::quota::{milli_value, to_quantity, NamespaceQuota, QuotaSpec};
+use super::user::User;
+use crate::errors::ApiError;
+use crate::utils::schema::{department_budgets, namespace_quotas, namespaces, users};
+
+/// The total quota granted to the namespaces of the members of a
+/// department and of its sub-teams, `None` amounts are unlimited
+#[derive(Debug, Serialize, Queryable)]
+pub struct DepartBudget {
+    pub department: i32,
+    pub cpu: Option<String>,
+    pub memory: Option<String>,
+    pub storage: Option<String>,
+    pub updated_at: NaiveDateTime,
+}
+
+#[derive(Deserialize)]
+pub struct BudgetInfo {
+    pub id: i32,
+    pub cpu: Option<String>,
+    pub memory: Option<String>,
+    pub storage: Option<String>,
+}
+
+/// One budgeted resource, `remaining` is negative when the budget
+/// was lowered below the quotas already granted
+#[derive(Clone, Debug, Serialize)]
+pub struct BudgetLine {
+    pub budget: String,
+    pub allocated: String,
+    pub remaining: String,
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct BudgetReport {
+    pub cpu: Option<BudgetLine>,
+    pub memory: Option<BudgetLine>,
+    pub storage: Option<BudgetLine>,
+}
+
+/// Granted amounts in thousandths, see `milli_value`
+#[derive(Clone, Copy, Default)]
+struct Allocation {
+    cpu: i128,
+    memory: i128,
+    storage: i128,
+}
+
+impl Allocation {
+    fn of(cpu: &str, memory: &str, storage: &str) -> Allocation {
+        Allocation {
+            cpu: milli_value(cpu).unwrap_or(0),
+            memory: milli_value(memory).unwrap_or(0),
+            storage: milli_value(storage).unwrap_or(0),
+        }
+    }
+
+    fn add(self, other: Allocation) -> Allocation {
+        Allocation {
+            cpu: self.cpu + other.cpu,
+            memory: self.memory + other.memory,
+            storage: self.storage + other.storage,
+        }
+    }
+}
+
+impl DepartBudget {
+    /// Create or replace the budget of a department
+    pub fn set(info: &BudgetInfo) -> Result<DepartBudget, ApiError> {
+        for x in [&info.cpu, &info.memory, &info.storage]
+            .iter()
+            .copied()
+            .flatten()
+        {
+            if milli_value(x).is_none() {
+                return Err(ApiError::new(400, format!("Invalid quantity {}", x)));
+            }
+        }
+        let conn = db::connection()?;
+
+        let values = (
+            department_budgets::cpu.eq(&info.cpu),
+            department_budgets::memory.eq(&info.memory),
+            department_budgets::storage.eq(&info.storage),
+            department_budgets::updated_at.eq(Utc::now().naive_utc()),
+        );
+        let result = diesel::insert_into(department_budgets::table)
+            .values(&(department_budgets::department.eq(info.id), values))
+            .on_conflict(department_budgets::department)
+            .do_update()
+            .set(values)
+            .get_result(&conn)?;
+        Ok(result)
+    }
+
+    pub fn find(id: i32) -> Result<Option<DepartBudget>, ApiError> {
+        let conn = db::connection()?;
+
+        let result = department_budgets::table.find(id).first(&conn).optional()?;
+        Ok(result)
+    }
+
+    /// Budget against the allocation of department `id`,
+    /// empty if the department has no budget
+    pub fn report(id: i32) -> Result<BudgetReport, ApiError> {
+        match DepartBudget::find(id)? {
+            Some(budget) => Ok(budget.report_of(budget.allocated(None)?)),
+            None => Ok(BudgetReport::default()),
+        }
+    }
+
+    /// Reports of all the budgeted departments
+    pub fn reports() -> Result<BTreeMap<i32, BudgetReport>, ApiError> {
+        let conn = db::connection()?;
+
+        let budgets: Vec<DepartBudget> = department_budgets::table.get_results(&conn)?;
+        let mut results = BTreeMap::new();
+        for budget in budgets {
+            let report = budget.report_of(budget.allocated(None)?);
+            results.insert(budget.department, report);
+        }
+        Ok(results)
+    }
+
+    /// Check granting `spec` to a namespace of `owner` keeps every department
+    /// above the owner within budget. `replacing` is the namespace whose
+    /// current grant is replaced by `spec`.
+    pub fn check(owner: &User, replacing: Option<i32>, spec: &QuotaSpec) -> Result<(), ApiError> {
+        let depart = match owner.belong_to {
+            Some(depart) => depart,
+            None => return Ok(()),
+        };
+        let extra = Allocation::of(&spec.cpu, &spec.memory, &spec.storage);
+
+        for id in Department::ancestors(depart)? {
+            let budget = match DepartBudget::find(id)? {
+                Some(budget) => budget,
+                None => continue,
+            };
+            let current = budget.allocated(None)?;
+            let allocated = budget.allocated(replacing)?.add(extra);
+            let lines = [
+                ("cpu", &budget.cpu, current.cpu, allocated.cpu),
+                ("memory", &budget.memory, current.memory, allocated.memory),
+                (
+                    "storage",
+                    &budget.storage,
+                    current.storage,
+                    allocated.storage,
+                ),
+            ];
+            // Lowering a grant is fine even for departments already over budget
+            for (name, limit, current, used) in lines.iter() {
+                if let Some(limit) = limit {
+                    if milli_value(limit).unwrap_or(0) < *used && current < used {
+                        return Err(ApiError::new(
+                            403,
+                            format!(
+                                "The {} budget {} of department {} would be exceeded",
+                                name, limit, id
+                            ),
+                        ));
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// `check` and record the grant of `spec` to namespace `namespace_id`
+    /// while the budgets above `owner` are locked, so concurrent grants are
+    /// checked one after the other. Returns the new grant with the replaced
+    /// one for `NamespaceQuota::restore` when the cluster rejects it.
+    pub fn grant(
+        owner: &User,
+        namespace_id: i32,
+        spec: &QuotaSpec,
+    ) -> Result<(NamespaceQuota, Option<NamespaceQuota>), ApiError> {
+        let conn = db::connection()?;
+
+        conn.transaction(|| {
+            if let Some(depart) = owner.belong_to {
+                department_budgets::table
+                    .filter(department_budgets::department.eq_any(Department::ancestors(depart)?))
+                    .select(department_budgets::department)
+                    .for_update()
+                    .load::<i32>(&conn)?;
+            }
+            let previous = NamespaceQuota::find(namespace_id)?;
+            DepartBudget::check(owner, Some(namespace_id), spec)?;
+            let granted = NamespaceQuota::grant(namespace_id, spec)?;
+            Ok((granted, previous))
+        })
+    }
+
+    /// Quotas granted within the department and its sub-teams,
+    /// leaving out the grant of namespace `except`
+    fn allocated(&self, except: Option<i32>) -> Result<Allocation, ApiError> {
+        allocated(&Department::subtree_ids(self.department)?, except)
+    }
+
+    fn report_of(&self, allocated: Allocation) -> BudgetReport {
+        let line = |budget: &Option<String>, used: i128| {
+            budget.as_ref().map(|x| BudgetLine {
+                budget: x.clone(),
+                allocated: to_quantity(used),
+                remaining: to_quantity(milli_value(x).unwrap_or(0) - used),
+            })
+        };
+        BudgetReport {
+            cpu: line(&self.cpu, allocated.cpu),
+            memory: line(&self.memory, allocated.memory),
+            storage: line(&self.storage, allocated.storage),
+        }
+    }
+}
+
+/// Sum of the quotas granted to the valid namespaces of the members
+/// of `departs`
+fn allocated(departs: &[i32], except: Option<i32>) -> Result<Allocation, ApiError> {
+    let conn = db::connection()?;
+
+    let granted: Vec<(i32, String, String, String)> = namespace_quotas::table
+        .inner_join(namespaces::table.inner_join(users::table))
+        .filter(namespaces::valid.eq(true))
+        .filter(users::belong_to.eq_any(departs))
+        .select((
+            namespace_quotas::namespace_id,
+            namespace_quotas::cpu,
+            namespace_quotas::memory,
+            namespace_quotas::storage,
+        ))
+        .get_results(&conn)?;
+    let result = granted
+        .iter()
+        .filter(|(id, ..)| Some(*id) != except)
+        .fold(Allocation::default(), |sum, (_, cpu, memory, storage)| {
+            sum.add(Allocation::of(cpu, memory, storage))
+        });
+    Ok(result)
+}
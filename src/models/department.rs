@@ -1,10 +1,18 @@
+use chrono::Utc;
 use diesel::prelude::*;
+use diesel::sql_types::{Int4, Varchar};
 use uuid::Uuid;
 
+use std::collections::BTreeMap;
+
+use super::budget::{BudgetReport, DepartBudget};
 use super::db;
+use super::kube::NamespaceResources;
 use super::user::{ClusterRole, User};
 use crate::errors::ApiError;
-use crate::utils::schema::{departments, users};
+use crate::utils::schema::{
+    department_budgets, departments, invitations, namespaces, quota_policies, users,
+};
 
 #[derive(Debug, Serialize, Deserialize, Queryable, Insertable)]
 #[table_name = "departments"]
@@ -12,25 +20,69 @@ pub struct Department {
     pub id: i32,
     pub name: String,
     pub admin: Option<Uuid>,
+    pub parent: Option<i32>,
+}
+
+/// `Department` with its sub-teams
+#[derive(Serialize)]
+pub struct DepartNode {
+    #[serde(flatten)]
+    pub department: Department,
+    pub children: Vec<DepartNode>,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(QueryableByName)]
+struct DepartId {
+    #[sql_type = "Int4"]
+    id: i32,
+}
+
+#[derive(Serialize, Deserialize, QueryableByName)]
 pub struct DepartInfo {
+    #[sql_type = "Int4"]
     pub id: i32,
+    #[sql_type = "Varchar"]
     pub name: String,
+    #[sql_type = "Varchar"]
     pub admin: String,
+    #[sql_type = "Varchar"]
     pub email: String,
 }
 
-impl DepartInfo {
-    fn new(id: i32, name: String, admin: String, email: String) -> DepartInfo {
-        DepartInfo {
-            id,
-            name,
-            admin,
-            email,
-        }
-    }
+/// `DepartInfo` with the budget of the department, `None` if unlimited
+#[derive(Serialize)]
+pub struct DepartOverview {
+    #[serde(flatten)]
+    pub info: DepartInfo,
+    pub budget: Option<BudgetReport>,
+}
+
+/// A department member with the resources of every namespace
+/// and the private repositories owned
+#[derive(Serialize)]
+pub struct MemberDashboard {
+    pub id: Uuid,
+    pub name: String,
+    pub email: String,
+    pub role: ClusterRole,
+    pub namespaces: BTreeMap<String, NamespaceResources>,
+    pub repositories: Vec<String>,
+}
+
+/// Members, their valid namespaces and pending invitations of a department
+#[derive(Debug, Serialize)]
+pub struct DepartUsage {
+    pub users: i64,
+    pub namespaces: i64,
+    pub invitations: i64,
+}
+
+/// Usage of a department alone and rolled up with all its sub-teams
+#[derive(Debug, Serialize)]
+pub struct UsageReport {
+    pub id: i32,
+    pub own: DepartUsage,
+    pub total: DepartUsage,
 }
 
 impl Department {
@@ -73,6 +125,75 @@ impl Department {
         Ok(info)
     }
 
+    /// Make user `admin` of department `id` its `DepartmentAdmin`, the other
+    /// admins of the department become lessees and are returned
+    pub fn appoint_admin(id: i32, admin: &Uuid) -> Result<(Department, Vec<Uuid>), ApiError> {
+        let conn = db::connection()?;
+
+        conn.transaction(|| {
+            departments::table
+                .filter(departments::id.eq(id))
+                .for_update()
+                .first::<Department>(&conn)?;
+            let user: User = users::table.find(admin).for_update().first(&conn)?;
+            if user.belong_to != Some(id) {
+                return Err(ApiError::new(
+                    400,
+                    format!("{} is not in department {}", user.email, id),
+                ));
+            }
+
+            if user.role == ClusterRole::Lessee {
+                diesel::update(users::table.find(admin))
+                    .set(users::role.eq(ClusterRole::DepartmentAdmin))
+                    .execute(&conn)?;
+            }
+            let replaced = diesel::update(
+                users::table
+                    .filter(users::belong_to.eq(id))
+                    .filter(users::role.eq(ClusterRole::DepartmentAdmin))
+                    .filter(users::id.ne(admin)),
+            )
+            .set(users::role.eq(ClusterRole::Lessee))
+            .returning(users::id)
+            .get_results(&conn)?;
+            let depart = diesel::update(departments::table.filter(departments::id.eq(id)))
+                .set(departments::admin.eq(admin))
+                .get_result(&conn)?;
+            Ok((depart, replaced))
+        })
+    }
+
+    /// Remove `admin` from the departments it manages
+    pub fn clear_admin(admin: &Uuid) -> Result<(), ApiError> {
+        let conn = db::connection()?;
+
+        diesel::update(departments::table.filter(departments::admin.eq(admin)))
+            .set(departments::admin.eq(None::<Uuid>))
+            .execute(&conn)?;
+        Ok(())
+    }
+
+    /// Id of the first department named as one of `names`
+    pub fn find_by_names(names: &[String]) -> Result<Option<i32>, ApiError> {
+        let conn = db::connection()?;
+
+        let result = departments::table
+            .filter(departments::name.eq_any(names))
+            .select(departments::id)
+            .order(departments::id)
+            .first(&conn)
+            .optional()?;
+        Ok(result)
+    }
+
+    pub fn find(id: i32) -> Result<Department, ApiError> {
+        let conn = db::connection()?;
+
+        let result = departments::table.find(id).first(&conn)?;
+        Ok(result)
+    }
+
     pub fn list_all() -> Result<Vec<Department>, ApiError> {
         let conn = db::connection()?;
 
@@ -80,43 +201,326 @@ impl Department {
         Ok(result)
     }
 
-    pub fn list_infos() -> Result<Vec<DepartInfo>, ApiError> {
+    /// One row per `DepartmentAdmin` of each department, departments
+    /// without admin are reported with `N/A`
+    pub fn list_infos() -> Result<Vec<DepartOverview>, ApiError> {
         let conn = db::connection()?;
 
-        let admins: Vec<User> = users::table
-            .filter(users::role.eq(ClusterRole::DepartmentAdmin))
-            .get_results(&conn)?;
-        let departs: Vec<Department> = departments::table.get_results(&conn)?;
-        let mut results: Vec<DepartInfo> = Vec::new();
-        for depart in departs.iter() {
-            if let None = depart.admin {
-                results.push(DepartInfo::new(
-                    depart.id,
-                    depart.name.clone(),
-                    "N/A".to_owned(),
-                    "N/A".to_owned(),
-                ))
-            }
-            for admin in admins.iter() {
-                if depart.id == admin.belong_to.unwrap() {
-                    results.push(DepartInfo::new(
-                        depart.id,
-                        depart.name.clone(),
-                        admin.name.clone(),
-                        admin.email.clone(),
-                    ))
+        let infos: Vec<DepartInfo> = diesel::sql_query(
+            "SELECT d.id, d.name, COALESCE(u.name, 'N/A') AS admin, \
+             COALESCE(u.email, 'N/A') AS email \
+             FROM departments d LEFT JOIN users u \
+             ON u.belong_to = d.id AND u.role = 'department_admin' \
+             ORDER BY d.id, u.name",
+        )
+        .load(&conn)?;
+        let budgets = DepartBudget::reports()?;
+        let results = infos
+            .into_iter()
+            .map(|info| DepartOverview {
+                budget: budgets.get(&info.id).cloned(),
+                info,
+            })
+            .collect();
+        Ok(results)
+    }
+
+    pub fn rename(id: i32, name: &str) -> Result<Department, ApiError> {
+        let conn = db::connection()?;
+
+        let info = diesel::update(departments::table.filter(departments::id.eq(id)))
+            .set(departments::name.eq(name))
+            .get_result(&conn)?;
+        Ok(info)
+    }
+
+    /// Records referring to department `id` and to its subtree
+    pub fn usage(id: i32) -> Result<UsageReport, ApiError> {
+        let conn = db::connection()?;
+
+        let subtree = subtree_of(&conn, id)?;
+        Ok(UsageReport {
+            id,
+            own: usage_of(&conn, &[id])?,
+            total: usage_of(&conn, &subtree)?,
+        })
+    }
+
+    /// Ids of department `id` and all its descendants
+    pub fn subtree_ids(id: i32) -> Result<Vec<i32>, ApiError> {
+        let conn = db::connection()?;
+
+        subtree_of(&conn, id)
+    }
+
+    /// Department `id` followed by its parents up to the top level
+    pub fn ancestors(id: i32) -> Result<Vec<i32>, ApiError> {
+        let conn = db::connection()?;
+
+        let results: Vec<DepartId> = diesel::sql_query(
+            "WITH RECURSIVE up(id, parent, depth) AS ( \
+             SELECT id, parent, 0 FROM departments WHERE id = $1 \
+             UNION SELECT d.id, d.parent, up.depth + 1 FROM departments d \
+             JOIN up ON d.id = up.parent) \
+             SELECT id FROM up ORDER BY depth",
+        )
+        .bind::<Int4, _>(id)
+        .load(&conn)?;
+        Ok(results.into_iter().map(|x| x.id).collect())
+    }
+
+    /// Whether department `id` is `ancestor` or one of its descendants
+    pub fn is_within(id: i32, ancestor: i32) -> Result<bool, ApiError> {
+        if id == ancestor {
+            return Ok(true);
+        }
+        Ok(Department::ancestors(id)?.contains(&ancestor))
+    }
+
+    /// Move department `id` under `parent`, or to the top level with `None`.
+    /// A department can not be moved into its own subtree.
+    pub fn set_parent(id: i32, parent: Option<i32>) -> Result<Department, ApiError> {
+        let conn = db::connection()?;
+
+        conn.transaction(|| {
+            diesel::sql_query("LOCK TABLE departments IN SHARE ROW EXCLUSIVE MODE")
+                .execute(&conn)?;
+            if let Some(parent) = parent {
+                if subtree_of(&conn, id)?.contains(&parent) {
+                    return Err(ApiError::new(
+                        400,
+                        "Can not move a department into its own subtree".to_owned(),
+                    ));
                 }
             }
+
+            let info = diesel::update(departments::table.filter(departments::id.eq(id)))
+                .set(departments::parent.eq(parent))
+                .get_result(&conn)?;
+            Ok(info)
+        })
+    }
+
+    /// All the departments as a forest, or the subtree of `root`
+    pub fn tree(root: Option<i32>) -> Result<Vec<DepartNode>, ApiError> {
+        let conn = db::connection()?;
+
+        let mut departs: Vec<Department> = departments::table
+            .order(departments::name)
+            .get_results(&conn)?;
+        let roots: Vec<i32> = match root {
+            Some(root) => vec![root],
+            None => departs
+                .iter()
+                .filter(|x| x.parent.is_none())
+                .map(|x| x.id)
+                .collect(),
+        };
+
+        let mut results = Vec::new();
+        for id in roots {
+            let pos = departs
+                .iter()
+                .position(|x| x.id == id)
+                .ok_or_else(|| ApiError::new(404, "Record not found".to_owned()))?;
+            let department = departs.remove(pos);
+            results.push(build_node(department, &mut departs));
         }
-        /*
-        let results = departments::table
-            .left_join(users::table.on(
-                users::table.filter(users::role.eq(ClusterRole::DepartmentAdmin))
-                departments::id.eq(users::belong_to).and(
-                    users::role.eq(ClusterRole::DepartmentAdmin))
-            ))
-            .select((departments::id, departments::name, users::name, users::email))
-            .load(&conn)?;*/
         Ok(results)
     }
+
+    /// Delete department `id`, refused while it is referenced unless
+    /// `cascade`. Cascading detaches the members with their namespaces
+    /// from any department, demotes its admins to `Lessee` and revokes
+    /// the pending invitations.
+    pub fn delete(id: i32, cascade: bool) -> Result<DepartUsage, ApiError> {
+        let conn = db::connection()?;
+
+        conn.transaction(|| {
+            let depart: Department = departments::table
+                .filter(departments::id.eq(id))
+                .for_update()
+                .first(&conn)?;
+            let usage = usage_of(&conn, &[id])?;
+            if !cascade && (usage.users > 0 || usage.invitations > 0) {
+                return Err(ApiError::new(
+                    409,
+                    format!(
+                        "Department is referenced by {} users with {} namespaces and {} pending invitations",
+                        usage.users, usage.namespaces, usage.invitations
+                    ),
+                ));
+            }
+
+            let now = Utc::now().naive_utc();
+            diesel::update(
+                users::table
+                    .filter(users::belong_to.eq(id))
+                    .filter(users::role.eq(ClusterRole::DepartmentAdmin)),
+            )
+            .set(users::role.eq(ClusterRole::Lessee))
+            .execute(&conn)?;
+            diesel::update(users::table.filter(users::belong_to.eq(id)))
+                .set(users::belong_to.eq(None::<i32>))
+                .execute(&conn)?;
+            diesel::update(
+                invitations::table
+                    .filter(invitations::department.eq(id))
+                    .filter(invitations::accepted_at.is_null())
+                    .filter(invitations::revoked_at.is_null()),
+            )
+            .set(invitations::revoked_at.eq(now))
+            .execute(&conn)?;
+            diesel::update(invitations::table.filter(invitations::department.eq(id)))
+                .set(invitations::department.eq(None::<i32>))
+                .execute(&conn)?;
+            // Sub-teams move up one level
+            diesel::update(departments::table.filter(departments::parent.eq(id)))
+                .set(departments::parent.eq(depart.parent))
+                .execute(&conn)?;
+            diesel::delete(departments::table.filter(departments::id.eq(id))).execute(&conn)?;
+            Ok(usage)
+        })
+    }
+
+    /// Move all the members and invitations of `from` into `into` and
+    /// delete `from`. The admins of `from` keep administrating the merged
+    /// department, `into` takes the admin of `from` if it has none.
+    /// Sub-teams of `from` become sub-teams of `into`. The budget and the
+    /// quota policy of `from` pass to `into`, the merge is refused when
+    /// both departments have one.
+    pub fn merge(from: i32, into: i32) -> Result<Department, ApiError> {
+        let conn = db::connection()?;
+
+        if from == into {
+            return Err(ApiError::new(
+                400,
+                "Can not merge a department into itself".to_owned(),
+            ));
+        }
+        conn.transaction(|| {
+            let source: Department = departments::table
+                .filter(departments::id.eq(from))
+                .for_update()
+                .first(&conn)?;
+            let target: Department = departments::table
+                .filter(departments::id.eq(into))
+                .for_update()
+                .first(&conn)?;
+
+            let budgets: i64 = department_budgets::table
+                .filter(department_budgets::department.eq_any(&[from, into]))
+                .count()
+                .get_result(&conn)?;
+            let policies: i64 = quota_policies::table
+                .filter(quota_policies::department.eq_any(&[from, into]))
+                .count()
+                .get_result(&conn)?;
+            if budgets > 1 || policies > 1 {
+                return Err(ApiError::new(
+                    409,
+                    format!(
+                        "Departments {} and {} both have a {}, remove one before merging",
+                        from,
+                        into,
+                        if budgets > 1 {
+                            "budget"
+                        } else {
+                            "quota policy"
+                        }
+                    ),
+                ));
+            }
+            diesel::update(
+                department_budgets::table.filter(department_budgets::department.eq(from)),
+            )
+            .set(department_budgets::department.eq(into))
+            .execute(&conn)?;
+            diesel::update(quota_policies::table.filter(quota_policies::department.eq(from)))
+                .set(quota_policies::department.eq(into))
+                .execute(&conn)?;
+            diesel::update(users::table.filter(users::belong_to.eq(from)))
+                .set(users::belong_to.eq(into))
+                .execute(&conn)?;
+            diesel::update(invitations::table.filter(invitations::department.eq(from)))
+                .set(invitations::department.eq(into))
+                .execute(&conn)?;
+            diesel::update(
+                departments::table
+                    .filter(departments::parent.eq(from))
+                    .filter(departments::id.ne(into)),
+            )
+            .set(departments::parent.eq(into))
+            .execute(&conn)?;
+            let parent = match target.parent {
+                Some(parent) if parent == from => source.parent,
+                parent => parent,
+            };
+            diesel::delete(departments::table.filter(departments::id.eq(from))).execute(&conn)?;
+            let merged = diesel::update(departments::table.filter(departments::id.eq(into)))
+                .set((
+                    departments::admin.eq(target.admin.or(source.admin)),
+                    departments::parent.eq(parent),
+                ))
+                .get_result(&conn)?;
+            Ok(merged)
+        })
+    }
+}
+
+fn subtree_of(conn: &PgConnection, id: i32) -> Result<Vec<i32>, ApiError> {
+    let results: Vec<DepartId> = diesel::sql_query(
+        "WITH RECURSIVE tree(id) AS ( \
+         SELECT id FROM departments WHERE id = $1 \
+         UNION SELECT d.id FROM departments d JOIN tree ON d.parent = tree.id) \
+         SELECT id FROM tree",
+    )
+    .bind::<Int4, _>(id)
+    .load(conn)?;
+    Ok(results.into_iter().map(|x| x.id).collect())
+}
+
+/// Take the children of `department` out of `rest` recursively
+fn build_node(department: Department, rest: &mut Vec<Department>) -> DepartNode {
+    let (children, others): (Vec<Department>, Vec<Department>) = rest
+        .drain(..)
+        .partition(|x| x.parent == Some(department.id));
+    *rest = others;
+
+    let children = children
+        .into_iter()
+        .map(|child| build_node(child, rest))
+        .collect();
+    DepartNode {
+        department,
+        children,
+    }
+}
+
+fn usage_of(conn: &PgConnection, ids: &[i32]) -> Result<DepartUsage, ApiError> {
+    let members = users::table
+        .filter(users::belong_to.eq_any(ids))
+        .select(users::id);
+    let users: i64 = users::table
+        .filter(users::belong_to.eq_any(ids))
+        .count()
+        .get_result(conn)?;
+    let namespaces: i64 = namespaces::table
+        .filter(namespaces::uid.eq_any(members))
+        .filter(namespaces::valid.eq(true))
+        .count()
+        .get_result(conn)?;
+    let invitations: i64 = invitations::table
+        .filter(invitations::department.eq_any(ids))
+        .filter(invitations::accepted_at.is_null())
+        .filter(invitations::revoked_at.is_null())
+        .filter(invitations::expires_at.ge(Utc::now().naive_utc()))
+        .count()
+        .get_result(conn)?;
+
+    Ok(DepartUsage {
+        users,
+        namespaces,
+        invitations,
+    })
 }
@@ -15,6 +15,27 @@ pub struct Invitation {
     pub is_admin: bool,
     pub expires_at: NaiveDateTime,
     pub created_at: NaiveDateTime,
+    pub invited_by: Option<Uuid>,
+    pub accepted_at: Option<NaiveDateTime>,
+    pub revoked_at: Option<NaiveDateTime>,
+}
+
+/// Lifecycle state derived from the timestamps of `Invitation`
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum InvitationStatus {
+    Pending,
+    Expired,
+    Accepted,
+    Revoked,
+}
+
+/// `Invitation` with its status for listing
+#[derive(Serialize)]
+pub struct InvitationRecord {
+    #[serde(flatten)]
+    pub invitation: Invitation,
+    pub status: InvitationStatus,
 }
 
 /// Any type impl `Into<String>` can create `Invitation`
@@ -29,15 +50,19 @@ impl From<&InvitationData> for Invitation {
             is_admin: data.is_admin,
             expires_at: now + chrono::Duration::hours(24),
             created_at: now,
+            invited_by: None,
+            accepted_at: None,
+            revoked_at: None,
         }
     }
 }
 
 impl Invitation {
-    pub fn create(data: &InvitationData) -> Result<Invitation, ApiError> {
+    pub fn create(data: &InvitationData, invited_by: &Uuid) -> Result<Invitation, ApiError> {
         let conn = db::connection()?;
 
-        let info: Invitation = Invitation::from(data);
+        let mut info: Invitation = Invitation::from(data);
+        info.invited_by = Some(*invited_by);
         let inserted = diesel::insert_into(invitations::table)
             .values(&info)
             .get_result(&conn)?;
@@ -60,14 +85,77 @@ impl Invitation {
         Ok(results)
     }
 
+    /// Accepted and revoked invitations are expired as well
     pub fn is_expired(id: &Uuid) -> Result<bool, ApiError> {
+        let info = Invitation::get_info(id)?;
+        Ok(info.status() != InvitationStatus::Pending)
+    }
+
+    pub fn status(&self) -> InvitationStatus {
+        if self.accepted_at.is_some() {
+            InvitationStatus::Accepted
+        } else if self.revoked_at.is_some() {
+            InvitationStatus::Revoked
+        } else if self.expires_at < Utc::now().naive_utc() {
+            InvitationStatus::Expired
+        } else {
+            InvitationStatus::Pending
+        }
+    }
+
+    /// Invitations of the departments or of all departments, newest first
+    pub fn list(
+        departments: Option<&[i32]>,
+        status: Option<InvitationStatus>,
+    ) -> Result<Vec<InvitationRecord>, ApiError> {
         let conn = db::connection()?;
 
         let now = Utc::now().naive_utc();
-        let info: Invitation = invitations::table
-            .filter(invitations::id.eq(id))
-            .first(&conn)?;
-        Ok(info.expires_at < now)
+        let mut query = invitations::table.into_boxed();
+        if let Some(departments) = departments {
+            query = query.filter(invitations::department.eq_any(departments));
+        }
+        query = match status {
+            Some(InvitationStatus::Accepted) => {
+                query.filter(invitations::accepted_at.is_not_null())
+            }
+            Some(InvitationStatus::Revoked) => query
+                .filter(invitations::accepted_at.is_null())
+                .filter(invitations::revoked_at.is_not_null()),
+            Some(InvitationStatus::Expired) => query
+                .filter(invitations::accepted_at.is_null())
+                .filter(invitations::revoked_at.is_null())
+                .filter(invitations::expires_at.lt(now)),
+            Some(InvitationStatus::Pending) => query
+                .filter(invitations::accepted_at.is_null())
+                .filter(invitations::revoked_at.is_null())
+                .filter(invitations::expires_at.ge(now)),
+            None => query,
+        };
+
+        let results = query
+            .order(invitations::created_at.desc())
+            .get_results::<Invitation>(&conn)?
+            .into_iter()
+            .map(|invitation| InvitationRecord {
+                status: invitation.status(),
+                invitation,
+            })
+            .collect();
+        Ok(results)
+    }
+
+    pub fn revoke(id: &Uuid) -> Result<Invitation, ApiError> {
+        let conn = db::connection()?;
+
+        let result = diesel::update(
+            invitations::table
+                .filter(invitations::id.eq(id))
+                .filter(invitations::accepted_at.is_null()),
+        )
+        .set(invitations::revoked_at.eq(Utc::now().naive_utc()))
+        .get_result(&conn)?;
+        Ok(result)
     }
 
     pub fn get_info(id: &Uuid) -> Result<Invitation, ApiError> {
@@ -78,22 +166,22 @@ impl Invitation {
             .first(&conn)?;
         Ok(info)
     }
-
-    pub fn set_expire(email: &str) -> Result<(), ApiError> {
-        let conn = db::connection()?;
-
-        let _res: Invitation =
-            diesel::update(invitations::table.filter(invitations::email.eq(email)))
-                .set(invitations::expires_at.eq(Utc::now().naive_utc()))
-                .get_result(&conn)?;
-        Ok(())
-    }
 }
 
 /// Struct to hold user sent data
-#[derive(Deserialize)]
+#[derive(Clone, Deserialize)]
 pub struct InvitationData {
     pub email: String,
     pub department: Option<i32>,
     pub is_admin: bool,
 }
+
+impl From<&Invitation> for InvitationData {
+    fn from(invit: &Invitation) -> Self {
+        InvitationData {
+            email: invit.email.clone(),
+            department: invit.department,
+            is_admin: invit.is_admin,
+        }
+    }
+}
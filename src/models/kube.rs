@@ -1,6 +1,12 @@
+use chrono::{DateTime, Utc};
 use k8s_openapi::api::apps::v1::Deployment;
 use k8s_openapi::api::core::v1::{Container, Namespace, Pod, Service};
 use kube::api::Meta;
+use uuid::Uuid;
+
+use std::collections::BTreeMap;
+
+use super::ingress::IngressResponse;
 
 const AVAILABLE: &'static str = "Available";
 const TRUE: &'static str = "True";
@@ -51,6 +57,25 @@ pub struct ServiceInfo {
     pub port: i32,
 }
 
+/// Deployments, their pods, services and ingresses of one namespace
+#[derive(Serialize)]
+pub struct NamespaceResources {
+    pub deploy: Vec<ResourceState>,
+    pub service: Vec<ResourceState>,
+    pub pod: BTreeMap<String, Vec<ResourceState>>,
+    pub ingress: Vec<IngressResponse>,
+}
+
+/// A namespace labeled `dispense=pegasus` in the cluster, `owner` is
+/// missing for namespaces the network profile was never applied to
+#[derive(Serialize)]
+pub struct ManagedNamespace {
+    pub name: String,
+    pub owner: Option<Uuid>,
+    pub active: bool,
+    pub created_at: Option<DateTime<Utc>>,
+}
+
 /// ResourceState `From` traits
 impl From<&Deployment> for ResourceState {
     fn from(info: &Deployment) -> Self {
@@ -0,0 +1,49 @@
+/// Sign in lockout key kind, failures are counted for
+/// both the email and the client ip
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LockKind {
+    Email,
+    Ip,
+}
+
+impl LockKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            LockKind::Email => "email",
+            LockKind::Ip => "ip",
+        }
+    }
+
+    pub fn parse(kind: &str) -> Option<LockKind> {
+        match kind {
+            "email" => Some(LockKind::Email),
+            "ip" => Some(LockKind::Ip),
+            _ => None,
+        }
+    }
+
+    /// Failures allowed before the key is locked
+    pub fn threshold(self) -> i64 {
+        match self {
+            LockKind::Email => 5,
+            LockKind::Ip => 20,
+        }
+    }
+}
+
+/// Active lockout, `ttl` is the remaining seconds
+#[derive(Serialize)]
+pub struct Lockout {
+    pub kind: LockKind,
+    pub value: String,
+    pub failures: i64,
+    pub ttl: i64,
+}
+
+/// Json parse data to clear a lockout
+#[derive(Deserialize)]
+pub struct LockoutInfo {
+    pub kind: LockKind,
+    pub value: String,
+}
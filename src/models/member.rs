@@ -0,0 +1,188 @@
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+use diesel::sql_types::Varchar;
+use uuid::Uuid;
+
+use super::db;
+use super::namespace::Namespace;
+use super::user::User;
+use crate::errors::ApiError;
+use crate::utils::schema::{namespace_members, namespaces, users};
+
+/// Role of a user in a namespace. `Maintainer`s operate the workloads
+/// like the `Owner`, only the owner deletes the namespace and shares it.
+#[derive(Clone, Copy, DbEnum, Debug, PartialEq, Serialize, Deserialize)]
+pub enum MemberRole {
+    Owner,
+    Maintainer,
+    Viewer,
+}
+
+impl MemberRole {
+    /// Whether the role allows at least what `other` does
+    pub fn includes(self, other: MemberRole) -> bool {
+        self.rank() >= other.rank()
+    }
+
+    fn rank(self) -> u8 {
+        match self {
+            MemberRole::Owner => 2,
+            MemberRole::Maintainer => 1,
+            MemberRole::Viewer => 0,
+        }
+    }
+}
+
+#[derive(QueryableByName)]
+struct NamespaceName {
+    #[sql_type = "Varchar"]
+    namespace: String,
+}
+
+#[derive(Debug, Serialize, Queryable)]
+pub struct NamespaceMember {
+    pub namespace_id: i32,
+    pub uid: Uuid,
+    pub role: MemberRole,
+    pub added_by: Option<Uuid>,
+    pub created_at: NaiveDateTime,
+}
+
+/// `NamespaceMember` with the name and email of the user
+#[derive(Debug, Serialize, Queryable)]
+pub struct MemberInfo {
+    pub uid: Uuid,
+    pub name: String,
+    pub email: String,
+    pub role: MemberRole,
+    pub added_by: Option<Uuid>,
+    pub created_at: NaiveDateTime,
+}
+
+impl NamespaceMember {
+    /// Share namespace `ns` with `user` of the owner's department,
+    /// the role of a member already there is replaced
+    pub fn add(
+        ns: &Namespace,
+        user: &User,
+        role: MemberRole,
+        by: &Uuid,
+    ) -> Result<NamespaceMember, ApiError> {
+        if role == MemberRole::Owner {
+            return Err(ApiError::new(
+                400,
+                "A namespace has exactly one owner".to_owned(),
+            ));
+        }
+        if user.id == ns.uid {
+            return Err(ApiError::new(
+                400,
+                format!("{} owns namespace {}", user.email, ns.namespace),
+            ));
+        }
+        let depart = User::find(ns.uid)?.belong_to;
+        if depart.is_none() || user.belong_to != depart {
+            return Err(ApiError::new(
+                403,
+                format!(
+                    "{} is not in the department of namespace {}",
+                    user.email, ns.namespace
+                ),
+            ));
+        }
+        let conn = db::connection()?;
+
+        let result = diesel::insert_into(namespace_members::table)
+            .values(&(
+                namespace_members::namespace_id.eq(ns.id),
+                namespace_members::uid.eq(user.id),
+                namespace_members::role.eq(role),
+                namespace_members::added_by.eq(by),
+            ))
+            .on_conflict((namespace_members::namespace_id, namespace_members::uid))
+            .do_update()
+            .set((
+                namespace_members::role.eq(role),
+                namespace_members::added_by.eq(by),
+            ))
+            .get_result(&conn)?;
+        Ok(result)
+    }
+
+    /// Remove user `uid` from namespace `namespace_id`, the owner stays
+    pub fn remove(namespace_id: i32, uid: &Uuid) -> Result<NamespaceMember, ApiError> {
+        let conn = db::connection()?;
+
+        let result = diesel::delete(
+            namespace_members::table
+                .find((namespace_id, uid))
+                .filter(namespace_members::role.ne(MemberRole::Owner)),
+        )
+        .get_result(&conn)?;
+        Ok(result)
+    }
+
+    /// Remove the members no longer in the department of the owner, after
+    /// departments were merged or deleted, returning the valid namespaces
+    /// they left
+    pub fn prune() -> Result<Vec<String>, ApiError> {
+        let conn = db::connection()?;
+
+        let results: Vec<NamespaceName> = diesel::sql_query(
+            "DELETE FROM namespace_members m USING namespaces n, users o, users u \
+             WHERE m.namespace_id = n.id AND n.uid = o.id AND m.uid = u.id \
+             AND m.role <> 'owner' AND n.valid \
+             AND (o.belong_to IS NULL OR u.belong_to IS DISTINCT FROM o.belong_to) \
+             RETURNING n.namespace",
+        )
+        .load(&conn)?;
+        Ok(results.into_iter().map(|x| x.namespace).collect())
+    }
+
+    pub fn list_of(namespace_id: i32) -> Result<Vec<MemberInfo>, ApiError> {
+        let conn = db::connection()?;
+
+        let results = namespace_members::table
+            .inner_join(users::table)
+            .filter(namespace_members::namespace_id.eq(namespace_id))
+            .select((
+                namespace_members::uid,
+                users::name,
+                users::email,
+                namespace_members::role,
+                namespace_members::added_by,
+                namespace_members::created_at,
+            ))
+            .order(namespace_members::created_at)
+            .get_results(&conn)?;
+        Ok(results)
+    }
+
+    /// Members of the valid namespace `ns` with their roles
+    pub fn roles_in(ns: &str) -> Result<Vec<(Uuid, MemberRole)>, ApiError> {
+        let conn = db::connection()?;
+
+        let results = namespace_members::table
+            .inner_join(namespaces::table)
+            .filter(namespaces::namespace.eq(ns))
+            .filter(namespaces::valid.eq(true))
+            .select((namespace_members::uid, namespace_members::role))
+            .get_results(&conn)?;
+        Ok(results)
+    }
+
+    /// Role of user `uid` in the valid namespace `ns`
+    pub fn role_of(ns: &str, uid: &Uuid) -> Result<Option<MemberRole>, ApiError> {
+        let conn = db::connection()?;
+
+        let result = namespace_members::table
+            .inner_join(namespaces::table)
+            .filter(namespaces::namespace.eq(ns))
+            .filter(namespaces::valid.eq(true))
+            .filter(namespace_members::uid.eq(uid))
+            .select(namespace_members::role)
+            .first(&conn)
+            .optional()?;
+        Ok(result)
+    }
+}
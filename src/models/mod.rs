@@ -1,12 +1,23 @@
+pub mod audit;
+pub mod budget;
 pub mod db;
 pub mod department;
 pub mod gitapis;
 pub mod invitation;
 pub mod kube;
+pub mod lockout;
+pub mod member;
 pub mod namespace;
+pub mod network;
+pub mod quota;
 pub mod registry;
 pub mod repository;
+pub mod request;
+pub mod reset;
+pub mod session;
 pub mod tag;
+pub mod token;
+pub mod totp;
 pub mod user;
 pub mod ingress;
 
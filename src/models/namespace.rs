@@ -1,10 +1,17 @@
 use diesel::prelude::*;
-use diesel::dsl::{exists, select};
 use uuid::Uuid;
 
 use super::db;
+use super::department::Department;
+use super::member::MemberRole;
+use super::user::User;
 use crate::errors::ApiError;
-use crate::utils::schema::namespaces;
+use crate::utils::schema::{namespace_members, namespaces, users};
+
+/// Longest DNS-1123 label
+const NAME_MAX: usize = 63;
+/// Names never handed out, `RESERVED_NAMESPACES` adds more
+const RESERVED: [&str; 4] = ["default", "kube-system", "kube-public", "kube-node-lease"];
 
 #[derive(Serialize, Deserialize, Insertable, Queryable, Clone)]
 #[table_name = "namespaces"]
@@ -22,32 +29,123 @@ pub struct NamespaceInfo {
 }
 
 impl Namespace {
-    pub fn create(info: NamespaceInfo) -> Result<Namespace, ApiError> {
+    /// Record namespace `info.ns` of user `info.uid`. A name released by
+    /// the same user is taken back, names ever used by another user are
+    /// never reassigned. The user row is locked while counting against
+    /// `limit` so concurrent creations can not exceed it.
+    pub fn create(info: &NamespaceInfo, limit: Option<i32>) -> Result<Namespace, ApiError> {
         let conn = db::connection()?;
 
-        if select(exists(namespaces::table
-                         .filter(namespaces::namespace.eq(&info.ns))))
-            .get_result(&conn)? {
-                let result = diesel::update(namespaces::table
-                                            .filter(namespaces::namespace.eq(&info.ns)))
-                    .set(namespaces::valid.eq(true))
+        conn.transaction(|| {
+            if let Some(limit) = limit {
+                users::table
+                    .find(info.uid)
+                    .select(users::id)
+                    .for_update()
+                    .first::<Uuid>(&conn)?;
+                let owned: i64 = namespaces::table
+                    .filter(namespaces::uid.eq(info.uid))
+                    .filter(namespaces::valid.eq(true))
+                    .count()
                     .get_result(&conn)?;
+                if owned >= i64::from(limit) {
+                    return Err(ApiError::new(
+                        403,
+                        format!(
+                            "Namespace limit {} reached, request more namespaces first",
+                            limit
+                        ),
+                    ));
+                }
+            }
+            let existing: Option<Namespace> = namespaces::table
+                .filter(namespaces::namespace.eq(&info.ns))
+                .for_update()
+                .first(&conn)
+                .optional()?;
+            match existing {
+                Some(ns) if ns.uid != info.uid => Err(ApiError::new(
+                    409,
+                    format!("Namespace {} belonged to another user", ns.namespace),
+                )),
+                Some(ns) if ns.valid => Err(ApiError::new(
+                    409,
+                    format!("Namespace {} already exists", ns.namespace),
+                )),
+                Some(ns) => {
+                    let result = diesel::update(namespaces::table.find(ns.id))
+                        .set(namespaces::valid.eq(true))
+                        .get_result(&conn)?;
+                    Ok(result)
+                }
+                None => {
+                    let result: Namespace = diesel::insert_into(namespaces::table)
+                        .values(&(
+                            namespaces::uid.eq(info.uid),
+                            namespaces::namespace.eq(&info.ns),
+                            namespaces::valid.eq(true),
+                        ))
+                        .get_result(&conn)?;
+                    diesel::insert_into(namespace_members::table)
+                        .values(&(
+                            namespace_members::namespace_id.eq(result.id),
+                            namespace_members::uid.eq(info.uid),
+                            namespace_members::role.eq(MemberRole::Owner),
+                        ))
+                        .execute(&conn)?;
+                    Ok(result)
+                }
+            }
+        })
+    }
 
-                Ok(result)
+    /// Check namespace `ns` may be created for user `uid`, done before
+    /// anything is created in the cluster
+    pub fn check_available(ns: &str, uid: &Uuid) -> Result<(), ApiError> {
+        let conn = db::connection()?;
 
-            } else {
-                let result = diesel::insert_into(namespaces::table)
-                    .values(&(
-                        namespaces::uid.eq(info.uid),
-                        namespaces::namespace.eq(info.ns),
-                        namespaces::valid.eq(true),
-                    ))
-                    .get_result(&conn)?;
+        let existing: Option<Namespace> = namespaces::table
+            .filter(namespaces::namespace.eq(ns))
+            .first(&conn)
+            .optional()?;
+        match existing {
+            Some(x) if x.uid != *uid => Err(ApiError::new(
+                409,
+                format!("Namespace {} belonged to another user", ns),
+            )),
+            Some(x) if x.valid => Err(ApiError::new(
+                409,
+                format!("Namespace {} already exists", ns),
+            )),
+            _ => Ok(()),
+        }
+    }
 
-                Ok(result)
-            }
+    /// Full name of the namespace `suffix` of `user` following
+    /// `NAMESPACE_PATTERN`, e.g. `{department}-{user}-{suffix}`.
+    /// `{user}` is the local part of the email, `{department}` the
+    /// department name, both reduced to DNS-1123 characters.
+    pub fn name_for(user: &User, suffix: &str) -> Result<String, ApiError> {
+        let pattern =
+            std::env::var("NAMESPACE_PATTERN").unwrap_or_else(|_| "{suffix}".to_owned());
+        let mut name = pattern.replace("{suffix}", suffix);
+        if name.contains("{user}") {
+            let local = user.email.split('@').next().unwrap_or_default();
+            name = name.replace("{user}", &slug(local));
+        }
+        if name.contains("{department}") {
+            let depart = match user.belong_to {
+                Some(id) => Department::find(id)?.name,
+                None => "none".to_owned(),
+            };
+            name = name.replace("{department}", &slug(&depart));
+        }
+        check_name(&name)?;
+        Ok(name)
     }
 
+    /// Set namespace `ns` of user `uid` invalid, the collaborators
+    /// lose it and are not back when the owner takes it back
     pub fn delete(uid: &Uuid, ns: &str) -> Result<String, ApiError> {
         let conn = db::connection()?;
 
@@ -56,14 +154,120 @@ impl Namespace {
         )
         .set(namespaces::valid.eq(false))
         .get_result(&conn)?;
+        diesel::delete(
+            namespace_members::table
+                .filter(namespace_members::namespace_id.eq(result.id))
+                .filter(namespace_members::role.ne(MemberRole::Owner)),
+        )
+        .execute(&conn)?;
         Ok(result.namespace)
     }
 
+    /// Owner of a valid namespace, `None` if the namespace
+    /// is not managed by pegasus
+    pub fn owner_of(ns: &str) -> Result<Option<Uuid>, ApiError> {
+        let conn = db::connection()?;
+
+        let result = namespaces::table
+            .filter(namespaces::namespace.eq(ns))
+            .filter(namespaces::valid.eq(true))
+            .select(namespaces::uid)
+            .first(&conn)
+            .optional()?;
+        Ok(result)
+    }
+
+    pub fn find(id: i32) -> Result<Namespace, ApiError> {
+        let conn = db::connection()?;
+
+        let result = namespaces::table.find(id).first(&conn)?;
+        Ok(result)
+    }
+
+    pub fn find_valid(ns: &str) -> Result<Namespace, ApiError> {
+        let conn = db::connection()?;
+
+        let result = namespaces::table
+            .filter(namespaces::namespace.eq(ns))
+            .filter(namespaces::valid.eq(true))
+            .first(&conn)?;
+        Ok(result)
+    }
+
+    pub fn list_valid() -> Result<Vec<Namespace>, ApiError> {
+        let conn = db::connection()?;
+
+        let results = namespaces::table
+            .filter(namespaces::valid.eq(true))
+            .order(namespaces::id)
+            .get_results(&conn)?;
+        Ok(results)
+    }
+
+    /// Set all namespaces of user `uid` invalid
+    pub fn delete_all_of(uid: &Uuid) -> Result<usize, ApiError> {
+        let conn = db::connection()?;
+
+        let result = diesel::update(namespaces::table.filter(namespaces::uid.eq(uid)))
+            .set(namespaces::valid.eq(false))
+            .execute(&conn)?;
+        let owned = namespaces::table
+            .filter(namespaces::uid.eq(uid))
+            .select(namespaces::id);
+        diesel::delete(
+            namespace_members::table
+                .filter(namespace_members::namespace_id.eq_any(owned))
+                .filter(namespace_members::role.ne(MemberRole::Owner)),
+        )
+        .execute(&conn)?;
+        Ok(result)
+    }
+
+    /// Drop the memberships of user `uid` in other namespaces and the
+    /// members of the namespaces `uid` owns, returning the valid
+    /// namespaces whose access changed
+    pub fn leave_all_of(uid: &Uuid) -> Result<Vec<String>, ApiError> {
+        let affected = Namespace::get_ns_of(uid)?;
+        let conn = db::connection()?;
+
+        let owned = namespaces::table
+            .filter(namespaces::uid.eq(uid))
+            .select(namespaces::id);
+        diesel::delete(
+            namespace_members::table
+                .filter(
+                    namespace_members::uid
+                        .eq(uid)
+                        .or(namespace_members::namespace_id.eq_any(owned)),
+                )
+                .filter(namespace_members::role.ne(MemberRole::Owner)),
+        )
+        .execute(&conn)?;
+        Ok(affected)
+    }
+
+    /// Valid namespaces owned by the users of department `depart`
+    pub fn get_of_department(depart: i32) -> Result<Vec<String>, ApiError> {
+        let conn = db::connection()?;
+
+        let results = namespaces::table
+            .inner_join(users::table)
+            .filter(users::belong_to.eq(depart))
+            .filter(namespaces::valid.eq(true))
+            .select(namespaces::namespace)
+            .get_results(&conn)?;
+        Ok(results)
+    }
+
+    /// Valid namespaces user `uid` owns or is a member of
     pub fn get_ns_of(uid: &Uuid) -> Result<Vec<String>, ApiError> {
         let conn = db::connection()?;
 
+        let shared = namespace_members::table
+            .filter(namespace_members::uid.eq(uid))
+            .select(namespace_members::namespace_id);
         let results: Vec<String> = namespaces::table
-            .filter(namespaces::uid.eq(uid))
+            .filter(namespaces::uid.eq(uid).or(namespaces::id.eq_any(shared)))
             .filter(namespaces::valid.eq(true))
             .get_results(&conn)?
             .iter()
@@ -71,4 +275,60 @@ impl Namespace {
             .collect();
         Ok(results)
     }
+
+    /// Valid namespaces owned by user `uid`, leaving out the shared ones
+    pub fn get_owned_by(uid: &Uuid) -> Result<Vec<String>, ApiError> {
+        let conn = db::connection()?;
+
+        let results = namespaces::table
+            .filter(namespaces::uid.eq(uid))
+            .filter(namespaces::valid.eq(true))
+            .select(namespaces::namespace)
+            .get_results(&conn)?;
+        Ok(results)
+    }
+}
+
+/// Lower case `x` and replace the characters not allowed in a
+/// DNS-1123 label by `-`
+fn slug(x: &str) -> String {
+    let replaced: String = x
+        .to_lowercase()
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '-' })
+        .collect();
+    replaced.trim_matches('-').to_owned()
+}
+
+/// Reject names that are not DNS-1123 labels or are reserved
+fn check_name(name: &str) -> Result<(), ApiError> {
+    let valid = !name.is_empty()
+        && name.len() <= NAME_MAX
+        && name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+        && !name.starts_with('-')
+        && !name.ends_with('-');
+    if !valid {
+        return Err(ApiError::new(
+            400,
+            format!(
+                "Namespace {} must be at most {} lower case letters, digits or '-' \
+                 starting and ending with a letter or digit",
+                name, NAME_MAX
+            ),
+        ));
+    }
+
+    let extra = std::env::var("RESERVED_NAMESPACES").unwrap_or_default();
+    let accounts = std::env::var("PEGASUS_ACCOUNT_NS").unwrap_or_default();
+    let mut reserved = RESERVED
+        .iter()
+        .copied()
+        .chain(extra.split(',').map(str::trim))
+        .chain(std::iter::once(accounts.as_str()));
+    if name.starts_with("kube-") || name.starts_with("pegasus-") || reserved.any(|x| x == name) {
+        return Err(ApiError::new(400, format!("Namespace {} is reserved", name)));
+    }
+    Ok(())
 }
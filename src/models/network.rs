@@ -0,0 +1,184 @@
+use chrono::{NaiveDateTime, Utc};
+use diesel::prelude::*;
+use diesel::sql_types::{Int4, Nullable, Timestamp, Uuid as SqlUuid, Varchar};
+use uuid::Uuid;
+
+use super::db;
+use super::namespace::Namespace;
+use super::user::User;
+use crate::errors::ApiError;
+use crate::utils::schema::{namespace_networks, network_rules};
+
+/// Ingress isolation of a namespace, pods of the same namespace always
+/// reach each other. `Department` also admits the namespaces of the
+/// owner's department, `IngressController` the ingress controller.
+#[derive(Clone, Copy, DbEnum, Debug, PartialEq, Serialize, Deserialize)]
+pub enum NetworkProfile {
+    Isolated,
+    Department,
+    IngressController,
+}
+
+impl NetworkProfile {
+    /// Profile of new namespaces from `NETWORK_PROFILE`,
+    /// `IngressController` so ingresses keep working by default
+    pub fn configured() -> NetworkProfile {
+        match std::env::var("NETWORK_PROFILE")
+            .as_ref()
+            .map(|x| x.as_str())
+        {
+            Ok("Isolated") => NetworkProfile::Isolated,
+            Ok("Department") => NetworkProfile::Department,
+            _ => NetworkProfile::IngressController,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Queryable)]
+pub struct NamespaceNetwork {
+    pub namespace_id: i32,
+    pub profile: NetworkProfile,
+    pub updated_at: NaiveDateTime,
+}
+
+#[derive(Debug, Serialize, Queryable)]
+pub struct NetworkRule {
+    pub id: i32,
+    pub from_ns: i32,
+    pub to_ns: i32,
+    pub created_by: Option<Uuid>,
+    pub created_at: NaiveDateTime,
+}
+
+/// `NetworkRule` with the namespace names
+#[derive(Debug, Serialize, QueryableByName)]
+pub struct RuleInfo {
+    #[sql_type = "Int4"]
+    pub id: i32,
+    #[sql_type = "Varchar"]
+    pub from: String,
+    #[sql_type = "Varchar"]
+    pub to: String,
+    #[sql_type = "Nullable<SqlUuid>"]
+    pub created_by: Option<Uuid>,
+    #[sql_type = "Timestamp"]
+    pub created_at: NaiveDateTime,
+}
+
+impl NamespaceNetwork {
+    /// Profile of namespace `namespace_id`, the configured one if never set
+    pub fn profile_of(namespace_id: i32) -> Result<NetworkProfile, ApiError> {
+        let conn = db::connection()?;
+
+        let result = namespace_networks::table
+            .find(namespace_id)
+            .select(namespace_networks::profile)
+            .first(&conn)
+            .optional()?;
+        Ok(result.unwrap_or_else(NetworkProfile::configured))
+    }
+
+    pub fn set(namespace_id: i32, profile: NetworkProfile) -> Result<NamespaceNetwork, ApiError> {
+        let conn = db::connection()?;
+
+        let now = Utc::now().naive_utc();
+        let result = diesel::insert_into(namespace_networks::table)
+            .values(&(
+                namespace_networks::namespace_id.eq(namespace_id),
+                namespace_networks::profile.eq(profile),
+                namespace_networks::updated_at.eq(now),
+            ))
+            .on_conflict(namespace_networks::namespace_id)
+            .do_update()
+            .set((
+                namespace_networks::profile.eq(profile),
+                namespace_networks::updated_at.eq(now),
+            ))
+            .get_result(&conn)?;
+        Ok(result)
+    }
+}
+
+impl NetworkRule {
+    /// Allow `from` to reach `to`, both namespaces belong to the same
+    /// user or to users of the same department
+    pub fn create(from: &Namespace, to: &Namespace, by: &Uuid) -> Result<NetworkRule, ApiError> {
+        if from.id == to.id {
+            return Err(ApiError::new(
+                400,
+                "Pods of a namespace always reach each other".to_owned(),
+            ));
+        }
+        if from.uid != to.uid {
+            let from_depart = User::find(from.uid)?.belong_to;
+            if from_depart.is_none() || from_depart != User::find(to.uid)?.belong_to {
+                return Err(ApiError::new(
+                    403,
+                    format!(
+                        "Namespaces {} and {} are not in the same department",
+                        from.namespace, to.namespace
+                    ),
+                ));
+            }
+        }
+        let conn = db::connection()?;
+
+        let result = diesel::insert_into(network_rules::table)
+            .values(&(
+                network_rules::from_ns.eq(from.id),
+                network_rules::to_ns.eq(to.id),
+                network_rules::created_by.eq(by),
+            ))
+            .get_result(&conn)?;
+        Ok(result)
+    }
+
+    pub fn find(id: i32) -> Result<NetworkRule, ApiError> {
+        let conn = db::connection()?;
+
+        let result = network_rules::table.find(id).first(&conn)?;
+        Ok(result)
+    }
+
+    /// Rules from or to namespace `namespace_id`
+    pub fn list_of(namespace_id: i32) -> Result<Vec<RuleInfo>, ApiError> {
+        let conn = db::connection()?;
+
+        let results = diesel::sql_query(
+            "SELECT r.id, f.namespace AS \"from\", t.namespace AS \"to\", \
+             r.created_by, r.created_at \
+             FROM network_rules r \
+             JOIN namespaces f ON f.id = r.from_ns \
+             JOIN namespaces t ON t.id = r.to_ns \
+             WHERE r.from_ns = $1 OR r.to_ns = $1 \
+             ORDER BY r.id",
+        )
+        .bind::<Int4, _>(namespace_id)
+        .load(&conn)?;
+        Ok(results)
+    }
+
+    pub fn delete(id: i32) -> Result<NetworkRule, ApiError> {
+        let conn = db::connection()?;
+
+        let result = diesel::delete(network_rules::table.find(id)).get_result(&conn)?;
+        Ok(result)
+    }
+
+    /// Delete the rules from or to namespace `namespace_id`
+    /// and return them for the cleanup in the cluster
+    pub fn delete_of(namespace_id: i32) -> Result<Vec<RuleInfo>, ApiError> {
+        let rules = NetworkRule::list_of(namespace_id)?;
+        let conn = db::connection()?;
+
+        diesel::delete(
+            network_rules::table.filter(
+                network_rules::from_ns
+                    .eq(namespace_id)
+                    .or(network_rules::to_ns.eq(namespace_id)),
+            ),
+        )
+        .execute(&conn)?;
+        Ok(rules)
+    }
+}
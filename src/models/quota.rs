@@ -0,0 +1,358 @@
+use chrono::{NaiveDateTime, Utc};
+use diesel::prelude::*;
+
+use std::collections::BTreeMap;
+
+use super::db;
+use super::department::Department;
+use super::user::{ClusterRole, User};
+use crate::errors::ApiError;
+use crate::utils::schema::{namespace_quotas, namespaces, quota_policies};
+
+/// Suffixes accepted in a kubernetes quantity with their value
+/// in thousandths, binary suffixes first as preferred for output
+const QUANTITY_SUFFIXES: [(&str, i128); 14] = [
+    ("Ei", 1000 << 60),
+    ("Pi", 1000 << 50),
+    ("Ti", 1000 << 40),
+    ("Gi", 1000 << 30),
+    ("Mi", 1000 << 20),
+    ("Ki", 1000 << 10),
+    ("E", 1_000_000_000_000_000_000_000),
+    ("P", 1_000_000_000_000_000_000),
+    ("T", 1_000_000_000_000_000),
+    ("G", 1_000_000_000_000),
+    ("M", 1_000_000_000),
+    ("k", 1_000_000),
+    ("", 1000),
+    ("m", 1),
+];
+
+/// Namespaces a user may own when no policy matches
+pub const DEFAULT_NAMESPACES: i32 = 3;
+
+/// Amounts of a `ResourceQuota` and the container defaults of a
+/// `LimitRange`. `cpu` and `memory` bound the sum of container limits,
+/// `storage` the sum of volume claims.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QuotaSpec {
+    pub cpu: String,
+    pub memory: String,
+    pub storage: String,
+    pub pods: i32,
+    pub services: i32,
+    pub default_cpu: String,
+    pub default_memory: String,
+}
+
+/// Applied to namespaces when no policy matches the owner
+impl Default for QuotaSpec {
+    fn default() -> Self {
+        QuotaSpec {
+            cpu: "4".to_owned(),
+            memory: "8Gi".to_owned(),
+            storage: "20Gi".to_owned(),
+            pods: 20,
+            services: 10,
+            default_cpu: "500m".to_owned(),
+            default_memory: "512Mi".to_owned(),
+        }
+    }
+}
+
+/// The quota of new namespaces of a role or of a department,
+/// with the count of namespaces each user may own
+#[derive(Debug, Serialize, Queryable)]
+pub struct QuotaPolicy {
+    pub id: i32,
+    pub role: Option<ClusterRole>,
+    pub department: Option<i32>,
+    pub cpu: String,
+    pub memory: String,
+    pub pods: i32,
+    pub services: i32,
+    pub default_cpu: String,
+    pub default_memory: String,
+    pub storage: String,
+    pub namespaces: i32,
+}
+
+/// Exactly one of `role` and `department` is given
+#[derive(Deserialize)]
+pub struct PolicyInfo {
+    pub role: Option<ClusterRole>,
+    pub department: Option<i32>,
+    pub namespaces: Option<i32>,
+    #[serde(flatten)]
+    pub spec: QuotaSpec,
+}
+
+/// The quota granted to a namespace when it was provisioned
+#[derive(Debug, Serialize, Queryable)]
+pub struct NamespaceQuota {
+    pub namespace_id: i32,
+    pub cpu: String,
+    pub memory: String,
+    pub pods: i32,
+    pub services: i32,
+    pub default_cpu: String,
+    pub default_memory: String,
+    pub updated_at: NaiveDateTime,
+    pub storage: String,
+}
+
+/// Hard limits of the `ResourceQuota` of a namespace against the
+/// observed usage, keyed by resource name such as `limits.cpu`
+#[derive(Serialize)]
+pub struct QuotaStatus {
+    pub hard: BTreeMap<String, String>,
+    pub used: BTreeMap<String, String>,
+}
+
+impl QuotaSpec {
+    pub fn validate(&self) -> Result<(), ApiError> {
+        for x in &[
+            &self.cpu,
+            &self.memory,
+            &self.storage,
+            &self.default_cpu,
+            &self.default_memory,
+        ] {
+            if !is_quantity(x) {
+                return Err(ApiError::new(400, format!("Invalid quantity {}", x)));
+            }
+        }
+        if self.pods < 0 || self.services < 0 {
+            return Err(ApiError::new(400, "Counts must not be negative".to_owned()));
+        }
+        Ok(())
+    }
+}
+
+impl QuotaPolicy {
+    pub fn list() -> Result<Vec<QuotaPolicy>, ApiError> {
+        let conn = db::connection()?;
+
+        let results = quota_policies::table
+            .order(quota_policies::id)
+            .get_results(&conn)?;
+        Ok(results)
+    }
+
+    /// Create or replace the policy of a role or a department
+    pub fn set(info: &PolicyInfo) -> Result<QuotaPolicy, ApiError> {
+        if info.role.is_some() == info.department.is_some() {
+            return Err(ApiError::new(
+                400,
+                "Exactly one of role and department must be specified".to_owned(),
+            ));
+        }
+        info.spec.validate()?;
+        let namespaces = info.namespaces.unwrap_or(DEFAULT_NAMESPACES);
+        if namespaces < 0 {
+            return Err(ApiError::new(400, "Counts must not be negative".to_owned()));
+        }
+        let conn = db::connection()?;
+
+        conn.transaction(|| {
+            if let Some(role) = info.role {
+                diesel::delete(quota_policies::table.filter(quota_policies::role.eq(role)))
+                    .execute(&conn)?;
+            }
+            if let Some(department) = info.department {
+                diesel::delete(
+                    quota_policies::table.filter(quota_policies::department.eq(department)),
+                )
+                .execute(&conn)?;
+            }
+            let spec = &info.spec;
+            let result = diesel::insert_into(quota_policies::table)
+                .values(&(
+                    quota_policies::role.eq(info.role),
+                    quota_policies::department.eq(info.department),
+                    quota_policies::cpu.eq(&spec.cpu),
+                    quota_policies::memory.eq(&spec.memory),
+                    quota_policies::storage.eq(&spec.storage),
+                    quota_policies::namespaces.eq(namespaces),
+                    quota_policies::pods.eq(spec.pods),
+                    quota_policies::services.eq(spec.services),
+                    quota_policies::default_cpu.eq(&spec.default_cpu),
+                    quota_policies::default_memory.eq(&spec.default_memory),
+                ))
+                .get_result(&conn)?;
+            Ok(result)
+        })
+    }
+
+    pub fn delete(id: i32) -> Result<QuotaPolicy, ApiError> {
+        let conn = db::connection()?;
+
+        let result = diesel::delete(quota_policies::table.find(id)).get_result(&conn)?;
+        Ok(result)
+    }
+
+    /// Quota for the namespaces of `user`
+    pub fn resolve(user: &User) -> Result<QuotaSpec, ApiError> {
+        Ok(QuotaPolicy::policy_of(user)?
+            .map(|x| x.spec())
+            .unwrap_or_default())
+    }
+
+    /// Count of namespaces `user` may own before any granted request
+    pub fn namespaces_of(user: &User) -> Result<i32, ApiError> {
+        Ok(QuotaPolicy::policy_of(user)?
+            .map(|x| x.namespaces)
+            .unwrap_or(DEFAULT_NAMESPACES))
+    }
+
+    /// The policy of the nearest department of `user` up the tree,
+    /// then the policy of the role
+    fn policy_of(user: &User) -> Result<Option<QuotaPolicy>, ApiError> {
+        let conn = db::connection()?;
+
+        if let Some(depart) = user.belong_to {
+            let ancestors = Department::ancestors(depart)?;
+            let mut policies: Vec<QuotaPolicy> = quota_policies::table
+                .filter(quota_policies::department.eq_any(&ancestors))
+                .get_results(&conn)?;
+            let nearest = ancestors
+                .iter()
+                .find_map(|id| policies.iter().position(|x| x.department == Some(*id)));
+            if let Some(idx) = nearest {
+                return Ok(Some(policies.swap_remove(idx)));
+            }
+        }
+
+        let policy = quota_policies::table
+            .filter(quota_policies::role.eq(user.role))
+            .first(&conn)
+            .optional()?;
+        Ok(policy)
+    }
+
+    fn spec(&self) -> QuotaSpec {
+        QuotaSpec {
+            cpu: self.cpu.clone(),
+            memory: self.memory.clone(),
+            storage: self.storage.clone(),
+            pods: self.pods,
+            services: self.services,
+            default_cpu: self.default_cpu.clone(),
+            default_memory: self.default_memory.clone(),
+        }
+    }
+}
+
+impl NamespaceQuota {
+    pub fn spec(&self) -> QuotaSpec {
+        QuotaSpec {
+            cpu: self.cpu.clone(),
+            memory: self.memory.clone(),
+            storage: self.storage.clone(),
+            pods: self.pods,
+            services: self.services,
+            default_cpu: self.default_cpu.clone(),
+            default_memory: self.default_memory.clone(),
+        }
+    }
+
+    /// Record the amounts applied to namespace `namespace_id`
+    pub fn grant(namespace_id: i32, spec: &QuotaSpec) -> Result<NamespaceQuota, ApiError> {
+        let conn = db::connection()?;
+
+        let values = (
+            namespace_quotas::cpu.eq(&spec.cpu),
+            namespace_quotas::memory.eq(&spec.memory),
+            namespace_quotas::storage.eq(&spec.storage),
+            namespace_quotas::pods.eq(spec.pods),
+            namespace_quotas::services.eq(spec.services),
+            namespace_quotas::default_cpu.eq(&spec.default_cpu),
+            namespace_quotas::default_memory.eq(&spec.default_memory),
+            namespace_quotas::updated_at.eq(Utc::now().naive_utc()),
+        );
+        let result = diesel::insert_into(namespace_quotas::table)
+            .values(&(namespace_quotas::namespace_id.eq(namespace_id), values))
+            .on_conflict(namespace_quotas::namespace_id)
+            .do_update()
+            .set(values)
+            .get_result(&conn)?;
+        Ok(result)
+    }
+
+    pub fn find(namespace_id: i32) -> Result<Option<NamespaceQuota>, ApiError> {
+        let conn = db::connection()?;
+
+        let result = namespace_quotas::table
+            .find(namespace_id)
+            .first(&conn)
+            .optional()?;
+        Ok(result)
+    }
+
+    /// Put back the grant `previous` replaced, none if it was the first
+    pub fn restore(namespace_id: i32, previous: Option<&NamespaceQuota>) -> Result<(), ApiError> {
+        match previous {
+            Some(previous) => {
+                NamespaceQuota::grant(namespace_id, &previous.spec())?;
+            }
+            None => {
+                let conn = db::connection()?;
+                diesel::delete(namespace_quotas::table.find(namespace_id)).execute(&conn)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Granted quota of a valid namespace, `None` for namespaces
+    /// provisioned before quotas were recorded
+    pub fn find_by_ns(ns: &str) -> Result<Option<NamespaceQuota>, ApiError> {
+        let conn = db::connection()?;
+
+        let result = namespace_quotas::table
+            .inner_join(namespaces::table)
+            .filter(namespaces::namespace.eq(ns))
+            .filter(namespaces::valid.eq(true))
+            .select(namespace_quotas::all_columns)
+            .first(&conn)
+            .optional()?;
+        Ok(result)
+    }
+}
+
+/// Whether `x` is a plain kubernetes quantity such as `500m` or `1.5Gi`
+pub fn is_quantity(x: &str) -> bool {
+    milli_value(x).is_some()
+}
+
+/// Value of quantity `x` in thousandths, so `500m` and `0.5` are both 500
+/// and `1Ki` is 1024000. Digits below a thousandth are truncated.
+pub fn milli_value(x: &str) -> Option<i128> {
+    let end = x
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(x.len());
+    let (number, suffix) = x.split_at(end);
+    let (_, unit) = QUANTITY_SUFFIXES.iter().find(|(s, _)| *s == suffix)?;
+    let (whole, fraction) = match number.split_once('.') {
+        Some((whole, fraction)) => (whole, fraction),
+        None => (number, ""),
+    };
+    if whole.is_empty() && fraction.is_empty() || fraction.contains('.') || fraction.len() > 18 {
+        return None;
+    }
+    let scale = 10_i128.pow(fraction.len() as u32);
+    let digits: i128 = format!("{}{}", whole, fraction).parse().ok()?;
+    digits.checked_mul(*unit).map(|x| x / scale)
+}
+
+/// Shortest exact quantity of `milli` thousandths, the inverse of `milli_value`
+pub fn to_quantity(milli: i128) -> String {
+    let sign = if milli < 0 { "-" } else { "" };
+    let abs = milli.abs();
+    match QUANTITY_SUFFIXES
+        .iter()
+        .find(|(_, unit)| abs != 0 && abs % unit == 0)
+    {
+        Some((suffix, unit)) => format!("{}{}{}", sign, abs / unit, suffix),
+        None => "0".to_owned(),
+    }
+}
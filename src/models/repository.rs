@@ -74,6 +74,16 @@ impl Repository {
         }
     }
 
+    pub fn find_valid(name: &str) -> Result<Repository, ApiError> {
+        let conn = db::connection()?;
+
+        let result = repositories::table
+            .filter(repositories::repo_name.eq(name))
+            .filter(repositories::is_valid.eq(true))
+            .first(&conn)?;
+        Ok(result)
+    }
+
     pub fn delete(name: &str) -> Result<(), ApiError> {
         let conn = db::connection()?;
 
@@ -83,6 +93,16 @@ impl Repository {
         Ok(())
     }
 
+    /// Set all repositories of user `uid` invalid
+    pub fn delete_all_of(uid: &Uuid) -> Result<usize, ApiError> {
+        let conn = db::connection()?;
+
+        let result = diesel::update(repositories::table.filter(repositories::belong_to.eq(uid)))
+            .set(repositories::is_valid.eq(false))
+            .execute(&conn)?;
+        Ok(result)
+    }
+
     pub fn record_state(name: &str) -> Result<RepoRecordState, ApiError> {
         let conn = db::connection()?;
 
@@ -132,6 +152,24 @@ impl Repository {
         Ok(results)
     }
 
+    /// Valid repositories of a user not visible to others
+    pub fn get_private_by_uid(id: &Uuid) -> Result<Vec<String>, ApiError> {
+        let conn = db::connection()?;
+
+        let results: Vec<String> = repositories::table
+            .filter(
+                repositories::belong_to
+                    .eq(id)
+                    .and(repositories::is_valid.eq(true))
+                    .and(repositories::is_public.eq(false)),
+            )
+            .get_results(&conn)?
+            .iter()
+            .map(Repository::repo_name)
+            .collect();
+        Ok(results)
+    }
+
     fn repo_name(&self) -> String {
         self.repo_name.clone()
     }
@@ -0,0 +1,293 @@
+use chrono::{NaiveDateTime, Utc};
+use diesel::pg::Pg;
+use diesel::prelude::*;
+use uuid::Uuid;
+
+use super::db;
+use super::namespace::Namespace;
+use super::quota::{is_quantity, NamespaceQuota, QuotaPolicy, QuotaSpec};
+use super::user::User;
+use crate::errors::ApiError;
+use crate::utils::schema::{resource_requests, users};
+
+/// Most namespaces one request may add
+const MAX_EXTRA_NAMESPACES: i32 = 100;
+
+/// The resource a request asks more of, all but `Namespaces`
+/// raise a hard limit of one namespace
+#[derive(Clone, Copy, DbEnum, Debug, PartialEq, Serialize, Deserialize)]
+pub enum RequestKind {
+    Cpu,
+    Memory,
+    Storage,
+    Namespaces,
+}
+
+/// `DepartmentApproved` requests wait for a `ClusterAdmin` when
+/// `REQUEST_CLUSTER_REVIEW` is set
+#[derive(Clone, Copy, DbEnum, Debug, PartialEq, Serialize, Deserialize)]
+pub enum RequestStatus {
+    Pending,
+    DepartmentApproved,
+    Approved,
+    Rejected,
+}
+
+#[derive(Debug, Serialize, Queryable)]
+pub struct ResourceRequest {
+    pub id: i32,
+    pub uid: Uuid,
+    pub namespace: Option<String>,
+    pub kind: RequestKind,
+    pub amount: String,
+    pub justification: String,
+    pub status: RequestStatus,
+    pub depart_reviewer: Option<Uuid>,
+    pub cluster_reviewer: Option<Uuid>,
+    pub comment: Option<String>,
+    pub created_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+}
+
+#[derive(Deserialize)]
+pub struct RequestInfo {
+    pub namespace: Option<String>,
+    pub kind: RequestKind,
+    pub amount: String,
+    pub justification: String,
+}
+
+#[derive(Default, Deserialize)]
+pub struct RequestQuery {
+    pub uid: Option<Uuid>,
+    pub status: Option<RequestStatus>,
+}
+
+impl ResourceRequest {
+    /// Whether approved requests need a second review by a `ClusterAdmin`
+    pub fn cluster_review_required() -> bool {
+        std::env::var("REQUEST_CLUSTER_REVIEW")
+            .map(|x| x == "true" || x == "1")
+            .unwrap_or(false)
+    }
+
+    pub fn create(uid: &Uuid, info: &RequestInfo) -> Result<ResourceRequest, ApiError> {
+        if info.justification.trim().is_empty() {
+            return Err(ApiError::new(400, "A justification is required".to_owned()));
+        }
+        match (info.kind, info.namespace.as_ref()) {
+            (RequestKind::Namespaces, None) => {
+                if info
+                    .amount
+                    .parse::<i32>()
+                    .map_or(true, |x| x <= 0 || x > MAX_EXTRA_NAMESPACES)
+                {
+                    return Err(ApiError::new(
+                        400,
+                        format!("Invalid namespace count {}", info.amount),
+                    ));
+                }
+            }
+            (RequestKind::Namespaces, Some(_)) => {
+                return Err(ApiError::new(
+                    400,
+                    "Namespace count requests do not target a namespace".to_owned(),
+                ));
+            }
+            (_, Some(ns)) => {
+                if Namespace::owner_of(ns)? != Some(*uid) {
+                    return Err(ApiError::new(
+                        400,
+                        format!("Namespace {} does not belong to the user", ns),
+                    ));
+                }
+                if !is_quantity(&info.amount) {
+                    return Err(ApiError::new(
+                        400,
+                        format!("Invalid quantity {}", info.amount),
+                    ));
+                }
+            }
+            (_, None) => {
+                return Err(ApiError::new(400, "Namespace must be provided".to_owned()));
+            }
+        }
+        let conn = db::connection()?;
+
+        let result = diesel::insert_into(resource_requests::table)
+            .values(&(
+                resource_requests::uid.eq(uid),
+                resource_requests::namespace.eq(&info.namespace),
+                resource_requests::kind.eq(info.kind),
+                resource_requests::amount.eq(&info.amount),
+                resource_requests::justification.eq(&info.justification),
+            ))
+            .get_result(&conn)?;
+        Ok(result)
+    }
+
+    pub fn find(id: i32) -> Result<ResourceRequest, ApiError> {
+        let conn = db::connection()?;
+
+        let result = resource_requests::table.find(id).first(&conn)?;
+        Ok(result)
+    }
+
+    /// Matched requests newest first, `departs` restricts the requesters
+    /// to the users of the departments
+    pub fn list(
+        query: &RequestQuery,
+        departs: Option<&[i32]>,
+    ) -> Result<Vec<ResourceRequest>, ApiError> {
+        let conn = db::connection()?;
+
+        let mut q: resource_requests::BoxedQuery<Pg> = resource_requests::table.into_boxed();
+        if let Some(uid) = query.uid {
+            q = q.filter(resource_requests::uid.eq(uid));
+        }
+        if let Some(status) = query.status {
+            q = q.filter(resource_requests::status.eq(status));
+        }
+        if let Some(departs) = departs {
+            q = q.filter(
+                resource_requests::uid.eq_any(
+                    users::table
+                        .filter(users::belong_to.eq_any(departs.to_vec()))
+                        .select(users::id),
+                ),
+            );
+        }
+        let results = q.order(resource_requests::id.desc()).get_results(&conn)?;
+        Ok(results)
+    }
+
+    /// Status after a review, a `DepartmentAdmin` approval only
+    /// completes the request when no cluster review is required
+    pub fn next_status(
+        &self,
+        approve: bool,
+        by_cluster_admin: bool,
+    ) -> Result<RequestStatus, ApiError> {
+        match self.status {
+            RequestStatus::Pending | RequestStatus::DepartmentApproved if !approve => {
+                if self.status == RequestStatus::DepartmentApproved && !by_cluster_admin {
+                    return Err(ApiError::new(
+                        403,
+                        "The request is waiting for a cluster administrator".to_owned(),
+                    ));
+                }
+                Ok(RequestStatus::Rejected)
+            }
+            RequestStatus::Pending
+                if !by_cluster_admin && ResourceRequest::cluster_review_required() =>
+            {
+                Ok(RequestStatus::DepartmentApproved)
+            }
+            RequestStatus::Pending => Ok(RequestStatus::Approved),
+            RequestStatus::DepartmentApproved if by_cluster_admin => Ok(RequestStatus::Approved),
+            RequestStatus::DepartmentApproved => Err(ApiError::new(
+                403,
+                "The request is waiting for a cluster administrator".to_owned(),
+            )),
+            _ => Err(ApiError::new(
+                400,
+                format!("Request {} is already {:?}", self.id, self.status),
+            )),
+        }
+    }
+
+    /// Move the request to `status` if no one else reviewed it meanwhile
+    pub fn transition(
+        &self,
+        status: RequestStatus,
+        reviewer: &Uuid,
+        by_cluster_admin: bool,
+        comment: Option<String>,
+    ) -> Result<ResourceRequest, ApiError> {
+        let conn = db::connection()?;
+
+        let (depart_reviewer, cluster_reviewer) = if by_cluster_admin {
+            (self.depart_reviewer, Some(*reviewer))
+        } else {
+            (Some(*reviewer), self.cluster_reviewer)
+        };
+        let result = diesel::update(
+            resource_requests::table
+                .find(self.id)
+                .filter(resource_requests::status.eq(self.status)),
+        )
+        .set((
+            resource_requests::status.eq(status),
+            resource_requests::depart_reviewer.eq(depart_reviewer),
+            resource_requests::cluster_reviewer.eq(cluster_reviewer),
+            resource_requests::comment.eq(comment.or_else(|| self.comment.clone())),
+            resource_requests::updated_at.eq(Utc::now().naive_utc()),
+        ))
+        .get_result(&conn)
+        .optional()?;
+        result.ok_or_else(|| {
+            ApiError::new(
+                409,
+                format!("Request {} was reviewed concurrently", self.id),
+            )
+        })
+    }
+
+    /// Undo `transition` to `self` when applying an approval failed,
+    /// only while the request is still in the status of `claimed`
+    pub fn restore(&self, claimed: &ResourceRequest) -> Result<ResourceRequest, ApiError> {
+        let conn = db::connection()?;
+
+        let result = diesel::update(
+            resource_requests::table
+                .find(self.id)
+                .filter(resource_requests::status.eq(claimed.status)),
+        )
+        .set((
+            resource_requests::status.eq(self.status),
+            resource_requests::depart_reviewer.eq(self.depart_reviewer),
+            resource_requests::cluster_reviewer.eq(self.cluster_reviewer),
+            resource_requests::comment.eq(&self.comment),
+            resource_requests::updated_at.eq(Utc::now().naive_utc()),
+        ))
+        .get_result(&conn)?;
+        Ok(result)
+    }
+
+    /// The namespace with its quota raised by the request,
+    /// `None` for namespace count requests
+    pub fn granted_spec(&self) -> Result<Option<(Namespace, QuotaSpec)>, ApiError> {
+        let ns = match self.namespace.as_ref() {
+            Some(ns) => Namespace::find_valid(ns)?,
+            None => return Ok(None),
+        };
+        let mut spec = match NamespaceQuota::find_by_ns(&ns.namespace)? {
+            Some(granted) => granted.spec(),
+            None => QuotaPolicy::resolve(&User::find(self.uid)?)?,
+        };
+        match self.kind {
+            RequestKind::Cpu => spec.cpu = self.amount.clone(),
+            RequestKind::Memory => spec.memory = self.amount.clone(),
+            RequestKind::Storage => spec.storage = self.amount.clone(),
+            RequestKind::Namespaces => (),
+        }
+        Ok(Some((ns, spec)))
+    }
+
+    /// Namespaces `user` may own: the policy count plus approved requests
+    pub fn namespace_limit(user: &User) -> Result<i32, ApiError> {
+        let conn = db::connection()?;
+
+        let extra: Vec<String> = resource_requests::table
+            .filter(resource_requests::uid.eq(user.id))
+            .filter(resource_requests::kind.eq(RequestKind::Namespaces))
+            .filter(resource_requests::status.eq(RequestStatus::Approved))
+            .select(resource_requests::amount)
+            .get_results(&conn)?;
+        let extra = extra
+            .iter()
+            .filter_map(|x| x.parse::<i32>().ok())
+            .fold(0i32, |sum, x| sum.saturating_add(x.max(0)));
+        Ok(QuotaPolicy::namespaces_of(user)?.saturating_add(extra))
+    }
+}
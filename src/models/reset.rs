@@ -0,0 +1,97 @@
+use chrono::{NaiveDateTime, Utc};
+use diesel::prelude::*;
+use uuid::Uuid;
+
+use super::db;
+use crate::errors::ApiError;
+use crate::utils::pwd;
+use crate::utils::schema::{password_resets, users};
+
+/// Single-use password reset token mailed to the user,
+/// expires after one hour
+#[derive(Debug, Serialize, Deserialize, Queryable, Insertable)]
+#[table_name = "password_resets"]
+pub struct PasswordReset {
+    pub id: Uuid,
+    pub email: String,
+    pub used: bool,
+    pub expires_at: NaiveDateTime,
+    pub created_at: NaiveDateTime,
+}
+
+impl PasswordReset {
+    pub fn create(email: &str) -> Result<PasswordReset, ApiError> {
+        let conn = db::connection()?;
+
+        let now = Utc::now().naive_utc();
+        let info = PasswordReset {
+            id: Uuid::new_v4(),
+            email: email.to_owned(),
+            used: false,
+            expires_at: now + chrono::Duration::hours(1),
+            created_at: now,
+        };
+        let inserted = diesel::insert_into(password_resets::table)
+            .values(&info)
+            .get_result(&conn)?;
+
+        Ok(inserted)
+    }
+
+    /// Count records within 24 hours
+    pub fn count_one_day(eml: &str) -> Result<i64, ApiError> {
+        let conn = db::connection()?;
+
+        let to = Utc::now().naive_utc();
+        let from = to - chrono::Duration::hours(24);
+        let results: i64 = password_resets::table
+            .filter(password_resets::email.eq(eml))
+            .filter(password_resets::created_at.ge(from))
+            .filter(password_resets::created_at.le(to))
+            .count()
+            .get_result(&conn)?;
+        Ok(results)
+    }
+
+    /// Consume the token and set the new password of its user in one
+    /// transaction, returns the user email
+    pub fn consume(id: &Uuid, password: &str) -> Result<String, ApiError> {
+        let conn = db::connection()?;
+
+        let hash = pwd::hash(password)?;
+        conn.transaction(|| {
+            let now = Utc::now().naive_utc();
+            let info: PasswordReset = password_resets::table
+                .filter(password_resets::id.eq(id))
+                .for_update()
+                .first(&conn)?;
+            if info.used || info.expires_at < now {
+                return Err(ApiError::new(
+                    400,
+                    "The reset link is expired or used already".to_owned(),
+                ));
+            }
+
+            diesel::update(password_resets::table.filter(password_resets::id.eq(id)))
+                .set(password_resets::used.eq(true))
+                .execute(&conn)?;
+            diesel::update(users::table.filter(users::email.eq(&info.email)))
+                .set((users::password.eq(hash), users::updated_at.eq(now)))
+                .execute(&conn)?;
+            Ok(info.email)
+        })
+    }
+}
+
+/// Json parse data to request a password reset
+#[derive(Deserialize)]
+pub struct ResetRequest {
+    pub email: String,
+}
+
+/// Json parse data to reset a password with the mailed token
+#[derive(Deserialize)]
+pub struct ResetInfo {
+    pub id: Uuid,
+    pub password: String,
+}
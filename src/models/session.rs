@@ -0,0 +1,39 @@
+use chrono::NaiveDateTime;
+use std::collections::HashMap;
+use std::str::FromStr;
+use uuid::Uuid;
+
+/// Metadata of a signed in browser session, kept in redis beside the
+/// session state. `current` marks the session of the request.
+#[derive(Debug, Serialize)]
+pub struct SessionInfo {
+    pub id: String,
+    pub uid: Uuid,
+    pub ip: String,
+    pub user_agent: String,
+    pub created_at: NaiveDateTime,
+    pub last_seen: NaiveDateTime,
+    pub current: bool,
+}
+
+impl SessionInfo {
+    /// Parse the fields of the redis hash, `None` if any is missing
+    pub fn from_fields(id: &str, fields: &HashMap<String, String>) -> Option<SessionInfo> {
+        let timestamp = |key: &str| {
+            fields
+                .get(key)
+                .and_then(|x| x.parse().ok())
+                .map(|x| NaiveDateTime::from_timestamp(x, 0))
+        };
+
+        Some(SessionInfo {
+            id: id.to_owned(),
+            uid: fields.get("uid").and_then(|x| Uuid::from_str(x).ok())?,
+            ip: fields.get("ip").cloned().unwrap_or_default(),
+            user_agent: fields.get("user_agent").cloned().unwrap_or_default(),
+            created_at: timestamp("created_at")?,
+            last_seen: timestamp("last_seen")?,
+            current: false,
+        })
+    }
+}
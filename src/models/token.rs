@@ -0,0 +1,146 @@
+use actix_web::http::Method;
+use chrono::{NaiveDateTime, Utc};
+use diesel::prelude::*;
+use rand::Rng;
+use std::str::FromStr;
+use uuid::Uuid;
+
+use super::db;
+use crate::errors::ApiError;
+use crate::utils::pwd;
+use crate::utils::schema::api_tokens;
+
+/// Scope restriction of a personal api token
+/// `Full` acts as the user self, `ReadOnly` only allows `GET` requests,
+/// `Tasks` and `Repos` restrict the token to the related api scopes
+#[derive(Clone, Copy, DbEnum, Debug, PartialEq, Serialize, Deserialize)]
+pub enum TokenScope {
+    Full,
+    ReadOnly,
+    Tasks,
+    Repos,
+}
+
+const TASKS_PATHS: [&str; 4] = ["/api/tasks", "/api/ns", "/api/ing", "/api/network"];
+const REPOS_PATHS: [&str; 1] = ["/api/repos"];
+
+impl TokenScope {
+    pub fn allows(self, method: &Method, path: &str) -> bool {
+        match self {
+            TokenScope::Full => true,
+            TokenScope::ReadOnly => method == Method::GET,
+            TokenScope::Tasks => TASKS_PATHS.iter().any(|p| path.starts_with(p)),
+            TokenScope::Repos => REPOS_PATHS.iter().any(|p| path.starts_with(p)),
+        }
+    }
+}
+
+/// Named bearer token for non-browser clients, the secret part is
+/// hashed with argon2 as user passwords
+#[derive(Debug, Serialize, Deserialize, Queryable, Insertable)]
+#[table_name = "api_tokens"]
+pub struct ApiToken {
+    pub id: Uuid,
+    pub uid: Uuid,
+    pub name: String,
+    #[serde(skip_serializing)]
+    pub token_hash: String,
+    pub scope: TokenScope,
+    pub expires_at: Option<NaiveDateTime>,
+    pub last_used_at: Option<NaiveDateTime>,
+    pub is_valid: bool,
+    pub created_at: NaiveDateTime,
+}
+
+/// Json parse data to create `ApiToken`, `expires_in` in days
+#[derive(Deserialize)]
+pub struct TokenData {
+    pub name: String,
+    pub scope: Option<TokenScope>,
+    pub expires_in: Option<i64>,
+}
+
+impl ApiToken {
+    /// Create a token for user `uid`, returns the record and the plain
+    /// token `<id>.<secret>` which is only shown once
+    pub fn create(uid: &Uuid, data: &TokenData) -> Result<(ApiToken, String), ApiError> {
+        let conn = db::connection()?;
+
+        let secret: [u8; 32] = rand::thread_rng().gen();
+        let secret = hex::encode(secret);
+        let now = Utc::now().naive_utc();
+        let token = ApiToken {
+            id: Uuid::new_v4(),
+            uid: *uid,
+            name: data.name.clone(),
+            token_hash: pwd::hash(&secret)?,
+            scope: data.scope.unwrap_or(TokenScope::Full),
+            expires_at: data.expires_in.map(|d| now + chrono::Duration::days(d)),
+            last_used_at: None,
+            is_valid: true,
+            created_at: now,
+        };
+        let plain = format!("{}.{}", token.id, secret);
+
+        let token = diesel::insert_into(api_tokens::table)
+            .values(&token)
+            .get_result(&conn)?;
+        Ok((token, plain))
+    }
+
+    pub fn list_of(uid: &Uuid) -> Result<Vec<ApiToken>, ApiError> {
+        let conn = db::connection()?;
+
+        let results = api_tokens::table
+            .filter(api_tokens::uid.eq(uid))
+            .filter(api_tokens::is_valid.eq(true))
+            .order(api_tokens::created_at.desc())
+            .get_results(&conn)?;
+        Ok(results)
+    }
+
+    pub fn revoke(uid: &Uuid, id: &Uuid) -> Result<ApiToken, ApiError> {
+        let conn = db::connection()?;
+
+        let result = diesel::update(
+            api_tokens::table.filter(api_tokens::id.eq(id).and(api_tokens::uid.eq(uid))),
+        )
+        .set(api_tokens::is_valid.eq(false))
+        .get_result(&conn)?;
+        Ok(result)
+    }
+
+    /// Look up a plain token, returns the valid and unexpired record
+    /// which secret matches
+    pub fn authenticate(plain: &str) -> Result<Option<ApiToken>, ApiError> {
+        let mut parts = plain.splitn(2, '.');
+        let (id, secret) = match (parts.next(), parts.next()) {
+            (Some(id), Some(secret)) => (id, secret),
+            _ => return Ok(None),
+        };
+        let id = match Uuid::from_str(id) {
+            Ok(id) => id,
+            Err(_) => return Ok(None),
+        };
+
+        let conn = db::connection()?;
+        let token: Option<ApiToken> = api_tokens::table
+            .filter(api_tokens::id.eq(id))
+            .filter(api_tokens::is_valid.eq(true))
+            .first(&conn)
+            .optional()?;
+        let token = match token {
+            Some(token) => token,
+            None => return Ok(None),
+        };
+
+        let now = Utc::now().naive_utc();
+        if token.expires_at.filter(|t| *t < now).is_some() || !pwd::verify(&token.token_hash, secret)? {
+            return Ok(None);
+        }
+        diesel::update(api_tokens::table.filter(api_tokens::id.eq(id)))
+            .set(api_tokens::last_used_at.eq(now))
+            .execute(&conn)?;
+        Ok(Some(token))
+    }
+}
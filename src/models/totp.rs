@@ -0,0 +1,189 @@
+use chrono::{NaiveDateTime, Utc};
+use diesel::prelude::*;
+use rand::Rng;
+use uuid::Uuid;
+
+use super::db;
+use super::user::ClusterRole;
+use crate::errors::ApiError;
+use crate::utils::schema::{recovery_codes, totp_credentials};
+use crate::utils::{pwd, totp};
+
+/// Number of single-use recovery codes issued on enabling
+const RECOVERY_CODES: usize = 10;
+
+/// TOTP secret of a user, only `enabled` credentials are challenged
+/// on sign in. `last_step` is the latest accepted time step so
+/// a code can not be replayed.
+#[derive(Debug, Serialize, Deserialize, Queryable, Insertable)]
+#[table_name = "totp_credentials"]
+pub struct TotpCredential {
+    pub uid: Uuid,
+    #[serde(skip_serializing)]
+    pub secret: String,
+    pub enabled: bool,
+    pub last_step: i64,
+    pub created_at: NaiveDateTime,
+}
+
+/// Json parse data of a second step code
+#[derive(Deserialize)]
+pub struct TotpCode {
+    pub code: String,
+}
+
+impl TotpCredential {
+    pub fn find(uid: &Uuid) -> Result<Option<TotpCredential>, ApiError> {
+        let conn = db::connection()?;
+
+        let result = totp_credentials::table
+            .filter(totp_credentials::uid.eq(uid))
+            .first(&conn)
+            .optional()?;
+        Ok(result)
+    }
+
+    /// Roles listed in `TOTP_REQUIRED_ROLES` must enroll before signing in,
+    /// e.g. `ClusterAdmin,DepartmentAdmin`
+    pub fn is_required(role: ClusterRole) -> bool {
+        std::env::var("TOTP_REQUIRED_ROLES")
+            .map(|roles| roles.split(',').any(|r| r.trim() == format!("{:?}", role)))
+            .unwrap_or(false)
+    }
+
+    /// Generate a new pending secret for the user, replaces any
+    /// unconfirmed one
+    pub fn enroll(uid: &Uuid) -> Result<TotpCredential, ApiError> {
+        let conn = db::connection()?;
+
+        conn.transaction(|| {
+            let existing: Option<TotpCredential> = totp_credentials::table
+                .filter(totp_credentials::uid.eq(uid))
+                .for_update()
+                .first(&conn)
+                .optional()?;
+            if existing.filter(|x| x.enabled).is_some() {
+                return Err(ApiError::new(
+                    409,
+                    "Two-factor authentication is enabled already".to_owned(),
+                ));
+            }
+
+            let cred = TotpCredential {
+                uid: *uid,
+                secret: totp::generate_secret(),
+                enabled: false,
+                last_step: 0,
+                created_at: Utc::now().naive_utc(),
+            };
+            let result = diesel::insert_into(totp_credentials::table)
+                .values(&cred)
+                .on_conflict(totp_credentials::uid)
+                .do_update()
+                .set((
+                    totp_credentials::secret.eq(&cred.secret),
+                    totp_credentials::created_at.eq(cred.created_at),
+                ))
+                .get_result(&conn)?;
+            Ok(result)
+        })
+    }
+
+    /// Enable the pending credential with the first valid code,
+    /// returns the plain recovery codes which are only shown once
+    pub fn enable(&self, code: &str) -> Result<Option<Vec<String>>, ApiError> {
+        let conn = db::connection()?;
+
+        let step = match totp::verify(&self.secret, code, self.last_step) {
+            Some(step) => step,
+            None => return Ok(None),
+        };
+        let codes: Vec<String> = (0..RECOVERY_CODES)
+            .map(|_| {
+                let code: [u8; 5] = rand::thread_rng().gen();
+                let code = hex::encode(code);
+                format!("{}-{}", &code[..5], &code[5..])
+            })
+            .collect();
+        let rows = codes
+            .iter()
+            .map(|c| {
+                Ok((
+                    recovery_codes::uid.eq(self.uid),
+                    recovery_codes::code_hash.eq(pwd::hash(c)?),
+                ))
+            })
+            .collect::<Result<Vec<_>, ApiError>>()?;
+
+        conn.transaction(|| {
+            diesel::update(totp_credentials::table.filter(totp_credentials::uid.eq(self.uid)))
+                .set((
+                    totp_credentials::enabled.eq(true),
+                    totp_credentials::last_step.eq(step),
+                ))
+                .execute(&conn)?;
+            diesel::delete(recovery_codes::table.filter(recovery_codes::uid.eq(self.uid)))
+                .execute(&conn)?;
+            diesel::insert_into(recovery_codes::table)
+                .values(&rows)
+                .execute(&conn)?;
+            Ok(Some(codes))
+        })
+    }
+
+    /// Check a code of the authenticator app, the accepted step is
+    /// recorded so concurrent requests can not reuse it
+    pub fn verify(&self, code: &str) -> Result<bool, ApiError> {
+        let conn = db::connection()?;
+
+        let step = match totp::verify(&self.secret, code, self.last_step) {
+            Some(step) => step,
+            None => return Ok(false),
+        };
+        let updated = diesel::update(
+            totp_credentials::table
+                .filter(totp_credentials::uid.eq(self.uid))
+                .filter(totp_credentials::last_step.lt(step)),
+        )
+        .set(totp_credentials::last_step.eq(step))
+        .execute(&conn)?;
+        Ok(updated == 1)
+    }
+
+    /// Consume one of the unused recovery codes
+    pub fn use_recovery(&self, code: &str) -> Result<bool, ApiError> {
+        let conn = db::connection()?;
+
+        let candidates: Vec<(i32, String)> = recovery_codes::table
+            .filter(recovery_codes::uid.eq(self.uid))
+            .filter(recovery_codes::used.eq(false))
+            .select((recovery_codes::id, recovery_codes::code_hash))
+            .get_results(&conn)?;
+        for (id, hash) in candidates.iter() {
+            if pwd::verify(hash, code.trim())? {
+                let updated = diesel::update(
+                    recovery_codes::table
+                        .filter(recovery_codes::id.eq(id))
+                        .filter(recovery_codes::used.eq(false)),
+                )
+                .set(recovery_codes::used.eq(true))
+                .execute(&conn)?;
+                return Ok(updated == 1);
+            }
+        }
+        Ok(false)
+    }
+
+    /// Remove the credential with its recovery codes
+    pub fn disable(uid: &Uuid) -> Result<(), ApiError> {
+        let conn = db::connection()?;
+
+        conn.transaction(|| {
+            diesel::delete(recovery_codes::table.filter(recovery_codes::uid.eq(uid)))
+                .execute(&conn)?;
+            diesel::delete(totp_credentials::table.filter(totp_credentials::uid.eq(uid)))
+                .execute(&conn)?;
+            Ok(())
+        })
+    }
+}
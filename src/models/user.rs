@@ -1,4 +1,3 @@
-use argon2::Config;
 use chrono::{NaiveDateTime, Utc};
 use diesel::dsl::{exists, select};
 use diesel::prelude::*;
@@ -6,20 +5,31 @@ use rand::Rng;
 use uuid::Uuid;
 
 use super::db;
+use super::invitation::{Invitation, InvitationStatus};
 use crate::errors::ApiError;
-use crate::utils::schema::users;
+use crate::utils::pwd;
+use crate::utils::schema::{departments, invitations, users};
 
 /// User roles to use k8s `RBAC`, includes 3 level
 /// `ClusterAdmin` control all the resources of the cluster
 /// `DepartmentAdmin` control all the lessees in the department
 /// Every user has one spefic role.
-#[derive(Clone, DbEnum, Debug, Serialize, Deserialize)]
+#[derive(Clone, Copy, DbEnum, Debug, PartialEq, Serialize, Deserialize)]
 pub enum ClusterRole {
     ClusterAdmin,
     DepartmentAdmin,
     Lessee,
 }
 
+/// Where the user identity comes from, only `Local` users
+/// hold a usable password in `users`
+#[derive(Clone, Copy, DbEnum, Debug, PartialEq, Serialize, Deserialize)]
+pub enum AuthProvider {
+    Local,
+    Ldap,
+    Oidc,
+}
+
 /// General user model
 #[derive(Debug, Serialize, Deserialize, Queryable, Insertable)]
 #[table_name = "users"]
@@ -33,6 +43,7 @@ pub struct User {
     pub belong_to: Option<i32>,
     pub created_at: NaiveDateTime,
     pub updated_at: Option<NaiveDateTime>,
+    pub provider: AuthProvider,
 }
 
 /// Json parse data of `User`
@@ -47,12 +58,36 @@ pub struct UserInfo {
     pub belong_to: Option<i32>,
 }
 
+/// Changeset of the editable user fields, `None` fields keep unchanged
+#[derive(Default, Deserialize, AsChangeset)]
+#[table_name = "users"]
+pub struct UserUpdate {
+    pub name: Option<String>,
+    pub belong_to: Option<i32>,
+    pub role: Option<ClusterRole>,
+}
+
+/// Json parse data to register with an invitation, the email,
+/// department and role come from the invitation
+#[derive(Deserialize)]
+pub struct RegisterInfo {
+    pub invitation: Uuid,
+    pub name: String,
+    pub password: String,
+}
+
 #[derive(Clone, Serialize, Deserialize)]
 pub struct LoginInfo {
     pub email: String,
     pub password: String,
 }
 
+#[derive(Deserialize)]
+pub struct PasswordInfo {
+    pub old_password: String,
+    pub new_password: String,
+}
+
 impl User {
     pub fn find(id: Uuid) -> Result<Self, ApiError> {
         let conn = db::connection()?;
@@ -84,6 +119,38 @@ impl User {
         Ok(results)
     }
 
+    /// The `DepartmentAdmin`s of department `depart`
+    pub fn admins_of(depart: i32) -> Result<Vec<User>, ApiError> {
+        let conn = db::connection()?;
+
+        let results = users::table
+            .filter(users::belong_to.eq(depart))
+            .filter(users::role.eq(ClusterRole::DepartmentAdmin))
+            .get_results(&conn)?;
+        Ok(results)
+    }
+
+    /// Emails of the administrators reviewing the requests of users in
+    /// department `depart`, the cluster administrators if it has none
+    pub fn reviewer_emails(depart: Option<i32>) -> Result<Vec<String>, ApiError> {
+        if let Some(depart) = depart {
+            let results: Vec<String> = User::admins_of(depart)?
+                .into_iter()
+                .map(|x| x.email)
+                .collect();
+            if !results.is_empty() {
+                return Ok(results);
+            }
+        }
+        let conn = db::connection()?;
+
+        let results = users::table
+            .filter(users::role.eq(ClusterRole::ClusterAdmin))
+            .select(users::email)
+            .get_results(&conn)?;
+        Ok(results)
+    }
+
     pub fn find_users_all() -> Result<Vec<UserInfo>, ApiError> {
         let conn = db::connection()?;
 
@@ -122,18 +189,172 @@ impl User {
         Ok(user)
     }
 
-    pub fn update(info: UserInfo) -> Result<Self, ApiError> {
+    /// Create the user of a pending invitation and consume the invitation
+    /// in one transaction, other open invitations of the email are revoked.
+    /// `is_admin` invitations of a department create its `DepartmentAdmin`.
+    pub fn register(info: &RegisterInfo) -> Result<Self, ApiError> {
         let conn = db::connection()?;
-        if let Some(id) = info.id {
-            let user = diesel::update(users::table)
-                .filter(users::id.eq(id))
-                .set(info)
+
+        let password = pwd::hash(&info.password)?;
+        conn.transaction(|| {
+            let now = Utc::now().naive_utc();
+            let invit: Invitation = invitations::table
+                .filter(invitations::id.eq(info.invitation))
+                .for_update()
+                .first(&conn)
+                .optional()?
+                .ok_or_else(|| ApiError::new(404, "Invitation not found".to_owned()))?;
+            if invit.status() != InvitationStatus::Pending {
+                return Err(ApiError::new(
+                    400,
+                    "The invitation is expired, revoked or used already".to_owned(),
+                ));
+            }
+            let exists: bool = select(exists(users::table.filter(users::email.eq(&invit.email))))
+                .get_result(&conn)?;
+            if exists {
+                return Err(ApiError::new(
+                    409,
+                    format!("User with {} exists already!", invit.email),
+                ));
+            }
+
+            let role = match invit.department {
+                Some(_) if invit.is_admin => ClusterRole::DepartmentAdmin,
+                _ => ClusterRole::Lessee,
+            };
+            let user: User = diesel::insert_into(users::table)
+                .values(User {
+                    id: Uuid::new_v4(),
+                    email: invit.email.clone(),
+                    name: info.name.clone(),
+                    password,
+                    role,
+                    belong_to: invit.department,
+                    created_at: now,
+                    updated_at: None,
+                    provider: AuthProvider::Local,
+                })
                 .get_result(&conn)?;
 
+            diesel::update(invitations::table.filter(invitations::id.eq(invit.id)))
+                .set((
+                    invitations::accepted_at.eq(now),
+                    invitations::expires_at.eq(now),
+                ))
+                .execute(&conn)?;
+            diesel::update(
+                invitations::table
+                    .filter(invitations::email.eq(&invit.email))
+                    .filter(invitations::accepted_at.is_null())
+                    .filter(invitations::revoked_at.is_null()),
+            )
+            .set(invitations::revoked_at.eq(now))
+            .execute(&conn)?;
+            if let (ClusterRole::DepartmentAdmin, Some(depart)) = (role, invit.department) {
+                diesel::update(departments::table.filter(departments::id.eq(depart)))
+                    .set(departments::admin.eq(user.id))
+                    .execute(&conn)?;
+            }
             Ok(user)
-        } else {
-            Err(ApiError::new(400, "User id must provide".to_owned()))
-        }
+        })
+    }
+
+    pub fn exist_admin() -> Result<bool, ApiError> {
+        let conn = db::connection()?;
+
+        let res = select(exists(
+            users::table.filter(users::role.eq(ClusterRole::ClusterAdmin)),
+        ))
+        .get_result(&conn)?;
+        Ok(res)
+    }
+
+    /// Create the first `ClusterAdmin` and the organisation departments,
+    /// refused once any `ClusterAdmin` exists
+    pub fn bootstrap(
+        email: &str,
+        name: &str,
+        password: &str,
+        departments: &[String],
+    ) -> Result<Self, ApiError> {
+        let conn = db::connection()?;
+
+        let password = pwd::hash(password)?;
+        conn.transaction(|| {
+            // Serialize concurrent bootstraps
+            diesel::sql_query("LOCK TABLE users IN SHARE ROW EXCLUSIVE MODE").execute(&conn)?;
+            let exists: bool = select(exists(
+                users::table.filter(users::role.eq(ClusterRole::ClusterAdmin)),
+            ))
+            .get_result(&conn)?;
+            if exists {
+                return Err(ApiError::new(
+                    409,
+                    "A cluster administrator exists already".to_owned(),
+                ));
+            }
+
+            for depart in departments.iter() {
+                diesel::insert_into(departments::table)
+                    .values(departments::name.eq(depart))
+                    .on_conflict(departments::name)
+                    .do_nothing()
+                    .execute(&conn)?;
+            }
+            let user = diesel::insert_into(users::table)
+                .values(User {
+                    id: Uuid::new_v4(),
+                    email: email.to_owned(),
+                    name: name.to_owned(),
+                    password,
+                    role: ClusterRole::ClusterAdmin,
+                    belong_to: None,
+                    created_at: Utc::now().naive_utc(),
+                    updated_at: None,
+                    provider: AuthProvider::Local,
+                })
+                .get_result(&conn)?;
+            Ok(user)
+        })
+    }
+
+    /// Create the user signed in through a directory provider
+    /// with an unusable random password
+    pub fn provision(
+        email: &str,
+        name: &str,
+        belong_to: Option<i32>,
+        provider: AuthProvider,
+    ) -> Result<Self, ApiError> {
+        let conn = db::connection()?;
+
+        let secret: [u8; 32] = rand::thread_rng().gen();
+        let user = User {
+            id: Uuid::new_v4(),
+            email: email.to_owned(),
+            name: name.chars().take(20).collect(),
+            password: pwd::hash(&hex::encode(secret))?,
+            role: ClusterRole::Lessee,
+            belong_to,
+            created_at: Utc::now().naive_utc(),
+            updated_at: None,
+            provider,
+        };
+        let user = diesel::insert_into(users::table)
+            .values(user)
+            .get_result(&conn)?;
+        Ok(user)
+    }
+
+    pub fn update(id: &Uuid, changes: UserUpdate) -> Result<Self, ApiError> {
+        let conn = db::connection()?;
+
+        let user = diesel::update(users::table)
+            .filter(users::id.eq(id))
+            .set((changes, users::updated_at.eq(Utc::now().naive_utc())))
+            .get_result(&conn)?;
+        Ok(user)
     }
 
     pub fn delete(id: Uuid) -> Result<usize, ApiError> {
@@ -144,20 +365,26 @@ impl User {
         Ok(res)
     }
 
-    /// hash password
-    fn hash_password(&mut self) -> Result<(), ApiError> {
-        let salt: [u8; 32] = rand::thread_rng().gen();
-        let config = Config::default();
+    pub fn set_password(id: &Uuid, password: &str) -> Result<(), ApiError> {
+        let conn = db::connection()?;
 
-        self.password = argon2::hash_encoded(self.password.as_bytes(), &salt, &config)
-            .map_err(|err| ApiError::new(500, format!("Failed to hash password: {}", err)))?;
+        diesel::update(users::table.filter(users::id.eq(id)))
+            .set((
+                users::password.eq(pwd::hash(password)?),
+                users::updated_at.eq(Utc::now().naive_utc()),
+            ))
+            .execute(&conn)?;
+        Ok(())
+    }
 
+    /// hash password
+    fn hash_password(&mut self) -> Result<(), ApiError> {
+        self.password = pwd::hash(&self.password)?;
         Ok(())
     }
 
     pub fn verify_password(&self, password: &str) -> Result<bool, ApiError> {
-        argon2::verify_encoded(&self.password, password.as_bytes())
-            .map_err(|e| ApiError::new(500, format!("Failed to verfify password: {}", e)))
+        pwd::verify(&self.password, password)
     }
 }
 
@@ -175,6 +402,7 @@ impl From<UserInfo> for User {
             belong_to: info.belong_to,
             created_at: Utc::now().naive_utc(),
             updated_at: None,
+            provider: AuthProvider::Local,
         }
     }
 }
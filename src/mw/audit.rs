@@ -0,0 +1,193 @@
+use actix_http::error::PayloadError;
+use actix_http::h1;
+use actix_service::{Service, Transform};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::{header, Method};
+use actix_web::web::{Bytes, BytesMut};
+use actix_web::{Error, HttpMessage};
+use futures::future::{ok, FutureExt, LocalBoxFuture, Ready};
+use futures::StreamExt;
+use serde_json::Value;
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::task::{Context, Poll};
+
+use super::auth::Identity;
+use crate::models::audit::{AuditEvent, NewAuditEvent};
+use crate::utils::client_ip;
+
+/// Largest json body buffered for the summary
+const BODY_LIMIT: usize = 262_144;
+/// Longest payload summary kept in `audit_events`
+const PAYLOAD_MAX: usize = 1024;
+/// Fields replaced by `***` in the payload summary
+const SECRET_FIELDS: [&str; 4] = ["password", "secret", "token", "code"];
+/// Paths whose whole payload is a secret
+const SECRET_PATHS: [&str; 1] = ["/api/users/reset"];
+/// Fields naming the resource a request operates on, by priority
+const TARGET_FIELDS: [&str; 6] = ["ns", "namespace", "name", "email", "id", "uid"];
+
+/// Record every mutating api request to `audit_events` after it is
+/// handled. The actor is the `Identity` extracted by the handler, so
+/// requests rejected before extraction are recorded as anonymous and
+/// denied ones are left to `Identity`, which records them with their
+/// target. Json bodies are buffered to keep a redacted summary.
+pub struct Audit;
+
+impl<S, B> Transform<S> for Audit
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = AuditMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(AuditMiddleware {
+            service: Rc::new(RefCell::new(service)),
+        })
+    }
+}
+
+pub struct AuditMiddleware<S> {
+    service: Rc<RefCell<S>>,
+}
+
+impl<S, B> Service for AuditMiddleware<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        self.service.borrow_mut().poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: ServiceRequest) -> Self::Future {
+        let service = self.service.clone();
+        let audited = is_mutating(req.method()) && req.path().starts_with("/api/");
+        if !audited {
+            return service.borrow_mut().call(req).boxed_local();
+        }
+
+        async move {
+            let body = if req.content_type() == "application/json" {
+                let mut payload = req.take_payload();
+                let mut body = BytesMut::new();
+                while let Some(chunk) = payload.next().await {
+                    body.extend_from_slice(&chunk?);
+                    if body.len() > BODY_LIMIT {
+                        return Err(PayloadError::Overflow.into());
+                    }
+                }
+                let body = body.freeze();
+                let (_, mut replay) = h1::Payload::create(true);
+                replay.unread_data(body.clone());
+                req.set_payload(replay.into());
+                Some(body)
+            } else {
+                None
+            };
+            let method = req.method().to_string();
+            let path = req.path().to_owned();
+
+            let fut = service.borrow_mut().call(req);
+            let res = fut.await?;
+
+            let ident = res.request().extensions().get::<Identity>().cloned();
+            // Denials are recorded with their target by `Identity`
+            if ident.as_ref().is_some_and(|x| x.is_denied()) {
+                return Ok(res);
+            }
+            let (payload, target) = match body {
+                Some(_) if SECRET_PATHS.contains(&path.as_str()) => (None, None),
+                Some(body) => summarize(&body),
+                None => (
+                    res.request()
+                        .headers()
+                        .get(header::CONTENT_TYPE)
+                        .and_then(|v| v.to_str().ok())
+                        .map(|v| format!("<{}>", v)),
+                    None,
+                ),
+            };
+            let status = res.status();
+            let event = NewAuditEvent {
+                actor: ident.as_ref().map(|x| x.id),
+                impersonator: ident.as_ref().and_then(|x| x.impersonator),
+                role: ident.as_ref().map(|x| x.role),
+                method,
+                path,
+                target,
+                payload,
+                status: i32::from(status.as_u16()),
+                success: status.is_success() || status.is_redirection(),
+                ip: client_ip(res.request()),
+            };
+            if let Err(e) = AuditEvent::record(&event) {
+                error!("Failed to record audit event {:?}: {}", event, e);
+            }
+            Ok(res)
+        }
+        .boxed_local()
+    }
+}
+
+fn is_mutating(method: &Method) -> bool {
+    method == Method::POST
+        || method == Method::PUT
+        || method == Method::PATCH
+        || method == Method::DELETE
+}
+
+/// Redacted and truncated json payload with the target resource
+fn summarize(body: &Bytes) -> (Option<String>, Option<String>) {
+    let mut value: Value = match serde_json::from_slice(body) {
+        Ok(value) => value,
+        Err(_) => return (Some("<invalid json>".to_owned()), None),
+    };
+    redact(&mut value);
+
+    let target = TARGET_FIELDS.iter().find_map(|key| match &value[*key] {
+        Value::String(x) => Some(format!("{}={}", key, x)),
+        Value::Number(x) => Some(format!("{}={}", key, x)),
+        _ => None,
+    });
+    let mut payload = value.to_string();
+    if payload.len() > PAYLOAD_MAX {
+        let mut end = PAYLOAD_MAX;
+        while !payload.is_char_boundary(end) {
+            end -= 1;
+        }
+        payload.truncate(end);
+        payload.push_str("...");
+    }
+    (Some(payload), target)
+}
+
+fn redact(value: &mut Value) {
+    match value {
+        Value::Object(map) => {
+            for (key, item) in map.iter_mut() {
+                let key = key.to_lowercase();
+                if SECRET_FIELDS.iter().any(|x| key.contains(x)) {
+                    *item = Value::String("***".to_owned());
+                } else {
+                    redact(item);
+                }
+            }
+        }
+        Value::Array(items) => items.iter_mut().for_each(redact),
+        _ => (),
+    }
+}
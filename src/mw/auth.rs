@@ -0,0 +1,446 @@
+//! Identity extractors used to guard the api scopes.
+//!
+//! `sign_in` stores `user_id` and `session_id` in the redis session, the
+//! role is always read from the database. Non-browser clients send a
+//! personal api token as `Authorization: Bearer <token>` instead.
+//! Handlers take one of the extractors below as an argument to require
+//! a signed in user with the proper `ClusterRole`. Anonymous requests and
+//! revoked sessions are rejected with `ServiceError::Unauthorized`,
+//! insufficient roles with `ServiceError::Forbidden`.
+//!
+//! A `ClusterAdmin` may impersonate another user for `IMPERSONATE_MINUTES`,
+//! the extractors then yield the target user with `impersonator` set.
+//!
+//! Denied accesses are stored in `audit_events` with their target by the
+//! checks themselves, for reads too, and skipped by the audit middleware.
+
+use actix::Addr;
+use actix_http::Payload;
+use actix_redis::RedisActor;
+use actix_session::{Session, UserSession};
+use actix_web::http::header;
+use actix_web::{web, FromRequest, HttpRequest};
+use chrono::Utc;
+use futures::future::{FutureExt, LocalBoxFuture};
+use uuid::Uuid;
+
+use std::cell::Cell;
+use std::rc::Rc;
+
+use crate::errors::{ApiError, ServiceError};
+use crate::models::audit::{AuditEvent, NewAuditEvent};
+use crate::models::department::Department;
+use crate::models::member::{MemberRole, NamespaceMember};
+use crate::models::namespace::Namespace;
+use crate::models::repository::Repository;
+use crate::models::token::ApiToken;
+use crate::models::user::{ClusterRole, User};
+use crate::services::session_service;
+use crate::utils::client_ip;
+
+/// Lifetime of an impersonation started by `/users/impersonate`
+pub const IMPERSONATE_MINUTES: i64 = 30;
+
+/// The signed in user of current request, `impersonator` is the
+/// `ClusterAdmin` acting as this user
+#[derive(Clone, Debug)]
+pub struct Identity {
+    pub id: Uuid,
+    pub role: ClusterRole,
+    pub impersonator: Option<Uuid>,
+    origin: Rc<Origin>,
+}
+
+/// The request an `Identity` was extracted from, shared by its clones
+#[derive(Debug)]
+struct Origin {
+    method: String,
+    path: String,
+    ip: String,
+    denied: Cell<bool>,
+}
+
+impl Origin {
+    fn of(req: &HttpRequest) -> Rc<Origin> {
+        Rc::new(Origin {
+            method: req.method().to_string(),
+            path: req.path().to_owned(),
+            ip: client_ip(req),
+            denied: Cell::new(false),
+        })
+    }
+}
+
+/// The admin and the target user of an unexpired impersonation,
+/// expired ones are removed from the session
+pub fn impersonation(sess: &Session) -> Option<(Uuid, Uuid)> {
+    let admin: Option<Uuid> = sess.get("user_id").ok().flatten();
+    let target: Option<Uuid> = sess.get("impersonate_id").ok().flatten();
+    let until: Option<i64> = sess.get("impersonate_until").ok().flatten();
+
+    match (admin, target, until) {
+        (Some(admin), Some(target), Some(until)) if until > Utc::now().timestamp() => {
+            Some((admin, target))
+        }
+        (_, Some(target), _) => {
+            info!(target: "audit", "Impersonation of {} expired", target);
+            sess.remove("impersonate_id");
+            sess.remove("impersonate_until");
+            None
+        }
+        _ => None,
+    }
+}
+
+impl Identity {
+    async fn from_request(req: HttpRequest) -> Result<Identity, ServiceError> {
+        let bearer = req
+            .headers()
+            .get(header::AUTHORIZATION)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.strip_prefix("Bearer "))
+            .map(|v| v.trim().to_owned());
+
+        let ident = match bearer {
+            Some(token) => Identity::from_token(&token, &req)?,
+            None => Identity::from_session(&req).await?,
+        };
+        // Picked up by the audit middleware
+        req.extensions_mut().insert(ident.clone());
+        Ok(ident)
+    }
+
+    fn from_token(plain: &str, req: &HttpRequest) -> Result<Identity, ServiceError> {
+        let token = ApiToken::authenticate(plain)
+            .map_err(|_| ServiceError::InternalServerError)?
+            .ok_or(ServiceError::Unauthorized)?;
+        let user = User::find(token.uid).map_err(|_| ServiceError::Unauthorized)?;
+
+        let ident = Identity {
+            id: user.id,
+            role: user.role,
+            impersonator: None,
+            origin: Origin::of(req),
+        };
+        if !token.scope.allows(req.method(), req.path()) {
+            return Err(ident.deny(
+                format!("token={}", token.id),
+                "The token scope does not allow this request".to_owned(),
+            ));
+        }
+        Ok(ident)
+    }
+
+    /// The session must still be registered in `session_service`. The role
+    /// is loaded from the database like for tokens, so a demotion takes
+    /// effect on the next request of the open sessions.
+    async fn from_session(req: &HttpRequest) -> Result<Identity, ServiceError> {
+        let sess = req.get_session();
+        let id: Option<Uuid> = sess
+            .get("user_id")
+            .map_err(|_| ServiceError::Unauthorized)?;
+        let (id, sid) = match (id, session_service::current(&sess)) {
+            (Some(id), Some(sid)) => (id, sid),
+            _ => return Err(ServiceError::Unauthorized),
+        };
+
+        let redis = req
+            .app_data::<web::Data<Addr<RedisActor>>>()
+            .ok_or(ServiceError::InternalServerError)?;
+        let owner = session_service::touch(redis, &sid)
+            .await
+            .map_err(|_| ServiceError::InternalServerError)?;
+        if owner != Some(id) {
+            sess.purge();
+            return Err(ServiceError::Unauthorized);
+        }
+
+        let user = User::find(id).map_err(|_| ServiceError::Unauthorized)?;
+        match impersonation(&sess) {
+            Some((admin, target)) if user.role == ClusterRole::ClusterAdmin => {
+                let target = User::find(target).map_err(|_| ServiceError::Unauthorized)?;
+                Ok(Identity {
+                    id: target.id,
+                    role: target.role,
+                    impersonator: Some(admin),
+                    origin: Origin::of(req),
+                })
+            }
+            Some((_, target)) => {
+                info!(target: "audit", "Impersonation of {} ended by a demotion of {}", target, id);
+                sess.remove("impersonate_id");
+                sess.remove("impersonate_until");
+                Ok(Identity {
+                    id,
+                    role: user.role,
+                    impersonator: None,
+                    origin: Origin::of(req),
+                })
+            }
+            None => Ok(Identity {
+                id,
+                role: user.role,
+                impersonator: None,
+                origin: Origin::of(req),
+            }),
+        }
+    }
+
+    pub fn is_cluster_admin(&self) -> bool {
+        self.role == ClusterRole::ClusterAdmin
+    }
+
+    pub fn is_admin(&self) -> bool {
+        self.role != ClusterRole::Lessee
+    }
+
+    /// Whether a denial of this request is already in `audit_events`
+    pub fn is_denied(&self) -> bool {
+        self.origin.denied.get()
+    }
+
+    /// Record the denied access to `target` as an audit event and
+    /// reject the request with `msg`
+    fn deny(&self, target: String, msg: String) -> ServiceError {
+        warn!(
+            target: "audit",
+            "Denied {} ({:?}, impersonator {:?}) access to {}",
+            self.id,
+            self.role,
+            self.impersonator,
+            target
+        );
+        let event = NewAuditEvent {
+            actor: Some(self.id),
+            impersonator: self.impersonator,
+            role: Some(self.role),
+            method: self.origin.method.clone(),
+            path: self.origin.path.clone(),
+            target: Some(target),
+            payload: None,
+            status: 403,
+            success: false,
+            ip: self.origin.ip.clone(),
+        };
+        match AuditEvent::record(&event) {
+            Ok(()) => self.origin.denied.set(true),
+            Err(e) => error!("Failed to record audit event {:?}: {}", event, e),
+        }
+        ServiceError::Forbidden(msg)
+    }
+
+    /// Reject account management while impersonating
+    pub fn check_not_impersonated(&self) -> Result<(), ApiError> {
+        match self.impersonator {
+            Some(admin) => Err(self
+                .deny(
+                    format!("impersonator={}", admin),
+                    "Not allowed while impersonating a user".to_owned(),
+                )
+                .into()),
+            None => Ok(()),
+        }
+    }
+
+    /// Departments managed by a `DepartmentAdmin`: its own with all the
+    /// sub-teams. `None` for a `ClusterAdmin` which manages all of them.
+    pub fn managed_departments(&self) -> Result<Option<Vec<i32>>, ApiError> {
+        if self.is_cluster_admin() {
+            return Ok(None);
+        }
+        match self.user()?.belong_to {
+            Some(own) if self.role == ClusterRole::DepartmentAdmin => {
+                Ok(Some(Department::subtree_ids(own)?))
+            }
+            _ => Err(self
+                .deny(
+                    "departments".to_owned(),
+                    "Not an administrator of any department".to_owned(),
+                )
+                .into()),
+        }
+    }
+
+    /// Load the user record of the identity
+    pub fn user(&self) -> Result<User, ApiError> {
+        User::find(self.id)
+    }
+
+    /// Check the identity can operate the resources of user `uid`:
+    /// the user self, the `DepartmentAdmin` of the user's department or
+    /// of any department above it, or any `ClusterAdmin`
+    pub fn check_user(&self, uid: &Uuid) -> Result<(), ApiError> {
+        if self.allows_user(uid)? {
+            Ok(())
+        } else {
+            Err(self
+                .deny(
+                    format!("uid={}", uid),
+                    "Not allowed to access resources of this user".to_owned(),
+                )
+                .into())
+        }
+    }
+
+    fn allows_user(&self, uid: &Uuid) -> Result<bool, ApiError> {
+        if self.id == *uid || self.is_cluster_admin() {
+            return Ok(true);
+        }
+        if self.role == ClusterRole::DepartmentAdmin {
+            if let (Some(own), Some(depart)) = (self.user()?.belong_to, User::find(*uid)?.belong_to) {
+                return Department::is_within(depart, own);
+            }
+        }
+        Ok(false)
+    }
+
+    /// Check the identity can operate namespace `ns`, the namespace must be
+    /// owned by a user accessible through `check_user` or shared with the
+    /// identity as a `Maintainer`. Violations are recorded as audit events
+    /// before any kubernetes call is made.
+    pub fn check_namespace(&self, ns: &str) -> Result<(), ApiError> {
+        self.check_namespace_role(ns, MemberRole::Maintainer)
+    }
+
+    /// Check the identity can read namespace `ns`, like `check_namespace`
+    /// with `Viewer`s allowed too
+    pub fn check_namespace_view(&self, ns: &str) -> Result<(), ApiError> {
+        self.check_namespace_role(ns, MemberRole::Viewer)
+    }
+
+    /// Whether `check_namespace` passes, without recording a denial
+    pub fn allows_namespace(&self, ns: &str) -> Result<bool, ApiError> {
+        self.allows_namespace_role(ns, MemberRole::Maintainer)
+    }
+
+    fn allows_namespace_role(&self, ns: &str, least: MemberRole) -> Result<bool, ApiError> {
+        if self.is_cluster_admin() {
+            return Ok(true);
+        }
+        match Namespace::owner_of(ns)? {
+            Some(owner) => Ok(self.allows_user(&owner)?
+                || NamespaceMember::role_of(ns, &self.id)?.is_some_and(|role| role.includes(least))),
+            None => Ok(false),
+        }
+    }
+
+    fn check_namespace_role(&self, ns: &str, least: MemberRole) -> Result<(), ApiError> {
+        if self.allows_namespace_role(ns, least)? {
+            Ok(())
+        } else {
+            Err(self
+                .deny(
+                    format!("namespace={}", ns),
+                    format!("Not allowed to operate namespace {}", ns),
+                )
+                .into())
+        }
+    }
+
+    /// Check the identity can use repository `name`: public repositories
+    /// are readable by everyone, the others follow `check_user` on the
+    /// owner. Repositories without an owner are managed by a `ClusterAdmin`.
+    pub fn check_repository(&self, name: &str, write: bool) -> Result<(), ApiError> {
+        let repo = Repository::find_valid(name)?;
+        if repo.is_public && !write {
+            return Ok(());
+        }
+        let allowed = match repo.belong_to {
+            Some(owner) => self.allows_user(&owner)?,
+            None => self.is_cluster_admin(),
+        };
+        if allowed {
+            Ok(())
+        } else {
+            Err(self
+                .deny(
+                    format!("repository={}", name),
+                    format!("Not allowed to operate repository {}", name),
+                )
+                .into())
+        }
+    }
+
+    /// Check the identity can manage department `depart_id`, authority
+    /// of a `DepartmentAdmin` is inherited down the sub-teams
+    pub fn check_department(&self, depart_id: i32) -> Result<(), ApiError> {
+        if self.is_cluster_admin() {
+            return Ok(());
+        }
+        if self.role == ClusterRole::DepartmentAdmin {
+            if let Some(own) = self.user()?.belong_to {
+                if Department::is_within(depart_id, own)? {
+                    return Ok(());
+                }
+            }
+        }
+        Err(self
+            .deny(
+                format!("department={}", depart_id),
+                "Not allowed to manage this department".to_owned(),
+            )
+            .into())
+    }
+}
+
+impl FromRequest for Identity {
+    type Error = ServiceError;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+    type Config = ();
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        Identity::from_request(req.clone()).boxed_local()
+    }
+}
+
+/// Signed in `ClusterAdmin` or `DepartmentAdmin`
+pub struct AdminIdentity(pub Identity);
+
+impl FromRequest for AdminIdentity {
+    type Error = ServiceError;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+    type Config = ();
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        Identity::from_request(req.clone())
+            .map(|res| {
+                res.and_then(|id| {
+                    if id.is_admin() {
+                        Ok(AdminIdentity(id))
+                    } else {
+                        Err(id.deny(
+                            format!("role={:?}", id.role),
+                            "Administrator required".to_owned(),
+                        ))
+                    }
+                })
+            })
+            .boxed_local()
+    }
+}
+
+/// Signed in `ClusterAdmin`
+pub struct ClusterAdminIdentity(pub Identity);
+
+impl FromRequest for ClusterAdminIdentity {
+    type Error = ServiceError;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+    type Config = ();
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        Identity::from_request(req.clone())
+            .map(|res| {
+                res.and_then(|id| {
+                    if id.is_cluster_admin() {
+                        Ok(ClusterAdminIdentity(id))
+                    } else {
+                        Err(id.deny(
+                            format!("role={:?}", id.role),
+                            "Cluster administrator required".to_owned(),
+                        ))
+                    }
+                })
+            })
+            .boxed_local()
+    }
+}
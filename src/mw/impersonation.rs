@@ -0,0 +1,79 @@
+use actix_service::{Service, Transform};
+use actix_session::UserSession;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::{HeaderName, HeaderValue};
+use actix_web::Error;
+use futures::future::{ok, FutureExt, LocalBoxFuture, Ready};
+use std::task::{Context, Poll};
+
+use super::auth::impersonation;
+
+/// Mark the requests of an impersonating admin: every request is recorded
+/// in the audit log with both identities and the response carries
+/// `X-Impersonated-By`. Must be wrapped inside the session middleware.
+pub struct Impersonation;
+
+impl<S, B> Transform<S> for Impersonation
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = ImpersonationMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(ImpersonationMiddleware { service })
+    }
+}
+
+pub struct ImpersonationMiddleware<S> {
+    service: S,
+}
+
+impl<S, B> Service for ImpersonationMiddleware<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: ServiceRequest) -> Self::Future {
+        let marker = impersonation(&req.get_session());
+        if let Some((admin, target)) = marker {
+            info!(
+                target: "audit",
+                "{} impersonating {}: {} {}",
+                admin,
+                target,
+                req.method(),
+                req.path()
+            );
+        }
+
+        let fut = self.service.call(req);
+        async move {
+            let mut res = fut.await?;
+            if let Some((admin, _)) = marker {
+                if let Ok(value) = HeaderValue::from_str(&admin.to_string()) {
+                    res.headers_mut()
+                        .insert(HeaderName::from_static("x-impersonated-by"), value);
+                }
+            }
+            Ok(res)
+        }
+        .boxed_local()
+    }
+}
@@ -0,0 +1,9 @@
+mod audit;
+mod auth;
+mod impersonation;
+mod session;
+
+pub use audit::Audit;
+pub use auth::{AdminIdentity, ClusterAdminIdentity, Identity, IMPERSONATE_MINUTES};
+pub use impersonation::Impersonation;
+pub use session::{redis_addr, redis_session};
@@ -3,15 +3,17 @@ use time::Duration;
 
 use crate::utils::SECRET_KEY;
 
-// Use redis as session storge
-pub fn redis_session(day: i64, domain: &str) -> RedisSession {
+/// Redis address from `REDIS_HOST` and `REDIS_PORT`
+pub fn redis_addr() -> String {
     let redis_host = std::env::var("REDIS_HOST").unwrap_or_else(|_| "localhost".to_string());
     let redis_port = std::env::var("REDIS_PORT").unwrap_or_else(|_| "6379".to_string());
 
-    RedisSession::new(
-        format!("{}:{}", redis_host, redis_port),
-        SECRET_KEY.as_bytes(),
-    )
-    .cookie_max_age(Duration::days(day))
-    .cookie_domain(domain)
+    format!("{}:{}", redis_host, redis_port)
+}
+
+// Use redis as session storge
+pub fn redis_session(day: i64, domain: &str) -> RedisSession {
+    RedisSession::new(redis_addr(), SECRET_KEY.as_bytes())
+        .cookie_max_age(Duration::days(day))
+        .cookie_domain(domain)
 }
@@ -2,8 +2,8 @@ use actix_session::Session;
 use actix_web::{get, web, HttpResponse, Result, Scope};
 
 use crate::handlers::{
-    depart_handlers, invitation_handlers, kube_test_handlers, ns_handlers, repos_handlers,
-    tasks_handlers, user_handlers, ing_handlers,
+    audit_handlers, depart_handlers, invitation_handlers, kube_test_handlers, network_handlers,
+    ns_handlers, repos_handlers, request_handlers, tasks_handlers, user_handlers, ing_handlers,
 };
 use crate::utils::JSON_PARSE_CONFIG;
 
@@ -39,7 +39,10 @@ pub fn api_scope() -> Scope {
         .service(depart_handlers::department_scope())
         .service(kube_test_handlers::kube_test_scope())
         .service(ns_handlers::ns_scope())
+        .service(network_handlers::network_scope())
         .service(tasks_handlers::tasks_scope())
         .service(repos_handlers::repos_scope())
         .service(ing_handlers::ing_scope())
+        .service(audit_handlers::audit_scope())
+        .service(request_handlers::request_scope())
 }
@@ -0,0 +1,693 @@
+//! Authentication providers behind `sign_in`.
+//!
+//! Local users verify the argon2 password stored in `users`, directory users
+//! bind against LDAP and OIDC users sign in with the authorization code flow.
+//! Directory users are provisioned as `Lessee` on the first sign in, the
+//! first of their groups named as a department decides `belong_to`. The
+//! groups are only read then: later sign ins keep the role and department,
+//! which cluster admins change with `/users/role` and `/users/move`.
+//! OIDC users are only provisioned or signed in with a verified email.
+//!
+//! Each external provider implements `AuthProvider`, the ones configured
+//! by the environment are registered in `PROVIDERS`.
+
+use futures::future::{self, FutureExt, LocalBoxFuture};
+use lazy_static::lazy_static;
+use ldap3::{ldap_escape, LdapConnAsync, Scope, SearchEntry};
+use reqwest::{Client, Url};
+use serde_json::Value;
+
+use crate::errors::ApiError;
+use crate::models::department::Department;
+use crate::models::user::{AuthProvider as ProviderKind, LoginInfo, User};
+
+/// Identity asserted by an external provider
+pub struct ProviderIdentity {
+    pub email: String,
+    pub name: String,
+    pub groups: Vec<String>,
+}
+
+/// An external identity provider. Providers checking passwords answer
+/// `verify`, the ones redirecting the browser `authorize_url` and
+/// `callback`, the other methods keep their refusing defaults.
+pub trait AuthProvider: Send + Sync {
+    /// Recorded as the provider of the users it signs in
+    fn kind(&self) -> ProviderKind;
+
+    /// Whether `sign_in` may pass email and password credentials
+    fn accepts_passwords(&self) -> bool {
+        false
+    }
+
+    fn verify<'a>(
+        &'a self,
+        _email: &'a str,
+        _password: &'a str,
+    ) -> LocalBoxFuture<'a, Result<ProviderIdentity, ApiError>> {
+        future::err(invalid_credentials()).boxed_local()
+    }
+
+    /// Url the browser is redirected to with `state`
+    fn authorize_url(&self, _state: &str) -> Result<String, ApiError> {
+        Err(no_redirect(self.kind()))
+    }
+
+    /// Identity of the browser coming back with `code`
+    fn callback<'a>(&'a self, _code: &'a str) -> LocalBoxFuture<'a, Result<ProviderIdentity, ApiError>> {
+        future::err(no_redirect(self.kind())).boxed_local()
+    }
+}
+
+fn no_redirect(kind: ProviderKind) -> ApiError {
+    ApiError::new(404, format!("{:?} provider does not sign in by redirect", kind))
+}
+
+/// LDAP bind provider, enabled by `LDAP_URL`
+///
+/// The user entry is searched under `LDAP_BASE_DN` with `LDAP_USER_FILTER`,
+/// `{}` is replaced by the escaped email. `LDAP_BIND_DN` and
+/// `LDAP_BIND_PASSWD` configure the service account used for the search.
+pub struct LdapConfig {
+    pub url: String,
+    pub base_dn: String,
+    pub bind_dn: Option<String>,
+    pub bind_passwd: Option<String>,
+    pub user_filter: String,
+}
+
+impl LdapConfig {
+    fn from_env() -> Option<LdapConfig> {
+        let url = std::env::var("LDAP_URL").ok()?;
+        Some(LdapConfig {
+            url,
+            base_dn: std::env::var("LDAP_BASE_DN").expect("LDAP_BASE_DN must be set"),
+            bind_dn: std::env::var("LDAP_BIND_DN").ok(),
+            bind_passwd: std::env::var("LDAP_BIND_PASSWD").ok(),
+            user_filter: std::env::var("LDAP_USER_FILTER")
+                .unwrap_or_else(|_| "(mail={})".to_owned()),
+        })
+    }
+}
+
+/// OIDC authorization code flow provider, enabled by `OIDC_CLIENT_ID`
+pub struct OidcConfig {
+    pub auth_url: String,
+    pub token_url: String,
+    pub userinfo_url: String,
+    pub client_id: String,
+    pub client_secret: String,
+    pub redirect_uri: String,
+    pub groups_claim: String,
+}
+
+impl OidcConfig {
+    fn from_env() -> Option<OidcConfig> {
+        let client_id = std::env::var("OIDC_CLIENT_ID").ok()?;
+        Some(OidcConfig {
+            auth_url: std::env::var("OIDC_AUTH_URL").expect("OIDC_AUTH_URL must be set"),
+            token_url: std::env::var("OIDC_TOKEN_URL").expect("OIDC_TOKEN_URL must be set"),
+            userinfo_url: std::env::var("OIDC_USERINFO_URL")
+                .expect("OIDC_USERINFO_URL must be set"),
+            client_id,
+            client_secret: std::env::var("OIDC_CLIENT_SECRET")
+                .expect("OIDC_CLIENT_SECRET must be set"),
+            redirect_uri: std::env::var("OIDC_REDIRECT_URI")
+                .expect("OIDC_REDIRECT_URI must be set"),
+            groups_claim: std::env::var("OIDC_GROUPS_CLAIM")
+                .unwrap_or_else(|_| "groups".to_owned()),
+        })
+    }
+}
+
+impl AuthProvider for LdapConfig {
+    fn kind(&self) -> ProviderKind {
+        ProviderKind::Ldap
+    }
+
+    fn accepts_passwords(&self) -> bool {
+        true
+    }
+
+    fn verify<'a>(
+        &'a self,
+        email: &'a str,
+        password: &'a str,
+    ) -> LocalBoxFuture<'a, Result<ProviderIdentity, ApiError>> {
+        ldap_bind(self, email, password).boxed_local()
+    }
+}
+
+impl AuthProvider for OidcConfig {
+    fn kind(&self) -> ProviderKind {
+        ProviderKind::Oidc
+    }
+
+    fn authorize_url(&self, state: &str) -> Result<String, ApiError> {
+        let url = Url::parse_with_params(
+            &self.auth_url,
+            &[
+                ("response_type", "code"),
+                ("scope", "openid email profile"),
+                ("client_id", self.client_id.as_str()),
+                ("redirect_uri", self.redirect_uri.as_str()),
+                ("state", state),
+            ],
+        )
+        .map_err(|e| ApiError::new(500, format!("Invalid OIDC_AUTH_URL: {}", e)))?;
+        Ok(url.into_string())
+    }
+
+    fn callback<'a>(&'a self, code: &'a str) -> LocalBoxFuture<'a, Result<ProviderIdentity, ApiError>> {
+        oidc_identity(self, code).boxed_local()
+    }
+}
+
+lazy_static! {
+    /// The configured providers, the first accepting passwords
+    /// provisions the unknown emails of `sign_in`
+    static ref PROVIDERS: Vec<Box<dyn AuthProvider>> = {
+        let mut providers: Vec<Box<dyn AuthProvider>> = Vec::new();
+        if let Some(config) = LdapConfig::from_env() {
+            providers.push(Box::new(config));
+        }
+        if let Some(config) = OidcConfig::from_env() {
+            providers.push(Box::new(config));
+        }
+        providers
+    };
+    static ref CLIENT: Client = Client::new();
+}
+
+fn provider(kind: ProviderKind) -> Option<&'static dyn AuthProvider> {
+    PROVIDERS.iter().find(|x| x.kind() == kind).map(|x| x.as_ref())
+}
+
+/// Verify email and password credentials with the provider of the user,
+/// unknown emails are looked up in the first provider accepting passwords
+pub async fn sign_in(credentials: &LoginInfo) -> Result<User, ApiError> {
+    let user = match User::find_by_email(&credentials.email) {
+        Ok(user) => Some(user),
+        Err(e) if e.status_code == 404 => None,
+        Err(e) => return Err(e),
+    };
+
+    match user {
+        Some(user) if user.provider == ProviderKind::Local => {
+            if user.verify_password(&credentials.password)? {
+                Ok(user)
+            } else {
+                Err(invalid_credentials())
+            }
+        }
+        Some(user) => match provider(user.provider) {
+            Some(p) if p.accepts_passwords() => {
+                p.verify(&credentials.email, &credentials.password).await?;
+                Ok(user)
+            }
+            _ => Err(invalid_credentials()),
+        },
+        None => match PROVIDERS.iter().find(|x| x.accepts_passwords()) {
+            Some(p) => {
+                let identity = p.verify(&credentials.email, &credentials.password).await?;
+                provision(identity, p.kind())
+            }
+            None => Err(invalid_credentials()),
+        },
+    }
+}
+
+/// Same answer for unknown emails and wrong passwords to
+/// avoid user enumeration
+fn invalid_credentials() -> ApiError {
+    ApiError::new(401, "Invalid email or password".to_owned())
+}
+
+/// Bind as the directory entry of `email` to verify the password
+async fn ldap_bind(
+    config: &LdapConfig,
+    email: &str,
+    password: &str,
+) -> Result<ProviderIdentity, ApiError> {
+    // An empty password is an anonymous bind which always succeeds
+    if password.is_empty() {
+        return Err(invalid_credentials());
+    }
+
+    let (conn, mut ldap) = LdapConnAsync::new(&config.url).await?;
+    ldap3::drive!(conn);
+
+    if let (Some(dn), Some(passwd)) = (config.bind_dn.as_ref(), config.bind_passwd.as_ref()) {
+        ldap.simple_bind(dn, passwd).await?.success()?;
+    }
+    let filter = config.user_filter.replace("{}", &ldap_escape(email));
+    let (entries, _) = ldap
+        .search(
+            &config.base_dn,
+            Scope::Subtree,
+            &filter,
+            vec!["cn", "mail", "memberOf"],
+        )
+        .await?
+        .success()?;
+    let entry = match entries.into_iter().next() {
+        Some(entry) => SearchEntry::construct(entry),
+        None => return Err(invalid_credentials()),
+    };
+
+    ldap.simple_bind(&entry.dn, password)
+        .await?
+        .success()
+        .map_err(|_| invalid_credentials())?;
+    ldap.unbind().await?;
+
+    Ok(entry_identity(&entry, email))
+}
+
+/// Identity of the directory entry found for `email`
+fn entry_identity(entry: &SearchEntry, email: &str) -> ProviderIdentity {
+    let name = entry
+        .attrs
+        .get("cn")
+        .and_then(|x| x.first())
+        .cloned()
+        .unwrap_or_else(|| email.to_owned());
+    let groups = entry
+        .attrs
+        .get("memberOf")
+        .map(|x| x.iter().map(|dn| group_name(dn)).collect())
+        .unwrap_or_default();
+    ProviderIdentity {
+        email: email.to_owned(),
+        name,
+        groups,
+    }
+}
+
+/// Value of the first RDN, `cn=dev,ou=groups,dc=example` is `dev`
+fn group_name(dn: &str) -> String {
+    dn.split(',')
+        .next()
+        .and_then(|rdn| rdn.split_once('=').map(|x| x.1))
+        .unwrap_or(dn)
+        .to_owned()
+}
+
+/// Authorization endpoint url the browser is redirected to
+pub fn oidc_authorize_url(state: &str) -> Result<String, ApiError> {
+    oidc_provider()?.authorize_url(state)
+}
+
+/// Exchange the authorization code and load the user of the
+/// `userinfo` response
+pub async fn oidc_sign_in(code: &str) -> Result<User, ApiError> {
+    let provider = oidc_provider()?;
+    let identity = provider.callback(code).await?;
+
+    match User::find_by_email(&identity.email) {
+        Ok(user) if user.provider == provider.kind() => Ok(user),
+        Ok(_) => Err(ApiError::new(
+            409,
+            "The email is registered with another provider".to_owned(),
+        )),
+        Err(e) if e.status_code == 404 => provision(identity, provider.kind()),
+        Err(e) => Err(e),
+    }
+}
+
+async fn oidc_identity(config: &OidcConfig, code: &str) -> Result<ProviderIdentity, ApiError> {
+    let token: Value = CLIENT
+        .post(&config.token_url)
+        .form(&[
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("redirect_uri", config.redirect_uri.as_str()),
+            ("client_id", config.client_id.as_str()),
+            ("client_secret", config.client_secret.as_str()),
+        ])
+        .send()
+        .await?
+        .error_for_status()
+        .map_err(|err| ApiError::new(401, format!("OIDC token exchange: {}", err)))?
+        .json()
+        .await?;
+    let access_token = token["access_token"]
+        .as_str()
+        .ok_or_else(|| ApiError::new(401, "OIDC response without access token".to_owned()))?;
+
+    let info: Value = CLIENT
+        .get(&config.userinfo_url)
+        .bearer_auth(access_token)
+        .send()
+        .await?
+        .error_for_status()
+        .map_err(|err| ApiError::new(401, format!("OIDC userinfo: {}", err)))?
+        .json()
+        .await?;
+    userinfo_identity(&info, &config.groups_claim)
+}
+
+/// Identity of a `userinfo` response, the email must be verified
+/// since it links the account
+fn userinfo_identity(info: &Value, groups_claim: &str) -> Result<ProviderIdentity, ApiError> {
+    let email = info["email"]
+        .as_str()
+        .ok_or_else(|| ApiError::new(401, "OIDC userinfo without email".to_owned()))?;
+    // Some providers send the boolean claims as strings
+    let verified = match &info["email_verified"] {
+        Value::Bool(x) => *x,
+        Value::String(x) => x == "true",
+        _ => false,
+    };
+    if !verified {
+        return Err(ApiError::new(
+            401,
+            format!("OIDC email {} is not verified", email),
+        ));
+    }
+
+    Ok(ProviderIdentity {
+        email: email.to_owned(),
+        name: info["name"].as_str().unwrap_or(email).to_owned(),
+        groups: info[groups_claim]
+            .as_array()
+            .map(|x| {
+                x.iter()
+                    .filter_map(|g| g.as_str().map(str::to_owned))
+                    .collect()
+            })
+            .unwrap_or_default(),
+    })
+}
+
+fn oidc_provider() -> Result<&'static dyn AuthProvider, ApiError> {
+    provider(ProviderKind::Oidc)
+        .ok_or_else(|| ApiError::new(404, "OIDC provider is not configured".to_owned()))
+}
+
+fn provision(identity: ProviderIdentity, provider: ProviderKind) -> Result<User, ApiError> {
+    let belong_to = Department::find_by_names(&identity.groups)?;
+    User::provision(&identity.email, &identity.name, belong_to, provider)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{test, web, App, HttpRequest, HttpResponse};
+    use serde_json::json;
+
+    use std::collections::HashMap;
+
+    fn config_of(srv: &test::TestServer) -> OidcConfig {
+        // The bound address, `localhost` may resolve to IPv6 first
+        let url = |path: &str| format!("http://{}{}", srv.addr(), path);
+        OidcConfig {
+            auth_url: url("/authorize"),
+            token_url: url("/token"),
+            userinfo_url: url("/userinfo"),
+            client_id: "pegasus".to_owned(),
+            client_secret: "secret".to_owned(),
+            redirect_uri: "http://localhost/callback".to_owned(),
+            groups_claim: "groups".to_owned(),
+        }
+    }
+
+    async fn mock_token(form: web::Form<HashMap<String, String>>) -> HttpResponse {
+        match form.get("code").map(String::as_str) {
+            Some("good") => HttpResponse::Ok().json(json!({
+                "access_token": "t0k3n",
+                "token_type": "Bearer",
+            })),
+            _ => HttpResponse::BadRequest().json(json!({
+                "error": "invalid_grant",
+            })),
+        }
+    }
+
+    async fn mock_userinfo(req: HttpRequest) -> HttpResponse {
+        let auth = req.headers().get("authorization");
+        if auth.map(|x| x.as_bytes()) != Some(b"Bearer t0k3n") {
+            return HttpResponse::Unauthorized().finish();
+        }
+        HttpResponse::Ok().json(json!({
+            "email": "alice@example.com",
+            "email_verified": true,
+            "name": "Alice",
+            "groups": ["dev", "ops"],
+        }))
+    }
+
+    /// Identity provider granting the token `t0k3n` for the code `good`
+    fn mock_idp() -> test::TestServer {
+        test::start(|| {
+            App::new()
+                .route("/token", web::post().to(mock_token))
+                .route("/userinfo", web::get().to(mock_userinfo))
+        })
+    }
+
+    #[actix_rt::test]
+    async fn oidc_code_exchange_loads_userinfo() {
+        let srv = mock_idp();
+        let identity = config_of(&srv).callback("good").await.unwrap();
+        assert_eq!(identity.email, "alice@example.com");
+        assert_eq!(identity.name, "Alice");
+        assert_eq!(identity.groups, vec!["dev", "ops"]);
+    }
+
+    #[actix_rt::test]
+    async fn oidc_rejected_code_is_unauthorized() {
+        let srv = mock_idp();
+        let err = config_of(&srv).callback("bad").await.err().unwrap();
+        assert_eq!(err.status_code, 401);
+    }
+
+    #[test]
+    fn userinfo_requires_verified_email() {
+        let unverified = [
+            json!({"email": "a@example.com"}),
+            json!({"email": "a@example.com", "email_verified": false}),
+            json!({"email": "a@example.com", "email_verified": "false"}),
+        ];
+        for info in unverified.iter() {
+            let err = userinfo_identity(info, "groups").err().unwrap();
+            assert_eq!(err.status_code, 401);
+        }
+
+        let info = json!({"email": "a@example.com", "email_verified": "true"});
+        let identity = userinfo_identity(&info, "groups").unwrap();
+        assert_eq!(identity.name, "a@example.com");
+        assert!(identity.groups.is_empty());
+    }
+
+    #[test]
+    fn userinfo_reads_configured_groups_claim() {
+        let info = json!({
+            "email": "a@example.com",
+            "email_verified": true,
+            "groups": ["ignored"],
+            "roles": ["dev", 42],
+        });
+        let identity = userinfo_identity(&info, "roles").unwrap();
+        assert_eq!(identity.groups, vec!["dev"]);
+    }
+
+    /// Directory of `alice@example.com` with password `s3cret`, searched
+    /// by the service account `cn=svc,dc=example` with password `svcpw`
+    mod mock_ldap {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        use tokio::net::{TcpListener, TcpStream};
+
+        pub const ALICE_DN: &str = "uid=alice,ou=people,dc=example";
+        const ACCOUNTS: [(&str, &str); 2] = [("cn=svc,dc=example", "svcpw"), (ALICE_DN, "s3cret")];
+
+        /// Url of a server answering the connections of the current test
+        pub async fn start() -> String {
+            let mut listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let url = format!("ldap://{}", listener.local_addr().unwrap());
+            actix_rt::spawn(async move {
+                while let Ok((stream, _)) = listener.accept().await {
+                    actix_rt::spawn(serve(stream));
+                }
+            });
+            url
+        }
+
+        async fn serve(mut stream: TcpStream) {
+            let mut buf = Vec::new();
+            let mut chunk = [0u8; 1024];
+            loop {
+                while let Some((tag, value, used)) = read_tlv(&buf) {
+                    assert_eq!(tag, 0x30, "LDAPMessage is a sequence");
+                    let message = value.to_vec();
+                    buf.drain(..used);
+                    match respond(&message) {
+                        Some(reply) => stream.write_all(&reply).await.unwrap(),
+                        None => return,
+                    }
+                }
+                match stream.read(&mut chunk).await {
+                    Ok(0) | Err(_) => return,
+                    Ok(n) => buf.extend_from_slice(&chunk[..n]),
+                }
+            }
+        }
+
+        /// Replies to one message, `None` for an unbind
+        fn respond(message: &[u8]) -> Option<Vec<u8>> {
+            let (_, id, used) = read_tlv(message).unwrap();
+            let id = id.to_vec();
+            let (op, body, _) = read_tlv(&message[used..]).unwrap();
+            match op {
+                // BindRequest: version, name, simple password
+                0x60 => {
+                    let (_, _, used) = read_tlv(body).unwrap();
+                    let (_, name, n) = read_tlv(&body[used..]).unwrap();
+                    let (_, passwd, _) = read_tlv(&body[used + n..]).unwrap();
+                    let ok = ACCOUNTS
+                        .iter()
+                        .any(|(dn, pw)| dn.as_bytes() == name && pw.as_bytes() == passwd);
+                    Some(message_of(&id, &result(0x61, if ok { 0 } else { 49 })))
+                }
+                // SearchRequest, the filter holds the escaped email
+                0x63 => {
+                    let mut reply = Vec::new();
+                    if contains(body, b"alice@example.com") {
+                        let attrs = [
+                            attribute("cn", &["Alice"]),
+                            attribute("memberOf", &["cn=dev,ou=groups,dc=example"]),
+                        ]
+                        .concat();
+                        let entry = [tlv(0x04, ALICE_DN.as_bytes()), tlv(0x30, &attrs)].concat();
+                        reply.extend(message_of(&id, &tlv(0x64, &entry)));
+                    }
+                    reply.extend(message_of(&id, &result(0x65, 0)));
+                    Some(reply)
+                }
+                _ => None,
+            }
+        }
+
+        fn attribute(name: &str, values: &[&str]) -> Vec<u8> {
+            let values: Vec<u8> = values.iter().flat_map(|x| tlv(0x04, x.as_bytes())).collect();
+            tlv(0x30, &[tlv(0x04, name.as_bytes()), tlv(0x31, &values)].concat())
+        }
+
+        /// LDAPResult with empty matched dn and diagnostic message
+        fn result(op: u8, code: u8) -> Vec<u8> {
+            tlv(op, &[tlv(0x0a, &[code]), tlv(0x04, b""), tlv(0x04, b"")].concat())
+        }
+
+        fn message_of(id: &[u8], op: &[u8]) -> Vec<u8> {
+            tlv(0x30, &[tlv(0x02, id), op.to_vec()].concat())
+        }
+
+        fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+            haystack.windows(needle.len()).any(|x| x == needle)
+        }
+
+        fn tlv(tag: u8, value: &[u8]) -> Vec<u8> {
+            let mut out = vec![tag];
+            match value.len() {
+                len if len < 0x80 => out.push(len as u8),
+                len if len < 0x100 => out.extend(&[0x81, len as u8]),
+                len => out.extend(&[0x82, (len >> 8) as u8, len as u8]),
+            }
+            out.extend_from_slice(value);
+            out
+        }
+
+        /// Tag, value and encoded size of the first element of `buf`
+        fn read_tlv(buf: &[u8]) -> Option<(u8, &[u8], usize)> {
+            let tag = *buf.first()?;
+            let first = *buf.get(1)? as usize;
+            let (len, start) = if first < 0x80 {
+                (first, 2)
+            } else {
+                let n = first & 0x7f;
+                let bytes = buf.get(2..2 + n)?;
+                (bytes.iter().fold(0, |x, b| (x << 8) | *b as usize), 2 + n)
+            };
+            let value = buf.get(start..start + len)?;
+            Some((tag, value, start + len))
+        }
+    }
+
+    fn ldap_config(url: String) -> LdapConfig {
+        LdapConfig {
+            url,
+            base_dn: "dc=example".to_owned(),
+            bind_dn: Some("cn=svc,dc=example".to_owned()),
+            bind_passwd: Some("svcpw".to_owned()),
+            user_filter: "(mail={})".to_owned(),
+        }
+    }
+
+    #[actix_rt::test]
+    async fn ldap_bind_verifies_the_directory_password() {
+        let config = ldap_config(mock_ldap::start().await);
+        assert!(config.accepts_passwords());
+
+        let identity = config.verify("alice@example.com", "s3cret").await.unwrap();
+        assert_eq!(identity.email, "alice@example.com");
+        assert_eq!(identity.name, "Alice");
+        assert_eq!(identity.groups, vec!["dev"]);
+    }
+
+    #[actix_rt::test]
+    async fn ldap_bind_rejects_wrong_passwords_and_unknown_emails() {
+        let config = ldap_config(mock_ldap::start().await);
+
+        let attempts = [
+            ("alice@example.com", "wrong"),
+            ("alice@example.com", ""),
+            ("bob@example.com", "s3cret"),
+        ];
+        for (email, password) in attempts.iter() {
+            let err = config.verify(email, password).await.err().unwrap();
+            assert_eq!(err.status_code, 401, "{} {:?}", email, password);
+            assert_eq!(err.msg, invalid_credentials().msg);
+        }
+    }
+
+    #[actix_rt::test]
+    async fn ldap_service_account_must_bind() {
+        let mut config = ldap_config(mock_ldap::start().await);
+        config.bind_passwd = Some("wrong".to_owned());
+
+        let err = config.verify("alice@example.com", "s3cret").await.err().unwrap();
+        // A broken service account is a server error, not the user's
+        assert_eq!(err.status_code, 500);
+        assert!(err.msg.contains("rc=49"), "{}", err.msg);
+    }
+
+    #[test]
+    fn directory_entry_maps_name_and_groups() {
+        let mut attrs = HashMap::new();
+        attrs.insert("cn".to_owned(), vec!["Bob".to_owned()]);
+        attrs.insert(
+            "memberOf".to_owned(),
+            vec![
+                "cn=dev,ou=groups,dc=example".to_owned(),
+                "ops".to_owned(),
+            ],
+        );
+        let entry = SearchEntry {
+            dn: "uid=bob,dc=example".to_owned(),
+            attrs,
+            bin_attrs: HashMap::new(),
+        };
+        let identity = entry_identity(&entry, "bob@example.com");
+        assert_eq!(identity.name, "Bob");
+        assert_eq!(identity.groups, vec!["dev", "ops"]);
+
+        let bare = SearchEntry {
+            dn: "uid=bob,dc=example".to_owned(),
+            attrs: HashMap::new(),
+            bin_attrs: HashMap::new(),
+        };
+        let identity = entry_identity(&bare, "bob@example.com");
+        assert_eq!(identity.name, "bob@example.com");
+        assert!(identity.groups.is_empty());
+    }
+}
@@ -10,6 +10,8 @@ use lazy_static::lazy_static;
 
 use crate::errors::ApiError;
 use crate::models::invitation::Invitation;
+use crate::models::request::ResourceRequest;
+use crate::models::reset::PasswordReset;
 use crate::utils::{
     EMAIL_DOMAIN, ORGANISE_NAME, SENDING_EMAIL_ADDRESS, SENDING_EMAIL_PASSWD, SMTP_SERVER_ADDR,
 };
@@ -22,6 +24,11 @@ lazy_static! {
         // println!("{:?}", file);
         fs::read_to_string(file.as_path()).unwrap()
     };
+    pub static ref RESET_TEMPLATE: String = {
+        let mut file = std::env::current_dir().unwrap();
+        file.push("templates/reset.html");
+        fs::read_to_string(file.as_path()).unwrap()
+    };
 }
 
 pub fn send_email(invit: &Invitation) -> Result<(), ApiError> {
@@ -36,11 +43,68 @@ pub fn send_email(invit: &Invitation) -> Result<(), ApiError> {
         .replacen("#", EMAIL_DOMAIN.as_str(), 1)
         .replacen("#", &invit.id.to_string(), 1);
 
+    send(&invit.email, "Invitation from Pegasus", email_contents)
+}
+
+pub fn send_reset_email(reset: &PasswordReset) -> Result<(), ApiError> {
+    let email_contents = RESET_TEMPLATE
+        .clone()
+        .replacen("#", ORGANISE_NAME.as_str(), 1)
+        .replacen("#", EMAIL_DOMAIN.as_str(), 1)
+        .replacen("#", &reset.id.to_string(), 1)
+        .replacen("#", EMAIL_DOMAIN.as_str(), 1)
+        .replacen("#", &reset.id.to_string(), 1)
+        .replacen("#", EMAIL_DOMAIN.as_str(), 1)
+        .replacen("#", &reset.id.to_string(), 1);
+
+    send(&reset.email, "Reset your Pegasus password", email_contents)
+}
+
+/// Notify `to` that resource request `req` reached `event`
+pub fn send_request_notice(to: &str, req: &ResourceRequest, event: &str) -> Result<(), ApiError> {
+    let target = req
+        .namespace
+        .as_ref()
+        .map(|ns| format!(" of namespace {}", ns))
+        .unwrap_or_default();
+    let mut contents = format!(
+        "<p>Resource request #{} {}.</p><p>{:?}{}: {}</p><p>Justification: {}</p>",
+        req.id,
+        event,
+        req.kind,
+        target,
+        escape(&req.amount),
+        escape(&req.justification),
+    );
+    if let Some(comment) = req.comment.as_ref() {
+        contents.push_str(&format!("<p>Comment: {}</p>", escape(comment)));
+    }
+    contents.push_str(&format!(
+        "<p><a href=\"https://{}/requests/{}\">View the request</a></p>",
+        EMAIL_DOMAIN.as_str(),
+        req.id
+    ));
+
+    send(
+        to,
+        &format!("{} resource request #{}", ORGANISE_NAME.as_str(), req.id),
+        contents,
+    )
+}
+
+fn escape(x: &str) -> String {
+    x.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn send(to: &str, subject: &str, contents: String) -> Result<(), ApiError> {
     let email = EmailBuilder::new()
         .from(SENDING_EMAIL_ADDRESS.as_str())
-        .to(invit.email.as_str())
-        .subject("Invitation from Pegasus")
-        .alternative(email_contents, "")
+        .to(to)
+        .subject(subject)
+        .alternative(contents, "")
         .build()?;
 
     let mut transport = SmtpClient::new_simple(&SMTP_SERVER_ADDR)?
@@ -1,13 +1,21 @@
 use futures::executor::block_on;
+use futures::try_join;
 use k8s_openapi::api::apps::v1::Deployment;
-use k8s_openapi::api::core::v1::{Namespace, Node, Pod, Service};
+use k8s_openapi::api::core::v1::{
+    LimitRange, Namespace, Node, Pod, ResourceQuota, Secret, Service, ServiceAccount,
+};
+use k8s_openapi::api::networking::v1::NetworkPolicy;
+use k8s_openapi::api::rbac::v1::{Role, RoleBinding};
 use k8s_openapi::api::extensions::v1beta1::{Ingress, IngressBackend, HTTPIngressPath};
+use k8s_openapi::apimachinery::pkg::api::resource::Quantity;
 use k8s_openapi::apimachinery::pkg::util::intstr::IntOrString;
 use kube::{
-    api::{Api, DeleteParams, ListParams, LogParams, Meta, PostParams},
+    api::{Api, DeleteParams, ListParams, LogParams, Meta, PatchParams, PostParams},
     client::Client,
+    Error as KubeError,
 };
 use lazy_static::lazy_static;
+use serde::{de::DeserializeOwned, Serialize};
 use serde_json::json;
 use uuid::Uuid;
 
@@ -15,13 +23,70 @@ use std::collections::BTreeMap;
 use std::vec::Vec;
 
 use crate::errors::ApiError;
-use crate::models::kube::{DeployInfo, ResourceState, ServiceInfo};
+use crate::models::kube::{
+    DeployInfo, ManagedNamespace, NamespaceResources, ResourceState, ServiceInfo,
+};
 use crate::models::ingress::{IngressInfo, IngressResponse};
 use crate::models::namespace::Namespace as NS;
+use crate::models::network::{NetworkProfile, NetworkRule};
+use crate::models::quota::{QuotaSpec, QuotaStatus};
+
+/// Names of the quota objects pegasus manages in every namespace
+const QUOTA_NAME: &str = "pegasus-quota";
+const LIMIT_RANGE_NAME: &str = "pegasus-limits";
+/// Names of the roles and bindings granting users access to a namespace
+const OWNER_ROLE: &str = "pegasus-owner";
+const VIEWER_ROLE: &str = "pegasus-viewer";
+/// Resources the namespace owner manages with kubectl
+const OWNER_RESOURCES: [(&str, &str); 11] = [
+    ("", "pods"),
+    ("", "pods/log"),
+    ("", "pods/exec"),
+    ("", "services"),
+    ("", "configmaps"),
+    ("", "secrets"),
+    ("", "persistentvolumeclaims"),
+    ("apps", "deployments"),
+    ("apps", "replicasets"),
+    ("apps", "statefulsets"),
+    ("extensions", "ingresses"),
+];
+/// Resources viewers read, like the built-in `view` role without
+/// `secrets` nor the subresources reaching into pods
+const VIEWER_RESOURCES: [(&str, &str); 9] = [
+    ("", "pods"),
+    ("", "pods/log"),
+    ("", "services"),
+    ("", "configmaps"),
+    ("", "persistentvolumeclaims"),
+    ("apps", "deployments"),
+    ("apps", "replicasets"),
+    ("apps", "statefulsets"),
+    ("extensions", "ingresses"),
+];
+/// Name of the network policy carrying the profile of a namespace
+const PROFILE_POLICY: &str = "pegasus-profile";
+/// Namespace labels selected by the network policies
+const NS_LABEL: &str = "pegasus-namespace";
+const OWNER_LABEL: &str = "pegasus-owner";
+const DEPARTMENT_LABEL: &str = "pegasus-department";
+/// Polls of a new service account for its token
+const TOKEN_POLLS: u32 = 10;
 
 lazy_static! {
     pub static ref KUBE_CLIENT: Client =
         { block_on(Client::infer()).expect("Please config your k8s cluster correctly!") };
+    /// Labels of the namespace of the ingress controller, e.g.
+    /// `app.kubernetes.io/name=ingress-nginx`
+    pub static ref INGRESS_CONTROLLER_LABEL: (String, String) = {
+        let label = std::env::var("INGRESS_CONTROLLER_LABEL")
+            .unwrap_or_else(|_| "app.kubernetes.io/name=ingress-nginx".to_owned());
+        let (key, value) = label.split_once('=').unwrap_or((&label, ""));
+        (key.to_owned(), value.to_owned())
+    };
+    /// Namespace holding one service account per pegasus user
+    pub static ref ACCOUNT_NAMESPACE: String =
+        std::env::var("PEGASUS_ACCOUNT_NS").unwrap_or_else(|_| "pegasus-accounts".to_owned());
 }
 
 /// Get all nodes names, return a vector of String
@@ -57,6 +122,457 @@ where
     Ok(())
 }
 
+/// All the namespaces labeled `dispense=pegasus` with the owner
+/// `apply_network` labeled them with
+pub async fn list_managed_ns() -> Result<Vec<ManagedNamespace>, ApiError> {
+    let namespaces: Api<Namespace> = Api::all(KUBE_CLIENT.clone());
+    let results = namespaces
+        .list(&ListParams::default().labels("dispense=pegasus"))
+        .await?
+        .iter()
+        .map(|x| {
+            let meta = x.metadata.as_ref();
+            ManagedNamespace {
+                name: Meta::name(x),
+                owner: meta
+                    .and_then(|m| m.labels.as_ref())
+                    .and_then(|l| l.get(OWNER_LABEL))
+                    .and_then(|v| Uuid::parse_str(v).ok()),
+                active: ResourceState::from(x).state,
+                created_at: meta
+                    .and_then(|m| m.creation_timestamp.as_ref())
+                    .map(|t| t.0),
+            }
+        })
+        .collect();
+    Ok(results)
+}
+
+/// Create or update the `ResourceQuota` and `LimitRange` of namespace `ns`.
+/// The quota bounds container limits, so the limit range gives every
+/// container without limits the defaults of `spec`.
+pub async fn apply_quota(ns: &str, spec: &QuotaSpec) -> Result<(), ApiError> {
+    let quota: ResourceQuota = serde_json::from_value(json!({
+        "apiVersion": "v1",
+        "kind": "ResourceQuota",
+        "metadata": {
+            "name": QUOTA_NAME,
+            "namespace": ns,
+        },
+        "spec": {
+            "hard": {
+                "limits.cpu": &spec.cpu,
+                "limits.memory": &spec.memory,
+                "requests.storage": &spec.storage,
+                "pods": spec.pods.to_string(),
+                "services": spec.services.to_string(),
+            },
+        },
+    }))?;
+    let limits: LimitRange = serde_json::from_value(json!({
+        "apiVersion": "v1",
+        "kind": "LimitRange",
+        "metadata": {
+            "name": LIMIT_RANGE_NAME,
+            "namespace": ns,
+        },
+        "spec": {
+            "limits": [
+                {
+                    "type": "Container",
+                    "default": {
+                        "cpu": &spec.default_cpu,
+                        "memory": &spec.default_memory,
+                    },
+                },
+            ],
+        },
+    }))?;
+
+    create_or_patch(&Api::namespaced(KUBE_CLIENT.clone(), ns), QUOTA_NAME, &quota).await?;
+    create_or_patch(&Api::namespaced(KUBE_CLIENT.clone(), ns), LIMIT_RANGE_NAME, &limits).await?;
+    Ok(())
+}
+
+/// Hard limits and usage of the pegasus quota in namespace `ns`
+pub async fn get_quota_status(ns: &str) -> Result<QuotaStatus, ApiError> {
+    let quotas: Api<ResourceQuota> = Api::namespaced(KUBE_CLIENT.clone(), ns);
+    let quota = quotas.get(QUOTA_NAME).await?;
+
+    let status = quota.status.unwrap_or_default();
+    let amounts = |x: Option<BTreeMap<_, Quantity>>| {
+        x.unwrap_or_default()
+            .into_iter()
+            .map(|(k, v)| (k, v.0))
+            .collect()
+    };
+    Ok(QuotaStatus {
+        hard: amounts(status.hard),
+        used: amounts(status.used),
+    })
+}
+
+/// Create `obj`, or merge it into the existing object named `name`
+async fn create_or_patch<K>(api: &Api<K>, name: &str, obj: &K) -> Result<K, ApiError>
+where
+    K: Clone + DeserializeOwned + Meta + Serialize,
+{
+    match api.create(&PostParams::default(), obj).await {
+        Err(KubeError::Api(ae)) if ae.code == 409 => {
+            let patch = serde_json::to_vec(obj)?;
+            Ok(api.patch(name, &PatchParams::default(), patch).await?)
+        }
+        res => Ok(res?),
+    }
+}
+
+/// Label namespace `ns` with its owner and department and replace
+/// its ingress policy by the one of `profile`
+pub async fn apply_network(
+    ns: &str,
+    profile: NetworkProfile,
+    owner: &Uuid,
+    department: Option<i32>,
+) -> Result<(), ApiError> {
+    let namespaces: Api<Namespace> = Api::all(KUBE_CLIENT.clone());
+    let labels = json!({
+        "metadata": {
+            "labels": {
+                NS_LABEL: ns,
+                OWNER_LABEL: owner.to_string(),
+                DEPARTMENT_LABEL: department.map(|x| x.to_string()),
+            },
+        },
+    });
+    namespaces
+        .patch(ns, &PatchParams::default(), serde_json::to_vec(&labels)?)
+        .await?;
+
+    let mut peers = vec![json!({ "podSelector": {} })];
+    match (profile, department) {
+        (NetworkProfile::Department, Some(department)) => peers.push(json!({
+            "namespaceSelector": {
+                "matchLabels": { DEPARTMENT_LABEL: department.to_string() },
+            },
+        })),
+        (NetworkProfile::IngressController, _) => {
+            let (key, value) = &*INGRESS_CONTROLLER_LABEL;
+            peers.push(json!({
+                "namespaceSelector": {
+                    "matchLabels": { key: value },
+                },
+            }))
+        }
+        _ => (),
+    }
+    let policy: NetworkPolicy = serde_json::from_value(json!({
+        "apiVersion": "networking.k8s.io/v1",
+        "kind": "NetworkPolicy",
+        "metadata": {
+            "name": PROFILE_POLICY,
+            "namespace": ns,
+        },
+        "spec": {
+            "podSelector": {},
+            "policyTypes": ["Ingress"],
+            "ingress": [{ "from": peers }],
+        },
+    }))?;
+    let policies: Api<NetworkPolicy> = Api::namespaced(KUBE_CLIENT.clone(), ns);
+    create_or_patch(&policies, PROFILE_POLICY, &policy).await?;
+    Ok(())
+}
+
+fn allow_policy_name(from_ns: &str) -> String {
+    format!("pegasus-allow-{}", from_ns)
+}
+
+/// Admit the pods of namespace `from_ns` into namespace `to_ns`
+pub async fn allow_from(to_ns: &str, from_ns: &str) -> Result<(), ApiError> {
+    let name = allow_policy_name(from_ns);
+    let policy: NetworkPolicy = serde_json::from_value(json!({
+        "apiVersion": "networking.k8s.io/v1",
+        "kind": "NetworkPolicy",
+        "metadata": {
+            "name": &name,
+            "namespace": to_ns,
+        },
+        "spec": {
+            "podSelector": {},
+            "policyTypes": ["Ingress"],
+            "ingress": [{
+                "from": [{
+                    "namespaceSelector": {
+                        "matchLabels": { NS_LABEL: from_ns },
+                    },
+                }],
+            }],
+        },
+    }))?;
+    let policies: Api<NetworkPolicy> = Api::namespaced(KUBE_CLIENT.clone(), to_ns);
+    create_or_patch(&policies, &name, &policy).await?;
+    Ok(())
+}
+
+/// Remove the policy of `allow_from`, missing policies are fine
+pub async fn revoke_from(to_ns: &str, from_ns: &str) -> Result<(), ApiError> {
+    let policies: Api<NetworkPolicy> = Api::namespaced(KUBE_CLIENT.clone(), to_ns);
+    match policies
+        .delete(&allow_policy_name(from_ns), &DeleteParams::default())
+        .await
+    {
+        Err(KubeError::Api(ae)) if ae.code == 404 => Ok(()),
+        res => res.map(|_| ()).map_err(ApiError::from),
+    }
+}
+
+/// Drop the allow rules from or to namespace `ns` before it is deleted,
+/// with the policies it left in the other namespaces
+pub async fn remove_network_rules(ns: &NS) -> Result<(), ApiError> {
+    for rule in NetworkRule::delete_of(ns.id)? {
+        if rule.to != ns.namespace {
+            revoke_from(&rule.to, &rule.from).await?;
+        }
+    }
+    Ok(())
+}
+
+fn account_name(uid: &Uuid) -> String {
+    format!("user-{}", uid)
+}
+
+/// Create the service account of user `uid` if missing
+pub async fn ensure_account(uid: &Uuid) -> Result<(), ApiError> {
+    let namespaces: Api<Namespace> = Api::all(KUBE_CLIENT.clone());
+    let ns_obj: Namespace = serde_json::from_value(json!({
+        "apiVersion": "v1",
+        "kind": "Namespace",
+        "metadata": {
+            "name": ACCOUNT_NAMESPACE.as_str(),
+            "labels": {
+                "dispense": "pegasus-system",
+            },
+        },
+    }))?;
+    create_or_patch(&namespaces, &ACCOUNT_NAMESPACE, &ns_obj).await?;
+
+    let name = account_name(uid);
+    let accounts: Api<ServiceAccount> = Api::namespaced(KUBE_CLIENT.clone(), &ACCOUNT_NAMESPACE);
+    let account: ServiceAccount = serde_json::from_value(json!({
+        "apiVersion": "v1",
+        "kind": "ServiceAccount",
+        "metadata": {
+            "name": &name,
+            "namespace": ACCOUNT_NAMESPACE.as_str(),
+            "labels": {
+                "pegasus-user": uid.to_string(),
+            },
+        },
+    }))?;
+    create_or_patch(&accounts, &name, &account).await?;
+    Ok(())
+}
+
+/// Let the service accounts of `editors` manage namespace `ns` and the
+/// accounts of `viewers` read it
+pub async fn grant_access(ns: &str, editors: &[Uuid], viewers: &[Uuid]) -> Result<(), ApiError> {
+    for uid in editors.iter().chain(viewers) {
+        ensure_account(uid).await?;
+    }
+
+    let rules = |resources: &[(&str, &str)], verbs: &[&str]| -> Vec<serde_json::Value> {
+        resources
+            .iter()
+            .map(|(group, resource)| {
+                json!({
+                    "apiGroups": [group],
+                    "resources": [resource],
+                    "verbs": verbs,
+                })
+            })
+            .collect()
+    };
+    let subjects = |uids: &[Uuid]| -> Vec<serde_json::Value> {
+        uids.iter()
+            .map(|uid| {
+                json!({
+                    "kind": "ServiceAccount",
+                    "name": account_name(uid),
+                    "namespace": ACCOUNT_NAMESPACE.as_str(),
+                })
+            })
+            .collect()
+    };
+    let grants = [
+        (
+            OWNER_ROLE,
+            rules(
+                &OWNER_RESOURCES,
+                &["get", "list", "watch", "create", "update", "patch", "delete"],
+            ),
+            subjects(editors),
+        ),
+        (
+            VIEWER_ROLE,
+            rules(&VIEWER_RESOURCES, &["get", "list", "watch"]),
+            subjects(viewers),
+        ),
+    ];
+
+    let roles: Api<Role> = Api::namespaced(KUBE_CLIENT.clone(), ns);
+    let bindings: Api<RoleBinding> = Api::namespaced(KUBE_CLIENT.clone(), ns);
+    for (name, rules, subjects) in grants.iter() {
+        let role: Role = serde_json::from_value(json!({
+            "apiVersion": "rbac.authorization.k8s.io/v1",
+            "kind": "Role",
+            "metadata": {
+                "name": name,
+                "namespace": ns,
+            },
+            "rules": rules,
+        }))?;
+        let binding: RoleBinding = serde_json::from_value(json!({
+            "apiVersion": "rbac.authorization.k8s.io/v1",
+            "kind": "RoleBinding",
+            "metadata": {
+                "name": name,
+                "namespace": ns,
+            },
+            "roleRef": {
+                "apiGroup": "rbac.authorization.k8s.io",
+                "kind": "Role",
+                "name": name,
+            },
+            "subjects": subjects,
+        }))?;
+        create_or_patch(&roles, name, &role).await?;
+        create_or_patch(&bindings, name, &binding).await?;
+    }
+    Ok(())
+}
+
+/// Token and cluster ca certificate of the service account of user `uid`,
+/// waits for the token controller after the account or token is new
+pub async fn account_token(uid: &Uuid) -> Result<(String, Vec<u8>), ApiError> {
+    let accounts: Api<ServiceAccount> = Api::namespaced(KUBE_CLIENT.clone(), &ACCOUNT_NAMESPACE);
+    let secrets: Api<Secret> = Api::namespaced(KUBE_CLIENT.clone(), &ACCOUNT_NAMESPACE);
+
+    for _ in 0..TOKEN_POLLS {
+        let account = match accounts.get(&account_name(uid)).await {
+            Ok(account) => account,
+            Err(KubeError::Api(ae)) if ae.code == 404 => {
+                return Err(ApiError::new(
+                    503,
+                    "The account is not provisioned yet, please retry later".to_owned(),
+                ))
+            }
+            Err(e) => return Err(e.into()),
+        };
+        let names = account
+            .secrets
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|x| x.name);
+        for name in names {
+            let mut data = match secrets.get(&name).await {
+                Ok(secret) => secret.data.unwrap_or_default(),
+                Err(KubeError::Api(ae)) if ae.code == 404 => continue,
+                Err(e) => return Err(e.into()),
+            };
+            if let (Some(token), Some(ca)) = (data.remove("token"), data.remove("ca.crt")) {
+                let token = String::from_utf8(token.0)
+                    .map_err(|_| ApiError::new(500, "Malformed account token".to_owned()))?;
+                return Ok((token, ca.0));
+            }
+        }
+        tokio::time::delay_for(std::time::Duration::from_millis(500)).await;
+    }
+    Err(ApiError::new(
+        503,
+        "The account token is being issued, please retry later".to_owned(),
+    ))
+}
+
+/// Delete the token secrets of the account of user `uid`, the token
+/// controller issues a new one so the kubeconfigs handed out are revoked
+pub async fn rotate_token(uid: &Uuid) -> Result<(), ApiError> {
+    let accounts: Api<ServiceAccount> = Api::namespaced(KUBE_CLIENT.clone(), &ACCOUNT_NAMESPACE);
+    let secrets: Api<Secret> = Api::namespaced(KUBE_CLIENT.clone(), &ACCOUNT_NAMESPACE);
+
+    let account = match accounts.get(&account_name(uid)).await {
+        Ok(account) => account,
+        Err(KubeError::Api(ae)) if ae.code == 404 => return Ok(()),
+        Err(e) => return Err(e.into()),
+    };
+    for name in account.secrets.unwrap_or_default().into_iter().filter_map(|x| x.name) {
+        match secrets.delete(&name, &DeleteParams::default()).await {
+            Err(KubeError::Api(ae)) if ae.code == 404 => (),
+            res => {
+                res?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Delete the account of user `uid` with its tokens
+pub async fn delete_account(uid: &Uuid) -> Result<(), ApiError> {
+    let accounts: Api<ServiceAccount> = Api::namespaced(KUBE_CLIENT.clone(), &ACCOUNT_NAMESPACE);
+    match accounts.delete(&account_name(uid), &DeleteParams::default()).await {
+        Err(KubeError::Api(ae)) if ae.code == 404 => Ok(()),
+        res => res.map(|_| ()).map_err(ApiError::from),
+    }
+}
+
+/// Kubeconfig of user `uid` with one context per namespace in `namespaces`.
+/// The server is `KUBE_PUBLIC_SERVER`, or the api server pegasus talks to.
+pub async fn render_kubeconfig(uid: &Uuid, namespaces: &[String]) -> Result<String, ApiError> {
+    let (token, ca) = account_token(uid).await?;
+    let server = match std::env::var("KUBE_PUBLIC_SERVER") {
+        Ok(server) => server,
+        Err(_) => kube::config::Configuration::infer().await?.base_path,
+    };
+
+    let user = account_name(uid);
+    let contexts: Vec<serde_json::Value> = namespaces
+        .iter()
+        .map(|ns| {
+            json!({
+                "name": ns,
+                "context": {
+                    "cluster": "pegasus",
+                    "namespace": ns,
+                    "user": &user,
+                },
+            })
+        })
+        .collect();
+    let config = json!({
+        "apiVersion": "v1",
+        "kind": "Config",
+        "clusters": [
+            {
+                "name": "pegasus",
+                "cluster": {
+                    "server": server,
+                    "certificate-authority-data": base64::encode(&ca),
+                },
+            },
+        ],
+        "users": [
+            {
+                "name": &user,
+                "user": {
+                    "token": token,
+                },
+            },
+        ],
+        "contexts": contexts,
+        "current-context": namespaces.first(),
+    });
+    serde_yaml::to_string(&config).map_err(|e| ApiError::new(500, format!("Yaml error: {}", e)))
+}
+
 /// Delete namespace with name `ns`
 pub async fn delete_ns(ns: &str) -> Result<String, ApiError> {
     let resource: Api<Namespace> = Api::all(KUBE_CLIENT.clone());
@@ -132,6 +648,22 @@ pub async fn get_pod_within(ns: &str) -> Result<BTreeMap<String, Vec<ResourceSta
     Ok(result)
 }
 
+/// All the resources within a namespace, the lists are requested concurrently
+pub async fn get_resources_within(ns: &str) -> Result<NamespaceResources, ApiError> {
+    let (deploy, service, pod, ingress) = try_join!(
+        get_deploy_within(ns),
+        get_svc_within(ns),
+        get_pod_within(ns),
+        get_ing_within(ns),
+    )?;
+    Ok(NamespaceResources {
+        deploy,
+        service,
+        pod,
+        ingress,
+    })
+}
+
 /// Create a deployment with basic config
 pub async fn create_deploy(deploy_info: DeployInfo) -> Result<Deployment, ApiError> {
     let resource: Api<Deployment> = Api::namespaced(KUBE_CLIENT.clone(), &deploy_info.namespace);
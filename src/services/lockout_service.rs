@@ -0,0 +1,140 @@
+//! Failed sign in tracking and temporary lockout stored in redis.
+//!
+//! Failures are counted per email and per client ip within `FAIL_WINDOW`
+//! seconds. Once a counter reaches the threshold of its kind the key is
+//! locked for `LOCK_BASE` seconds, doubled by every further failure and
+//! capped by `LOCK_MAX`.
+
+use actix::Addr;
+use actix_redis::{RedisActor, RespValue};
+
+use crate::errors::ApiError;
+use crate::models::lockout::{LockKind, Lockout};
+use crate::utils::redis::{integer, send, string};
+
+const PREFIX: &str = "pegasus:login";
+const FAIL_WINDOW: i64 = 3600;
+const LOCK_BASE: i64 = 30;
+const LOCK_MAX: i64 = 3600;
+
+/// Reject the sign in if the email or the ip is locked
+pub async fn check(redis: &Addr<RedisActor>, email: &str, ip: &str) -> Result<(), ApiError> {
+    for (kind, value) in [(LockKind::Email, email), (LockKind::Ip, ip)].iter() {
+        let ttl = integer(send(redis, &["TTL", &lock_key(*kind, value)]).await?);
+        if ttl > 0 {
+            return Err(ApiError::new(
+                429,
+                format!(
+                    "Too many failed sign in attempts, retry after {} seconds",
+                    ttl
+                ),
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Count a failed sign in and lock the keys over threshold
+pub async fn record_failure(
+    redis: &Addr<RedisActor>,
+    email: &str,
+    ip: &str,
+) -> Result<(), ApiError> {
+    for (kind, value) in [(LockKind::Email, email), (LockKind::Ip, ip)].iter() {
+        let key = fail_key(*kind, value);
+        let failures = integer(send(redis, &["INCR", &key]).await?);
+        if failures == 1 {
+            send(redis, &["EXPIRE", &key, &FAIL_WINDOW.to_string()]).await?;
+        }
+
+        let over = failures - kind.threshold();
+        if over >= 0 {
+            let secs = (LOCK_BASE << over.min(10)).min(LOCK_MAX);
+            send(
+                redis,
+                &[
+                    "SET",
+                    &lock_key(*kind, value),
+                    &failures.to_string(),
+                    "EX",
+                    &secs.to_string(),
+                ],
+            )
+            .await?;
+            warn!(
+                target: "audit",
+                "Locked sign in of {} {} for {}s after {} failures",
+                kind.as_str(),
+                value,
+                secs,
+                failures
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Remove the failure counter and the lock of a key
+pub async fn clear(redis: &Addr<RedisActor>, kind: LockKind, value: &str) -> Result<(), ApiError> {
+    send(
+        redis,
+        &["DEL", &fail_key(kind, value), &lock_key(kind, value)],
+    )
+    .await?;
+    Ok(())
+}
+
+/// All the active lockouts
+pub async fn list(redis: &Addr<RedisActor>) -> Result<Vec<Lockout>, ApiError> {
+    let pattern = format!("{}:lock:*", PREFIX);
+    let mut keys = Vec::new();
+    let mut cursor = "0".to_owned();
+    loop {
+        let reply = send(redis, &["SCAN", &cursor, "MATCH", &pattern, "COUNT", "100"]).await?;
+        match reply {
+            RespValue::Array(mut items) if items.len() == 2 => {
+                if let RespValue::Array(batch) = items.pop().unwrap() {
+                    keys.extend(batch.into_iter().filter_map(string));
+                }
+                cursor = items.pop().and_then(string).unwrap_or_default();
+            }
+            _ => break,
+        }
+        if cursor == "0" || cursor.is_empty() {
+            break;
+        }
+    }
+
+    let mut results = Vec::new();
+    for key in keys.iter() {
+        let mut parts = key[pattern.len() - 1..].splitn(2, ':');
+        let (kind, value) = match (parts.next().and_then(LockKind::parse), parts.next()) {
+            (Some(kind), Some(value)) => (kind, value),
+            _ => continue,
+        };
+        let ttl = integer(send(redis, &["TTL", key]).await?);
+        let failures = send(redis, &["GET", key])
+            .await
+            .ok()
+            .and_then(string)
+            .and_then(|x| x.parse().ok())
+            .unwrap_or(0);
+        if ttl > 0 {
+            results.push(Lockout {
+                kind,
+                value: value.to_owned(),
+                failures,
+                ttl,
+            });
+        }
+    }
+    Ok(results)
+}
+
+fn fail_key(kind: LockKind, value: &str) -> String {
+    format!("{}:fail:{}:{}", PREFIX, kind.as_str(), value)
+}
+
+fn lock_key(kind: LockKind, value: &str) -> String {
+    format!("{}:lock:{}:{}", PREFIX, kind.as_str(), value)
+}
@@ -1,4 +1,8 @@
+pub mod auth_service;
 pub mod email_service;
 pub mod git_service;
 pub mod kube_service;
+pub mod lockout_service;
+pub mod reconcile_service;
 pub mod registry_service;
+pub mod session_service;
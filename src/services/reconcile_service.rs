@@ -0,0 +1,287 @@
+use chrono::{Duration, NaiveDateTime, Utc};
+use uuid::Uuid;
+
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+
+use crate::errors::ApiError;
+use crate::models::budget::DepartBudget;
+use crate::models::kube::ManagedNamespace;
+use crate::models::member::{MemberRole, NamespaceMember};
+use crate::models::namespace::{Namespace, NamespaceInfo};
+use crate::models::network::{NamespaceNetwork, NetworkProfile};
+use crate::models::quota::{NamespaceQuota, QuotaPolicy};
+use crate::models::request::ResourceRequest;
+use crate::models::user::User;
+use crate::services::kube_service;
+
+/// Seconds a new namespace may stay unrecorded, `create_ns`
+/// records it only once it is provisioned
+const GRACE_SECS: i64 = 300;
+/// Seconds between two background runs when `RECONCILE_INTERVAL` is unset
+const DEFAULT_INTERVAL: u64 = 600;
+
+/// What to do with labeled namespaces no valid record points to
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum UnrecordedAction {
+    Report,
+    /// Record it for the user of its owner label
+    Adopt,
+    Delete,
+}
+
+/// What to do with valid records whose namespace is gone
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum MissingAction {
+    Report,
+    Invalidate,
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct RepairPolicy {
+    pub unrecorded: UnrecordedAction,
+    pub missing: MissingAction,
+}
+
+impl RepairPolicy {
+    /// Policy of the background runs from `RECONCILE_UNRECORDED` and
+    /// `RECONCILE_MISSING`, nothing is repaired by default
+    pub fn configured() -> RepairPolicy {
+        let unrecorded = match std::env::var("RECONCILE_UNRECORDED")
+            .as_ref()
+            .map(|x| x.as_str())
+        {
+            Ok("Adopt") => UnrecordedAction::Adopt,
+            Ok("Delete") => UnrecordedAction::Delete,
+            _ => UnrecordedAction::Report,
+        };
+        let missing = match std::env::var("RECONCILE_MISSING")
+            .as_ref()
+            .map(|x| x.as_str())
+        {
+            Ok("Invalidate") => MissingAction::Invalidate,
+            _ => MissingAction::Report,
+        };
+        RepairPolicy {
+            unrecorded,
+            missing,
+        }
+    }
+}
+
+/// Drift between the `namespaces` table and the cluster, `repairs`
+/// holds the outcome of each repair by namespace
+#[derive(Serialize)]
+pub struct DriftReport {
+    pub checked_at: NaiveDateTime,
+    pub unrecorded: Vec<ManagedNamespace>,
+    pub missing: Vec<Namespace>,
+    pub repairs: BTreeMap<String, String>,
+}
+
+impl DriftReport {
+    pub fn is_clean(&self) -> bool {
+        self.unrecorded.is_empty() && self.missing.is_empty()
+    }
+}
+
+/// Compare the namespaces labeled `dispense=pegasus` with the valid records.
+/// Terminating namespaces are neither unrecorded nor missing yet.
+pub async fn scan() -> Result<DriftReport, ApiError> {
+    let cluster = kube_service::list_managed_ns().await?;
+    let records = Namespace::list_valid()?;
+
+    let missing = {
+        let present: HashSet<&str> = cluster.iter().map(|x| x.name.as_str()).collect();
+        records
+            .iter()
+            .filter(|x| !present.contains(x.namespace.as_str()))
+            .cloned()
+            .collect()
+    };
+    let recorded: HashSet<&str> = records.iter().map(|x| x.namespace.as_str()).collect();
+    let settled = Utc::now() - Duration::seconds(GRACE_SECS);
+    let unrecorded = cluster
+        .into_iter()
+        .filter(|x| x.active && !recorded.contains(x.name.as_str()))
+        .filter(|x| x.created_at.is_none_or(|t| t < settled))
+        .collect();
+
+    Ok(DriftReport {
+        checked_at: Utc::now().naive_utc(),
+        unrecorded,
+        missing,
+        repairs: BTreeMap::new(),
+    })
+}
+
+/// Scan and repair the drift following `policy`, then grant the access
+/// of the recorded namespaces again. A failed repair is reported and
+/// does not stop the others.
+pub async fn reconcile(policy: RepairPolicy) -> Result<DriftReport, ApiError> {
+    let mut report = scan().await?;
+
+    for ns in &report.unrecorded {
+        let res = match policy.unrecorded {
+            UnrecordedAction::Report => continue,
+            UnrecordedAction::Adopt => adopt(ns).await.map(|_| "Adopted".to_owned()),
+            UnrecordedAction::Delete => kube_service::delete_ns(&ns.name).await,
+        };
+        report.repairs.insert(ns.name.clone(), outcome(res));
+    }
+    for ns in &report.missing {
+        let res = match policy.missing {
+            MissingAction::Report => continue,
+            MissingAction::Invalidate => invalidate(ns).await.map(|_| "Invalidated".to_owned()),
+        };
+        report.repairs.insert(ns.namespace.clone(), outcome(res));
+    }
+    // Bindings follow the records, namespaces provisioned before access
+    // control or left behind by a failed grant get theirs here
+    let missing: HashSet<String> = report.missing.iter().map(|x| x.namespace.clone()).collect();
+    for ns in Namespace::list_valid()? {
+        if missing.contains(&ns.namespace) {
+            continue;
+        }
+        if let Err(e) = regrant(&ns).await {
+            report
+                .repairs
+                .insert(ns.namespace, format!("Failed to grant access: {}", e.msg));
+        }
+    }
+    Ok(report)
+}
+
+async fn regrant(ns: &Namespace) -> Result<(), ApiError> {
+    let owner = User::find(ns.uid)?;
+    grant_access(&ns.namespace, &owner).await
+}
+
+fn outcome(res: Result<String, ApiError>) -> String {
+    match res {
+        Ok(msg) => msg,
+        Err(e) => format!("Failed: {}", e.msg),
+    }
+}
+
+/// Record namespace `ns` for its owner. Only provisioned namespaces carry
+/// the owner label, so its quota is applied again and recorded. Like
+/// `create_ns` the namespace must fit the owner's namespace limit and the
+/// department budgets, it is left unrecorded otherwise.
+async fn adopt(ns: &ManagedNamespace) -> Result<(), ApiError> {
+    let uid = ns
+        .owner
+        .ok_or_else(|| ApiError::new(400, format!("Namespace {} has no owner label", ns.name)))?;
+    let owner = User::find(uid)?;
+    let spec = QuotaPolicy::resolve(&owner)?;
+    let limit = ResourceRequest::namespace_limit(&owner)?;
+
+    let info = NamespaceInfo {
+        uid,
+        ns: ns.name.clone(),
+    };
+    let record = Namespace::create(&info, Some(limit)).map_err(|e| over("limit", e))?;
+    let previous = match DepartBudget::grant(&owner, record.id, &spec) {
+        Ok((_, previous)) => previous,
+        Err(e) => {
+            Namespace::delete(&uid, &ns.name)?;
+            return Err(over("budget", e));
+        }
+    };
+    if let Err(e) = kube_service::apply_quota(&ns.name, &spec).await {
+        NamespaceQuota::restore(record.id, previous.as_ref())?;
+        Namespace::delete(&uid, &ns.name)?;
+        return Err(e);
+    }
+    NamespaceNetwork::set(record.id, NetworkProfile::configured())?;
+    Ok(())
+}
+
+/// Tell the refused grants apart in the repairs
+fn over(what: &str, e: ApiError) -> ApiError {
+    match e.status_code {
+        403 => ApiError::new(403, format!("Over {}, {}", what, e.msg)),
+        _ => e,
+    }
+}
+
+/// Release the record of namespace `ns` like `delete_ns` does
+async fn invalidate(ns: &Namespace) -> Result<(), ApiError> {
+    kube_service::remove_network_rules(ns).await?;
+    Namespace::delete(&ns.uid, &ns.namespace)?;
+    if let Err(e) = kube_service::rotate_token(&ns.uid).await {
+        error!("Failed to rotate the token of {}: {}", ns.uid, e);
+    }
+    Ok(())
+}
+
+/// Bind the owner and the maintainers of namespace `ns` as editors, its
+/// viewers and the admins of the owner's department as viewers
+pub async fn grant_access(ns: &str, owner: &User) -> Result<(), ApiError> {
+    let mut editors = vec![owner.id];
+    let mut viewers: Vec<Uuid> = match owner.belong_to {
+        Some(depart) => User::admins_of(depart)?.into_iter().map(|x| x.id).collect(),
+        None => Vec::new(),
+    };
+    for (uid, role) in NamespaceMember::roles_in(ns)? {
+        match role {
+            MemberRole::Maintainer => editors.push(uid),
+            MemberRole::Viewer => viewers.push(uid),
+            MemberRole::Owner => (),
+        }
+    }
+    viewers.retain(|x| !editors.contains(x));
+    kube_service::grant_access(ns, &editors, &viewers).await
+}
+
+/// Grant the access of the valid namespaces `namespaces` again after their
+/// members or department admins changed, a failure is logged and left to
+/// the next `reconcile`
+pub async fn grant_access_all(namespaces: &BTreeSet<String>) {
+    for ns in namespaces.iter() {
+        let res = match Namespace::find_valid(ns).and_then(|x| User::find(x.uid)) {
+            Ok(owner) => grant_access(ns, &owner).await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = res {
+            error!("Failed to grant the access of {}: {}", ns, e);
+        }
+    }
+}
+
+/// Run `reconcile` with the configured policy every `RECONCILE_INTERVAL`
+/// seconds, `0` disables the background runs
+pub fn spawn() {
+    let period = std::env::var("RECONCILE_INTERVAL")
+        .ok()
+        .and_then(|x| x.parse::<u64>().ok())
+        .unwrap_or(DEFAULT_INTERVAL);
+    if period == 0 {
+        return;
+    }
+
+    actix_rt::spawn(async move {
+        let period = std::time::Duration::from_secs(period);
+        let mut ticks = tokio::time::interval_at(tokio::time::Instant::now() + period, period);
+        loop {
+            ticks.tick().await;
+            match reconcile(RepairPolicy::configured()).await {
+                Ok(report) if report.is_clean() && report.repairs.is_empty() => (),
+                Ok(report) => warn!(
+                    "Namespace drift: unrecorded {:?}, missing {:?}, repairs {:?}",
+                    report
+                        .unrecorded
+                        .iter()
+                        .map(|x| x.name.as_str())
+                        .collect::<Vec<_>>(),
+                    report
+                        .missing
+                        .iter()
+                        .map(|x| x.namespace.as_str())
+                        .collect::<Vec<_>>(),
+                    report.repairs
+                ),
+                Err(e) => error!("Failed to reconcile namespaces: {}", e),
+            }
+        }
+    });
+}
@@ -0,0 +1,141 @@
+//! Signed in session registry stored in redis.
+//!
+//! `RedisSession` keeps the session state under a key only known to the
+//! cookie, so every sign in also gets a random `session_id` saved in the
+//! state and a metadata hash `pegasus:session:<id>`. The ids of a user
+//! are collected in the set `pegasus:sessions:<uid>`. Deleting the hash
+//! revokes the session, the `Identity` extractor rejects session states
+//! whose hash is gone.
+
+use actix::Addr;
+use actix_redis::RedisActor;
+use chrono::Utc;
+use std::collections::HashMap;
+use std::str::FromStr;
+use uuid::Uuid;
+
+use crate::errors::ApiError;
+use crate::models::session::SessionInfo;
+use crate::utils::redis::{integer, send, strings};
+
+const PREFIX: &str = "pegasus:session";
+/// Idle seconds before the metadata expires, same as the cookie max age
+const SESSION_TTL: i64 = 86400;
+/// Minimum seconds between two `last_seen` updates
+const TOUCH_INTERVAL: i64 = 60;
+
+/// Register a new session of user `uid`, returns the session id
+pub async fn start(
+    redis: &Addr<RedisActor>,
+    uid: &Uuid,
+    ip: &str,
+    user_agent: &str,
+) -> Result<String, ApiError> {
+    let id = Uuid::new_v4().to_simple().to_string();
+    let now = Utc::now().timestamp().to_string();
+    let key = session_key(&id);
+
+    send(
+        redis,
+        &[
+            "HSET",
+            &key,
+            "uid",
+            &uid.to_string(),
+            "ip",
+            ip,
+            "user_agent",
+            user_agent,
+            "created_at",
+            &now,
+            "last_seen",
+            &now,
+        ],
+    )
+    .await?;
+    send(redis, &["EXPIRE", &key, &SESSION_TTL.to_string()]).await?;
+    send(redis, &["SADD", &user_key(uid), &id]).await?;
+    Ok(id)
+}
+
+/// Owner of an active session, `last_seen` and the expiry are refreshed
+/// at most once per `TOUCH_INTERVAL`. `None` if the session is revoked.
+pub async fn touch(redis: &Addr<RedisActor>, id: &str) -> Result<Option<Uuid>, ApiError> {
+    let key = session_key(id);
+    let mut fields = strings(send(redis, &["HMGET", &key, "uid", "last_seen"]).await?).into_iter();
+    let (uid, last_seen) = match (fields.next(), fields.next()) {
+        (Some(uid), Some(last_seen)) => (uid, last_seen),
+        _ => return Ok(None),
+    };
+
+    let now = Utc::now().timestamp();
+    if now - last_seen.parse().unwrap_or(0) >= TOUCH_INTERVAL {
+        send(redis, &["HSET", &key, "last_seen", &now.to_string()]).await?;
+        send(redis, &["EXPIRE", &key, &SESSION_TTL.to_string()]).await?;
+    }
+    Ok(Uuid::from_str(&uid).ok())
+}
+
+pub async fn find(redis: &Addr<RedisActor>, id: &str) -> Result<Option<SessionInfo>, ApiError> {
+    let mut items = strings(send(redis, &["HGETALL", &session_key(id)]).await?).into_iter();
+    let mut fields = HashMap::new();
+    while let (Some(field), Some(value)) = (items.next(), items.next()) {
+        fields.insert(field, value);
+    }
+    Ok(SessionInfo::from_fields(id, &fields))
+}
+
+/// Active sessions of user `uid`, expired ids are pruned from the set
+pub async fn list(redis: &Addr<RedisActor>, uid: &Uuid) -> Result<Vec<SessionInfo>, ApiError> {
+    let ids = strings(send(redis, &["SMEMBERS", &user_key(uid)]).await?);
+
+    let mut results = Vec::new();
+    for id in ids.iter() {
+        match find(redis, id).await? {
+            Some(info) => results.push(info),
+            None => {
+                send(redis, &["SREM", &user_key(uid), id]).await?;
+            }
+        }
+    }
+    results.sort_by_key(|x| std::cmp::Reverse(x.last_seen));
+    Ok(results)
+}
+
+/// Revoke one session of user `uid`, returns false if not found
+pub async fn revoke(redis: &Addr<RedisActor>, uid: &Uuid, id: &str) -> Result<bool, ApiError> {
+    let removed = integer(send(redis, &["SREM", &user_key(uid), id]).await?);
+    let deleted = integer(send(redis, &["DEL", &session_key(id)]).await?);
+    Ok(removed + deleted > 0)
+}
+
+/// Revoke all the sessions of user `uid` but `except`, returns
+/// the number of revoked sessions
+pub async fn revoke_all(
+    redis: &Addr<RedisActor>,
+    uid: &Uuid,
+    except: Option<&str>,
+) -> Result<usize, ApiError> {
+    let ids = strings(send(redis, &["SMEMBERS", &user_key(uid)]).await?);
+
+    let mut count = 0;
+    for id in ids.iter().filter(|id| Some(id.as_str()) != except) {
+        if revoke(redis, uid, id).await? {
+            count += 1;
+        }
+    }
+    Ok(count)
+}
+
+/// Session id saved in the session state
+pub fn current(sess: &actix_session::Session) -> Option<String> {
+    sess.get("session_id").ok().flatten()
+}
+
+fn session_key(id: &str) -> String {
+    format!("{}:{}", PREFIX, id)
+}
+
+fn user_key(uid: &Uuid) -> String {
+    format!("{}s:{}", PREFIX, uid)
+}
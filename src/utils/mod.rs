@@ -1,6 +1,10 @@
+pub mod pwd;
+pub mod redis;
 pub mod schema;
+pub mod totp;
 mod util;
 
+pub use util::client_ip;
 pub use util::DOMAIN;
 pub use util::EMAIL_DOMAIN;
 pub use util::ENGINE_API;
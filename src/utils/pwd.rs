@@ -0,0 +1,19 @@
+use argon2::Config;
+use rand::Rng;
+
+use crate::errors::ApiError;
+
+/// Hash a secret with argon2 and a random salt, used for
+/// user passwords and api tokens
+pub fn hash(secret: &str) -> Result<String, ApiError> {
+    let salt: [u8; 32] = rand::thread_rng().gen();
+    let config = Config::default();
+
+    argon2::hash_encoded(secret.as_bytes(), &salt, &config)
+        .map_err(|err| ApiError::new(500, format!("Failed to hash password: {}", err)))
+}
+
+pub fn verify(hash: &str, secret: &str) -> Result<bool, ApiError> {
+    argon2::verify_encoded(hash, secret.as_bytes())
+        .map_err(|e| ApiError::new(500, format!("Failed to verfify password: {}", e)))
+}
@@ -0,0 +1,40 @@
+use actix::Addr;
+use actix_redis::{Command, RedisActor, RespValue};
+
+use crate::errors::ApiError;
+
+/// Send a raw command, redis errors are turned into `ApiError`
+pub async fn send(redis: &Addr<RedisActor>, args: &[&str]) -> Result<RespValue, ApiError> {
+    let cmd = RespValue::Array(
+        args.iter()
+            .map(|x| RespValue::BulkString(x.as_bytes().to_vec()))
+            .collect(),
+    );
+    match redis.send(Command(cmd)).await?? {
+        RespValue::Error(e) => Err(ApiError::new(500, format!("Redis error: {}", e))),
+        value => Ok(value),
+    }
+}
+
+pub fn integer(value: RespValue) -> i64 {
+    match value {
+        RespValue::Integer(x) => x,
+        _ => 0,
+    }
+}
+
+pub fn string(value: RespValue) -> Option<String> {
+    match value {
+        RespValue::BulkString(x) => String::from_utf8(x).ok(),
+        RespValue::SimpleString(x) => Some(x),
+        _ => None,
+    }
+}
+
+/// String items of an array reply
+pub fn strings(value: RespValue) -> Vec<String> {
+    match value {
+        RespValue::Array(items) => items.into_iter().filter_map(string).collect(),
+        _ => Vec::new(),
+    }
+}
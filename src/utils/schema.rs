@@ -1,8 +1,56 @@
+table! {
+    use crate::models::token::TokenScopeMapping;
+    use diesel::sql_types::{Bool, Nullable, Text, Timestamp, Uuid, Varchar};
+
+    api_tokens (id) {
+        id -> Uuid,
+        uid -> Uuid,
+        name -> Varchar,
+        token_hash -> Text,
+        scope -> TokenScopeMapping,
+        expires_at -> Nullable<Timestamp>,
+        last_used_at -> Nullable<Timestamp>,
+        is_valid -> Bool,
+        created_at -> Timestamp,
+    }
+}
+
+table! {
+    use crate::models::user::ClusterRoleMapping;
+    use diesel::sql_types::{Bool, Int4, Int8, Nullable, Text, Timestamp, Uuid, Varchar};
+
+    audit_events (id) {
+        id -> Int8,
+        actor -> Nullable<Uuid>,
+        impersonator -> Nullable<Uuid>,
+        role -> Nullable<ClusterRoleMapping>,
+        method -> Varchar,
+        path -> Text,
+        target -> Nullable<Text>,
+        payload -> Nullable<Text>,
+        status -> Int4,
+        success -> Bool,
+        ip -> Varchar,
+        created_at -> Timestamp,
+    }
+}
+
+table! {
+    department_budgets (department) {
+        department -> Int4,
+        cpu -> Nullable<Varchar>,
+        memory -> Nullable<Varchar>,
+        storage -> Nullable<Varchar>,
+        updated_at -> Timestamp,
+    }
+}
+
 table! {
     departments (id) {
         id -> Int4,
         name -> Varchar,
         admin -> Nullable<Uuid>,
+        parent -> Nullable<Int4>,
     }
 }
 
@@ -14,6 +62,9 @@ table! {
         is_admin -> Bool,
         expires_at -> Timestamp,
         created_at -> Timestamp,
+        invited_by -> Nullable<Uuid>,
+        accepted_at -> Nullable<Timestamp>,
+        revoked_at -> Nullable<Timestamp>,
     }
 }
 
@@ -26,6 +77,92 @@ table! {
     }
 }
 
+table! {
+    use crate::models::member::MemberRoleMapping;
+    use diesel::sql_types::{Int4, Nullable, Timestamp, Uuid};
+
+    namespace_members (namespace_id, uid) {
+        namespace_id -> Int4,
+        uid -> Uuid,
+        role -> MemberRoleMapping,
+        added_by -> Nullable<Uuid>,
+        created_at -> Timestamp,
+    }
+}
+
+table! {
+    use crate::models::network::NetworkProfileMapping;
+    use diesel::sql_types::{Int4, Timestamp};
+
+    namespace_networks (namespace_id) {
+        namespace_id -> Int4,
+        profile -> NetworkProfileMapping,
+        updated_at -> Timestamp,
+    }
+}
+
+table! {
+    namespace_quotas (namespace_id) {
+        namespace_id -> Int4,
+        cpu -> Varchar,
+        memory -> Varchar,
+        pods -> Int4,
+        services -> Int4,
+        default_cpu -> Varchar,
+        default_memory -> Varchar,
+        updated_at -> Timestamp,
+        storage -> Varchar,
+    }
+}
+
+table! {
+    network_rules (id) {
+        id -> Int4,
+        from_ns -> Int4,
+        to_ns -> Int4,
+        created_by -> Nullable<Uuid>,
+        created_at -> Timestamp,
+    }
+}
+
+table! {
+    password_resets (id) {
+        id -> Uuid,
+        email -> Varchar,
+        used -> Bool,
+        expires_at -> Timestamp,
+        created_at -> Timestamp,
+    }
+}
+
+table! {
+    use crate::models::user::ClusterRoleMapping;
+    use diesel::sql_types::{Int4, Nullable, Varchar};
+
+    quota_policies (id) {
+        id -> Int4,
+        role -> Nullable<ClusterRoleMapping>,
+        department -> Nullable<Int4>,
+        cpu -> Varchar,
+        memory -> Varchar,
+        pods -> Int4,
+        services -> Int4,
+        default_cpu -> Varchar,
+        default_memory -> Varchar,
+        storage -> Varchar,
+        namespaces -> Int4,
+    }
+}
+
+table! {
+    recovery_codes (id) {
+        id -> Int4,
+        uid -> Uuid,
+        code_hash -> Text,
+        used -> Bool,
+    }
+}
+
 table! {
     repositories (id) {
         id -> Int4,
@@ -36,6 +173,26 @@ table! {
     }
 }
 
+table! {
+    use crate::models::request::{RequestKindMapping, RequestStatusMapping};
+    use diesel::sql_types::{Int4, Nullable, Text, Timestamp, Uuid, Varchar};
+
+    resource_requests (id) {
+        id -> Int4,
+        uid -> Uuid,
+        namespace -> Nullable<Varchar>,
+        kind -> RequestKindMapping,
+        amount -> Varchar,
+        justification -> Text,
+        status -> RequestStatusMapping,
+        depart_reviewer -> Nullable<Uuid>,
+        cluster_reviewer -> Nullable<Uuid>,
+        comment -> Nullable<Text>,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
+    }
+}
+
 table! {
     tags (id) {
         id -> Int4,
@@ -46,9 +203,18 @@ table! {
 }
 
 table! {
-    use crate::models::user::ClusterRoleMapping;
-    use diesel::sql_types::{Uuid, Varchar, Text, Nullable,
-                            Int4, Timestamp};
+    totp_credentials (uid) {
+        uid -> Uuid,
+        secret -> Varchar,
+        enabled -> Bool,
+        last_step -> Int8,
+        created_at -> Timestamp,
+    }
+}
+
+table! {
+    use crate::models::user::{AuthProviderMapping, ClusterRoleMapping};
+    use diesel::sql_types::{Int4, Nullable, Text, Timestamp, Uuid, Varchar};
 
     users (id) {
         id -> Uuid,
@@ -59,16 +225,41 @@ table! {
         belong_to -> Nullable<Int4>,
         created_at -> Timestamp,
         updated_at -> Nullable<Timestamp>,
+        provider -> AuthProviderMapping,
     }
 }
 
+joinable!(api_tokens -> users (uid));
+joinable!(department_budgets -> departments (department));
+joinable!(invitations -> users (invited_by));
+joinable!(namespace_members -> namespaces (namespace_id));
+joinable!(namespace_members -> users (uid));
+joinable!(namespace_networks -> namespaces (namespace_id));
+joinable!(namespace_quotas -> namespaces (namespace_id));
+joinable!(namespaces -> users (uid));
+joinable!(quota_policies -> departments (department));
+joinable!(recovery_codes -> users (uid));
+joinable!(resource_requests -> users (uid));
+joinable!(totp_credentials -> users (uid));
 joinable!(users -> departments (belong_to));
 
 allow_tables_to_appear_in_same_query!(
+    api_tokens,
+    audit_events,
+    department_budgets,
     departments,
     invitations,
+    namespace_members,
+    namespace_networks,
+    namespace_quotas,
     namespaces,
+    network_rules,
+    password_resets,
+    quota_policies,
+    recovery_codes,
     repositories,
+    resource_requests,
     tags,
+    totp_credentials,
     users,
 );
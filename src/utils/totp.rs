@@ -0,0 +1,161 @@
+use rand::Rng;
+use reqwest::Url;
+use ring::hmac;
+
+/// RFC 6238 time step in seconds and code length
+const STEP: i64 = 30;
+const DIGITS: u32 = 6;
+const BASE32: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+/// Random 160 bits secret in base32
+pub fn generate_secret() -> String {
+    let secret: [u8; 20] = rand::thread_rng().gen();
+    base32_encode(&secret)
+}
+
+/// `otpauth` uri rendered as QR code by authenticator apps
+pub fn uri(issuer: &str, account: &str, secret: &str) -> String {
+    let mut url = Url::parse("otpauth://totp/").unwrap();
+    url.set_path(&format!("{}:{}", issuer, account));
+    url.query_pairs_mut()
+        .append_pair("secret", secret)
+        .append_pair("issuer", issuer)
+        .append_pair("digits", &DIGITS.to_string())
+        .append_pair("period", &STEP.to_string());
+    url.into_string()
+}
+
+pub fn current_step() -> i64 {
+    chrono::Utc::now().timestamp() / STEP
+}
+
+/// Time step matching `code` within one step of clock drift,
+/// only steps after `last_step` are accepted to prevent replay
+pub fn verify(secret: &str, code: &str, last_step: i64) -> Option<i64> {
+    let key = base32_decode(secret)?;
+    let code: u32 = code.trim().parse().ok()?;
+    let now = current_step();
+
+    (now - 1..=now + 1)
+        .filter(|step| *step > last_step)
+        .find(|step| hotp(&key, *step as u64) == code)
+}
+
+/// RFC 4226 HOTP value of `counter`
+fn hotp(key: &[u8], counter: u64) -> u32 {
+    let key = hmac::Key::new(hmac::HMAC_SHA1_FOR_LEGACY_USE_ONLY, key);
+    let tag = hmac::sign(&key, &counter.to_be_bytes());
+    let digest = tag.as_ref();
+
+    let offset = (digest[digest.len() - 1] & 0x0f) as usize;
+    let bin = (u32::from(digest[offset]) & 0x7f) << 24
+        | u32::from(digest[offset + 1]) << 16
+        | u32::from(digest[offset + 2]) << 8
+        | u32::from(digest[offset + 3]);
+    bin % 10u32.pow(DIGITS)
+}
+
+fn base32_encode(data: &[u8]) -> String {
+    let mut result = String::new();
+    let (mut buffer, mut bits) = (0u32, 0);
+    for byte in data {
+        buffer = (buffer << 8) | u32::from(*byte);
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            result.push(BASE32[((buffer >> bits) & 0x1f) as usize] as char);
+        }
+    }
+    if bits > 0 {
+        result.push(BASE32[((buffer << (5 - bits)) & 0x1f) as usize] as char);
+    }
+    result
+}
+
+fn base32_decode(data: &str) -> Option<Vec<u8>> {
+    let mut result = Vec::new();
+    let (mut buffer, mut bits) = (0u32, 0);
+    for c in data.trim_end_matches('=').bytes() {
+        let value = BASE32.iter().position(|x| *x == c.to_ascii_uppercase())? as u32;
+        buffer = (buffer << 5) | value;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            result.push((buffer >> bits) as u8);
+        }
+    }
+    Some(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RFC_KEY: &[u8] = b"12345678901234567890";
+
+    #[test]
+    fn hotp_matches_rfc4226() {
+        let expected = [
+            755224, 287082, 359152, 969429, 338314, 254676, 287922, 162583, 399871, 520489,
+        ];
+        for (counter, code) in expected.iter().enumerate() {
+            assert_eq!(hotp(RFC_KEY, counter as u64), *code);
+        }
+    }
+
+    #[test]
+    fn totp_matches_rfc6238() {
+        // SHA1 vectors of appendix B truncated to `DIGITS`
+        let expected = [
+            (59, 287082),
+            (1111111109, 81804),
+            (1111111111, 50471),
+            (1234567890, 5924),
+            (2000000000, 279037),
+            (20000000000, 353130),
+        ];
+        for (time, code) in expected.iter() {
+            assert_eq!(hotp(RFC_KEY, (*time / STEP) as u64), *code);
+        }
+    }
+
+    #[test]
+    fn base32_matches_rfc4648() {
+        let expected = [
+            ("f", "MY"),
+            ("fo", "MZXQ"),
+            ("foo", "MZXW6"),
+            ("foob", "MZXW6YQ"),
+            ("fooba", "MZXW6YTB"),
+            ("foobar", "MZXW6YTBOI"),
+        ];
+        for (plain, encoded) in expected.iter() {
+            assert_eq!(base32_encode(plain.as_bytes()), *encoded);
+            assert_eq!(base32_decode(encoded).unwrap(), plain.as_bytes());
+        }
+        assert_eq!(base32_decode("mzxw6===").unwrap(), b"foo");
+        assert!(base32_decode("MZ1W6").is_none());
+    }
+
+    #[test]
+    fn base32_round_trip() {
+        for _ in 0..32 {
+            let secret = generate_secret();
+            let key = base32_decode(&secret).unwrap();
+            assert_eq!(key.len(), 20);
+            assert_eq!(base32_encode(&key), secret);
+        }
+    }
+
+    #[test]
+    fn verify_rejects_replay() {
+        let secret = base32_encode(RFC_KEY);
+        let step = current_step();
+        let code = format!("{:06}", hotp(RFC_KEY, step as u64));
+
+        let accepted = verify(&secret, &code, 0).unwrap();
+        assert!((step - 1..=step + 1).contains(&accepted));
+        assert_eq!(verify(&secret, &code, step + 1), None);
+        assert_eq!(verify(&secret, "not a code", 0), None);
+    }
+}
@@ -1,5 +1,6 @@
-use actix_web::web;
+use actix_web::{web, HttpRequest};
 use lazy_static::lazy_static;
+use std::net::IpAddr;
 
 use crate::errors::ServiceError;
 
@@ -30,8 +31,47 @@ lazy_static! {
         std::env::var("GITHUB_REPO").expect("GITHUB_REPO must be set");
 }
 
+lazy_static! {
+    /// Reverse proxies whose `X-Forwarded-For` is believed, from the
+    /// comma separated `TRUSTED_PROXIES`
+    pub static ref TRUSTED_PROXIES: Vec<IpAddr> = std::env::var("TRUSTED_PROXIES")
+        .unwrap_or_default()
+        .split(',')
+        .filter_map(|x| x.trim().parse().ok())
+        .collect();
+}
+
 // return `ServiceError::BadRequest` if parse json error
 lazy_static! {
     pub static ref JSON_PARSE_CONFIG: web::JsonConfig = web::JsonConfig::default()
         .error_handler(|err, _req| { ServiceError::BadRequest(err.to_string()).into() });
 }
+
+/// Client ip of the tcp peer. `X-Forwarded-For` is only read when the peer
+/// is one of the `TRUSTED_PROXIES`, the client is then the last address
+/// not added by a trusted proxy, as clients may put anything in front.
+pub fn client_ip(req: &HttpRequest) -> String {
+    let peer = match req.peer_addr() {
+        Some(addr) => addr.ip(),
+        None => return "unknown".to_owned(),
+    };
+    if !TRUSTED_PROXIES.contains(&peer) {
+        return peer.to_string();
+    }
+
+    let forwarded = req
+        .headers()
+        .get_all("x-forwarded-for")
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .map(|x| x.trim().parse::<IpAddr>())
+        .collect::<Vec<_>>();
+    for addr in forwarded.into_iter().rev() {
+        match addr {
+            Ok(addr) if TRUSTED_PROXIES.contains(&addr) => continue,
+            Ok(addr) => return addr.to_string(),
+            Err(_) => break,
+        }
+    }
+    peer.to_string()
+}